@@ -1,11 +1,49 @@
-from .aras_types import ASGIApplication, LogLevel
+from .aras_types import AccessLogFormat, ASGIApplication, ClientCertMode, HttpProtocol, LogLevel, ProxyProtocolMode
 
 def serve(
     application: ASGIApplication,
-    addr: list[int] = [127, 0, 0, 1],
+    host: str | list[str] | None = None,
     port: int = 8080,
     keep_alive: bool = True,
     log_level: LogLevel = "INFO",
+    access_log: bool = True,
+    access_log_format: AccessLogFormat = "common",
+    access_log_file: str | None = None,
+    metrics_path: str | None = None,
+    metrics_host: str = "127.0.0.1",
+    metrics_port: int | None = None,
+    compression: bool = False,
+    compression_min_size: int = 500,
+    compression_encodings: str | None = None,
+    compression_exclude_types: str | None = None,
+    decompress_request_body: bool = False,
+    static_mounts: dict[str, str] | None = None,
+    static_index: str | None = "index.html",
+    static_listing: bool = False,
     max_concurrency: int | None = None,
+    max_queue: int = 0,
+    max_websockets: int | None = None,
+    rate_limit: float | None = None,
+    rate_limit_burst: int = 10,
+    rate_limit_allow_ips: str | None = None,
+    forwarded_allow_ips: str | None = None,
+    proxy_protocol: ProxyProtocolMode = "none",
     max_size_kb: int = 1_000_000,
+    max_size_kb_by_path: dict[str, int] | None = None,
+    http_protocol: HttpProtocol = "auto",
+    ssl_certfile: str | None = None,
+    ssl_keyfile: str | None = None,
+    ssl_ca_certs: str | None = None,
+    ssl_cert_reqs: ClientCertMode = "none",
+    uds: str | None = None,
+    uds_mode: int | None = None,
+    fd: int | None = None,
+    timeout_graceful_shutdown: float = 30.0,
+    timeout_header_read: float | None = 60.0,
+    timeout_keep_alive: float | None = 5.0,
+    timeout_body_read: float | None = 60.0,
+    timeout_request: float | None = None,
+    timeout_response_body: float | None = None,
+    timeout_write: float | None = 60.0,
+    timeout_queue: float = 5.0,
 ) -> None: ...
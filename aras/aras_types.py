@@ -5,6 +5,10 @@ Receive: TypeAlias = Callable[[], Awaitable[MutableMapping[str, Any]]]
 Scope: TypeAlias = MutableMapping[str, Any]
 
 LogLevel = Literal["DEBUG", "INFO", "WARN", "TRACE", "OFF", "ERROR"]
+HttpProtocol = Literal["h1", "h2", "auto"]
+ClientCertMode = Literal["none", "optional", "required"]
+ProxyProtocolMode = Literal["none", "optional", "required"]
+AccessLogFormat = Literal["common", "combined", "json"]
 
 class ASGIApplication(Protocol):
     async def __call__(self, scope: Scope, receive: Receive, send: Send) -> None: ...
@@ -4,7 +4,27 @@ import sys
 
 import click
 import aras
-from aras import LogLevel
+from aras import AccessLogFormat, ClientCertMode, HttpProtocol, LogLevel, ProxyProtocolMode
+
+
+def parse_path_limits(values: tuple[str, ...]) -> dict[str, int]:
+    limits = {}
+    for value in values:
+        prefix, _, limit = value.rpartition("=")
+        if not prefix or not limit.isdigit():
+            raise click.BadParameter(f"Expected PREFIX=KB, got '{value}'", param_hint="--max-size-kb-path")
+        limits[prefix] = int(limit)
+    return limits
+
+
+def parse_mounts(values: tuple[str, ...]) -> dict[str, str]:
+    mounts = {}
+    for value in values:
+        prefix, _, directory = value.partition("=")
+        if not prefix.startswith("/") or not directory:
+            raise click.BadParameter(f"Expected PREFIX=DIRECTORY, got '{value}'", param_hint="--mount")
+        mounts[prefix] = directory
+    return mounts
 
 
 @click.group()
@@ -17,9 +37,8 @@ def cli() -> None:
 @click.option(
     "--host",
     type=str,
-    default="127.0.0.1",
-    help="Bind socket to this host.",
-    show_default=True,
+    multiple=True,
+    help="Bind socket to this host, can be given multiple times. [default: 127.0.0.1]",
 )
 @click.option(
     "--port",
@@ -35,6 +54,90 @@ def cli() -> None:
     help="Set the server log level",
     show_default=True,
 )
+@click.option(
+    "--no-access-log",
+    is_flag=True,
+    help="Disable the access log",
+)
+@click.option(
+    "--access-log-format",
+    type=click.Choice(["common", "combined", "json"]),
+    default="common",
+    help="Line format of the access log, 'combined' adds the duration and request id",
+    show_default=True,
+)
+@click.option(
+    "--access-log-file",
+    type=str,
+    default=None,
+    help="Write the access log to this file, or '-' for stdout, instead of the server log",
+)
+@click.option(
+    "--metrics-path",
+    type=str,
+    default=None,
+    help="Serve Prometheus metrics on this path, on the metrics port if given or else the application listeners",
+)
+@click.option(
+    "--metrics-host",
+    type=str,
+    default="127.0.0.1",
+    help="Address of the separate metrics listener",
+    show_default=True,
+)
+@click.option(
+    "--metrics-port",
+    type=int,
+    default=None,
+    help="Serve Prometheus metrics on a separate listener on this port, at '/metrics' by default",
+)
+@click.option(
+    "--compression",
+    is_flag=True,
+    help="Compress responses for clients that accept gzip, br or zstd",
+)
+@click.option(
+    "--compression-min-size",
+    type=int,
+    default=500,
+    help="Responses with a smaller Content-Length in bytes are not compressed",
+    show_default=True,
+)
+@click.option(
+    "--compression-encodings",
+    type=str,
+    default=None,
+    help="Comma separated encodings to offer in order of preference, like 'br,gzip,deflate'. Defaults to 'br,zstd,gzip'",
+)
+@click.option(
+    "--compression-exclude-types",
+    type=str,
+    default=None,
+    help="Comma separated content types or prefixes like 'video/' that are never compressed, replaces the defaults",
+)
+@click.option(
+    "--decompress-request-body",
+    is_flag=True,
+    help="Decode gzip, deflate, br and zstd request bodies, the size limit applies to the decoded body",
+)
+@click.option(
+    "--mount",
+    type=str,
+    multiple=True,
+    help="Serve the files of a directory on a path prefix, like '/static=./app/static'. Can be given multiple times.",
+)
+@click.option(
+    "--static-index",
+    type=str,
+    default="index.html",
+    help="File served for requests to a mounted directory, an empty value disables it",
+    show_default=True,
+)
+@click.option(
+    "--static-listing",
+    is_flag=True,
+    help="List the files of mounted directories without an index file",
+)
 @click.option(
     "--no-keep-alive",
     is_flag=True,
@@ -47,6 +150,56 @@ def cli() -> None:
     help="Set the max concurrent requests",
     show_default=True,
 )
+@click.option(
+    "--max-queue",
+    type=int,
+    default=0,
+    help="Requests that may wait when the max concurrent requests is reached, 0 refuses them right away",
+    show_default=True,
+)
+@click.option(
+    "--max-websockets",
+    type=int,
+    default=None,
+    help="Set the max open websocket connections",
+    show_default=True,
+)
+@click.option(
+    "--rate-limit",
+    type=float,
+    default=0.0,
+    help="Requests per second a single client IP, or IPv6 /64, may send, 0 disables rate limiting",
+    show_default=True,
+)
+@click.option(
+    "--rate-limit-burst",
+    type=int,
+    default=10,
+    help="Requests a single client IP may send at once before being rate limited",
+    show_default=True,
+)
+@click.option(
+    "--rate-limit-allow-ips",
+    type=str,
+    default=None,
+    help="Comma separated IPs and networks that are never rate limited, like '10.0.0.0/8'",
+)
+@click.option(
+    "--forwarded-allow-ips",
+    type=str,
+    default=None,
+    help=(
+        "Comma separated IPs and networks of proxies trusted to set the client address, scheme and host "
+        "with the Forwarded or X-Forwarded-* headers, '*' trusts all"
+    ),
+)
+@click.option(
+    "--proxy-protocol",
+    type=click.Choice(["none", "optional", "required"]),
+    default="none",
+    help="Whether connections start with a PROXY protocol (v1 or v2) header carrying the client address",
+    show_default=True,
+)
 @click.option(
     "--max-size-kb",
     type=int,
@@ -54,14 +207,164 @@ def cli() -> None:
     help="Set the max size of a request body",
     show_default=True,
 )
+@click.option(
+    "--max-size-kb-path",
+    type=str,
+    multiple=True,
+    help="Max size of a request body for a path prefix, like '/upload=5000000'. Can be given multiple times.",
+)
+@click.option(
+    "--http",
+    type=click.Choice(["h1", "h2", "auto"]),
+    default="auto",
+    help="HTTP version(s) to serve, 'auto' detects HTTP/2 per connection",
+    show_default=True,
+)
+@click.option(
+    "--ssl-certfile",
+    type=str,
+    default=None,
+    help="SSL certificate chain file (PEM)",
+)
+@click.option(
+    "--ssl-keyfile",
+    type=str,
+    default=None,
+    help="SSL private key file (PEM)",
+)
+@click.option(
+    "--ssl-ca-certs",
+    type=str,
+    default=None,
+    help="CA certificates file (PEM) used to verify client certificates",
+)
+@click.option(
+    "--ssl-cert-reqs",
+    type=click.Choice(["none", "optional", "required"]),
+    default="none",
+    help="Whether a client certificate is required",
+    show_default=True,
+)
+@click.option(
+    "--uds",
+    type=str,
+    default=None,
+    help="Bind to a unix domain socket instead of host and port.",
+)
+@click.option(
+    "--uds-mode",
+    type=str,
+    default=None,
+    help="File permissions of the unix domain socket, in octal (e.g. 660).",
+)
+@click.option(
+    "--fd",
+    type=int,
+    default=None,
+    help="Bind to an already opened socket from this file descriptor.",
+)
+@click.option(
+    "--timeout-graceful-shutdown",
+    type=float,
+    default=30.0,
+    help="Seconds to wait for in-flight requests and websockets when shutting down",
+    show_default=True,
+)
+@click.option(
+    "--timeout-header-read",
+    type=float,
+    default=60.0,
+    help="Seconds a client gets to send the request headers, 0 disables the timeout",
+    show_default=True,
+)
+@click.option(
+    "--timeout-keep-alive",
+    type=float,
+    default=5.0,
+    help="Seconds to keep an idle connection open, 0 disables the timeout",
+    show_default=True,
+)
+@click.option(
+    "--timeout-body-read",
+    type=float,
+    default=60.0,
+    help="Seconds to wait for the next part of the request body, 0 disables the timeout",
+    show_default=True,
+)
+@click.option(
+    "--timeout-request",
+    type=float,
+    default=0.0,
+    help="Seconds the application gets to start a response before a 504 is sent, 0 disables the timeout",
+    show_default=True,
+)
+@click.option(
+    "--timeout-response-body",
+    type=float,
+    default=0.0,
+    help="Seconds to wait for the next part of the response body before aborting, 0 disables the timeout",
+    show_default=True,
+)
+@click.option(
+    "--timeout-write",
+    type=float,
+    default=60.0,
+    help="Seconds a write to the client may be blocked, 0 disables the timeout",
+    show_default=True,
+)
+@click.option(
+    "--timeout-queue",
+    type=float,
+    default=5.0,
+    help="Seconds a request may wait for the max concurrent requests before a 503 is sent",
+    show_default=True,
+)
 def serve(
     application: str,
-    host: str,
+    host: tuple[str, ...],
     port: int,
     log_level: LogLevel,
+    no_access_log: bool,
+    access_log_format: AccessLogFormat,
+    access_log_file: str | None,
+    metrics_path: str | None,
+    metrics_host: str,
+    metrics_port: int | None,
+    compression: bool,
+    compression_min_size: int,
+    compression_encodings: str | None,
+    compression_exclude_types: str | None,
+    decompress_request_body: bool,
+    mount: tuple[str, ...],
+    static_index: str,
+    static_listing: bool,
     no_keep_alive: bool,
     max_concurrency: int | None,
+    max_queue: int,
+    max_websockets: int | None,
+    rate_limit: float,
+    rate_limit_burst: int,
+    rate_limit_allow_ips: str | None,
+    forwarded_allow_ips: str | None,
+    proxy_protocol: ProxyProtocolMode,
     max_size_kb: int,
+    max_size_kb_path: tuple[str, ...],
+    http: HttpProtocol,
+    ssl_certfile: str | None,
+    ssl_keyfile: str | None,
+    ssl_ca_certs: str | None,
+    ssl_cert_reqs: ClientCertMode,
+    uds: str | None,
+    uds_mode: str | None,
+    fd: int | None,
+    timeout_graceful_shutdown: float,
+    timeout_header_read: float,
+    timeout_keep_alive: float,
+    timeout_body_read: float,
+    timeout_request: float,
+    timeout_response_body: float,
+    timeout_write: float,
+    timeout_queue: float,
 ) -> None:
     sys.path.insert(0, os.getcwd())
     module_str, application_str = application.split(":")
@@ -75,10 +378,48 @@ def serve(
         ) from exc
     aras.serve(
         loaded_app,
-        addr=[int(i) for i in host.split(".")],
+        host=list(host) if host else None,
         port=port,
         log_level=log_level,
+        access_log=not no_access_log,
+        access_log_format=access_log_format,
+        access_log_file=access_log_file,
+        metrics_path=metrics_path,
+        metrics_host=metrics_host,
+        metrics_port=metrics_port,
+        compression=compression,
+        compression_min_size=compression_min_size,
+        compression_encodings=compression_encodings,
+        compression_exclude_types=compression_exclude_types,
+        decompress_request_body=decompress_request_body,
+        static_mounts=parse_mounts(mount),
+        static_index=static_index or None,
+        static_listing=static_listing,
         keep_alive=not no_keep_alive,
         max_concurrency=max_concurrency,
+        max_queue=max_queue,
+        max_websockets=max_websockets,
+        rate_limit=rate_limit or None,
+        rate_limit_burst=rate_limit_burst,
+        rate_limit_allow_ips=rate_limit_allow_ips,
+        forwarded_allow_ips=forwarded_allow_ips,
+        proxy_protocol=proxy_protocol,
         max_size_kb=max_size_kb,
+        max_size_kb_by_path=parse_path_limits(max_size_kb_path),
+        http_protocol=http,
+        ssl_certfile=ssl_certfile,
+        ssl_keyfile=ssl_keyfile,
+        ssl_ca_certs=ssl_ca_certs,
+        ssl_cert_reqs=ssl_cert_reqs,
+        uds=uds,
+        uds_mode=int(uds_mode, 8) if uds_mode is not None else None,
+        fd=fd,
+        timeout_graceful_shutdown=timeout_graceful_shutdown,
+        timeout_header_read=timeout_header_read or None,
+        timeout_keep_alive=timeout_keep_alive or None,
+        timeout_body_read=timeout_body_read or None,
+        timeout_request=timeout_request or None,
+        timeout_response_body=timeout_response_body or None,
+        timeout_write=timeout_write or None,
+        timeout_queue=timeout_queue,
     )
@@ -0,0 +1,74 @@
+// Throughput of large request and response bodies through `serve_http`.
+// Run with `cargo bench -p aras_core`.
+use bytes::Bytes;
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use futures::stream;
+use http_body_util::{BodyExt, StreamBody};
+use hyper::body::Frame;
+use hyper::Request;
+
+use aras_core::*;
+
+const CHUNK_SIZE: usize = 64 * 1024;
+const BODY_SIZE: usize = 64 * 1024 * 1024;
+
+#[derive(Clone, Debug)]
+struct BenchState;
+impl State for BenchState {}
+
+// Reads the full request body and answers with `response_size` bytes
+#[derive(Clone)]
+struct BodyApp {
+    response_size: usize,
+}
+
+impl ASGICallable<BenchState> for BodyApp {
+    async fn call(&self, _scope: Scope<BenchState>, receive: ReceiveFn, send: SendFn) -> Result<()> {
+        loop {
+            if let ASGIReceiveEvent::HTTPRequest(msg) = receive().await? {
+                if !msg.more_body {
+                    break;
+                };
+            };
+        }
+        send(ASGISendEvent::new_http_response_start(200, Headers::new())).await?;
+        let chunk = Bytes::from(vec![b'x'; CHUNK_SIZE]);
+        let mut remaining = self.response_size;
+        loop {
+            let size = remaining.min(CHUNK_SIZE);
+            remaining -= size;
+            send(ASGISendEvent::new_http_response_body(chunk.slice(..size), remaining > 0)).await?;
+            if remaining == 0 {
+                return Ok(());
+            };
+        }
+    }
+}
+
+async fn round_trip(request_size: usize, response_size: usize) {
+    let chunk = Bytes::from(vec![b'x'; CHUNK_SIZE]);
+    let frames = (0..request_size / CHUNK_SIZE).map(move |_| Ok::<_, std::convert::Infallible>(Frame::data(chunk.clone())));
+    let request = Request::builder().body(StreamBody::new(stream::iter(frames))).unwrap();
+    let scope = Scope::HTTP(HTTPScope::from_hyper_request(&request, BenchState));
+    let app = ApplicationFactory::new(BodyApp { response_size }).build();
+
+    let mut body = serve_http(app, request, scope, Timeouts::default(), None, None).await.unwrap().into_body();
+    let mut received = 0;
+    while let Some(frame) = body.frame().await {
+        received += frame.unwrap().data_ref().map_or(0, |data| data.len());
+    }
+    assert!(received == response_size);
+}
+
+fn body_throughput(c: &mut Criterion) {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let mut group = c.benchmark_group("body_throughput");
+    group.throughput(Throughput::Bytes(BODY_SIZE as u64));
+    group.sample_size(20);
+    group.bench_function("upload", |b| b.to_async(&runtime).iter(|| round_trip(BODY_SIZE, 0)));
+    group.bench_function("download", |b| b.to_async(&runtime).iter(|| round_trip(0, BODY_SIZE)));
+    group.finish();
+}
+
+criterion_group!(benches, body_throughput);
+criterion_main!(benches);
@@ -51,6 +51,12 @@ impl<S: State, T: ASGICallable<S>> Application<S, T> {
         Ok(())
     }
 
+    // Send a message to the application without waiting for room in the queue.
+    // Returns `false` if the message was dropped.
+    pub fn try_send_to(&self, message: ASGIReceiveEvent) -> bool {
+        self.send_queue.try_send(message).is_ok()
+    }
+
     // Receive a message from the application
     pub async fn receive_from(&mut self) -> Result<Option<ASGISendEvent>> {
         Ok(self.receive_queue.lock().await.recv().await)
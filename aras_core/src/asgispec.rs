@@ -1,7 +1,10 @@
 use std::fmt::Debug;
 use std::future::Future;
+use std::path::PathBuf;
 use std::sync::Arc;
 
+use bytes::Bytes;
+
 use crate::error::Result;
 use crate::http::*;
 use crate::lifespan::*;
@@ -10,6 +13,9 @@ use crate::websocket::*;
 pub const ASGI_VERSION: &str = "3.0";
 pub const ASGI_SPEC_VERSION: &str = "2.4";
 
+// Header name and value pairs as passed to and from the application
+pub type Headers = Vec<(Bytes, Bytes)>;
+
 pub type SendFn = Arc<dyn Fn(ASGISendEvent) -> Box<dyn Future<Output = Result<()>> + Unpin + Sync + Send> + Send + Sync>;
 
 pub type ReceiveFn = Arc<dyn Fn() -> Box<dyn Future<Output = Result<ASGIReceiveEvent>> + Unpin + Sync + Send> + Send + Sync>;
@@ -52,6 +58,54 @@ impl ASGIScope {
     }
 }
 
+impl Default for ASGIScope {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Extensions supported by the server, exposed through the `extensions` scope key
+#[derive(Debug, Clone, Default)]
+pub struct ScopeExtensions {
+    pub tls: Option<TLSExtension>,
+    // Assigned by the server or passed in the `X-Request-ID` header
+    pub request_id: Option<String>,
+    // Whether `http.response.pathsend` and `http.response.trailers` are accepted, only for HTTP requests
+    pub pathsend: bool,
+    pub trailers: bool,
+}
+
+// Details of the TLS connection, as described by the ASGI TLS extension
+#[derive(Debug, Clone, Default)]
+pub struct TLSExtension {
+    pub server_cert: Option<String>,
+    pub client_cert_chain: Vec<String>,
+    pub client_cert_name: Option<String>,
+    pub client_cert_error: Option<String>,
+    pub tls_version: Option<u16>,
+    pub cipher_suite: Option<u16>,
+}
+
+// ASGI expects the bare version number ("1.1", "2") instead of "HTTP/1.1"
+pub fn http_version_to_scope(version: http::Version) -> String {
+    let version = match version {
+        http::Version::HTTP_09 => "0.9",
+        http::Version::HTTP_10 => "1.0",
+        http::Version::HTTP_11 => "1.1",
+        http::Version::HTTP_2 => "2",
+        http::Version::HTTP_3 => "3",
+        _ => "1.1",
+    };
+    String::from(version)
+}
+
+pub(crate) fn scope_headers(headers: &http::HeaderMap) -> Headers {
+    headers
+        .iter()
+        .map(|(name, value)| (Bytes::copy_from_slice(name.as_str().as_bytes()), Bytes::copy_from_slice(value.as_bytes())))
+        .collect()
+}
+
 #[derive(Debug)]
 pub enum ASGISendEvent {
     StartupComplete(LifespanStartupComplete),
@@ -60,6 +114,8 @@ pub enum ASGISendEvent {
     ShutdownFailed(LifespanShutdownFailed),
     HTTPResponseStart(HTTPResponseStartEvent),
     HTTPResponseBody(HTTPResonseBodyEvent),
+    HTTPResponsePathsend(HTTPResponsePathsendEvent),
+    HTTPResponseTrailers(HTTPResponseTrailersEvent),
     WebsocketAccept(WebsocketAcceptEvent),
     WebsocketClose(WebsocketCloseEvent),
     WebsocketSend(WebsocketSendEvent),
@@ -95,23 +151,52 @@ impl ASGISendEvent {
         Self::ShutdownFailed(LifespanShutdownFailed::new(message))
     }
 
-    pub fn new_http_response_start(status: u16, headers: Vec<(Vec<u8>, Vec<u8>)>) -> Self {
-        Self::HTTPResponseStart(HTTPResponseStartEvent::new(status, headers))
+    pub fn new_http_response_start<K, V>(status: u16, headers: impl IntoIterator<Item = (K, V)>) -> Self
+    where
+        K: Into<Bytes>,
+        V: Into<Bytes>,
+    {
+        Self::HTTPResponseStart(HTTPResponseStartEvent::new(status, into_headers(headers), false))
+    }
+
+    // Announces `http.response.trailers` messages after the last part of the body
+    pub fn new_http_response_start_with_trailers<K, V>(status: u16, headers: impl IntoIterator<Item = (K, V)>) -> Self
+    where
+        K: Into<Bytes>,
+        V: Into<Bytes>,
+    {
+        Self::HTTPResponseStart(HTTPResponseStartEvent::new(status, into_headers(headers), true))
+    }
+
+    pub fn new_http_response_body(data: impl Into<Bytes>, more_body: bool) -> Self {
+        Self::HTTPResponseBody(HTTPResonseBodyEvent::new(data.into(), more_body))
+    }
+
+    pub fn new_http_response_pathsend(path: impl Into<PathBuf>) -> Self {
+        Self::HTTPResponsePathsend(HTTPResponsePathsendEvent::new(path.into()))
     }
 
-    pub fn new_http_response_body(data: Vec<u8>, more_body: bool) -> Self {
-        Self::HTTPResponseBody(HTTPResonseBodyEvent::new(data, more_body))
+    pub fn new_http_response_trailers<K, V>(headers: impl IntoIterator<Item = (K, V)>, more_trailers: bool) -> Self
+    where
+        K: Into<Bytes>,
+        V: Into<Bytes>,
+    {
+        Self::HTTPResponseTrailers(HTTPResponseTrailersEvent::new(into_headers(headers), more_trailers))
     }
 
-    pub fn new_websocket_accept(subprotocol: Option<String>, headers: Vec<(Vec<u8>, Vec<u8>)>) -> Self {
-        Self::WebsocketAccept(WebsocketAcceptEvent::new(subprotocol, headers))
+    pub fn new_websocket_accept<K, V>(subprotocol: Option<String>, headers: impl IntoIterator<Item = (K, V)>) -> Self
+    where
+        K: Into<Bytes>,
+        V: Into<Bytes>,
+    {
+        Self::WebsocketAccept(WebsocketAcceptEvent::new(subprotocol, into_headers(headers)))
     }
 
     pub fn new_websocket_close(code: Option<usize>, reason: String) -> Self {
         Self::WebsocketClose(WebsocketCloseEvent::new(code, reason))
     }
 
-    pub fn new_websocket_send(bytes: Option<Vec<u8>>, text: Option<String>) -> Self {
+    pub fn new_websocket_send(bytes: Option<Bytes>, text: Option<String>) -> Self {
         Self::WebsocketSend(WebsocketSendEvent::new(bytes, text))
     }
 
@@ -133,8 +218,8 @@ impl ASGIReceiveEvent {
         Self::Shutdown(LifespanShutdown::new())
     }
 
-    pub fn new_http_request(data: Vec<u8>, more_body: bool) -> Self {
-        Self::HTTPRequest(HTTPRequestEvent::new(data, more_body))
+    pub fn new_http_request(data: impl Into<Bytes>, more_body: bool) -> Self {
+        Self::HTTPRequest(HTTPRequestEvent::new(data.into(), more_body))
     }
 
     pub fn new_http_disconnect() -> Self {
@@ -145,7 +230,7 @@ impl ASGIReceiveEvent {
         Self::WebsocketConnect(WebsocketConnectEvent::new())
     }
 
-    pub fn new_websocket_receive(bytes: Option<Vec<u8>>, text: Option<String>) -> Self {
+    pub fn new_websocket_receive(bytes: Option<Bytes>, text: Option<String>) -> Self {
         Self::WebsocketReceive(WebsocketReceiveEvent::new(bytes, text))
     }
 
@@ -154,6 +239,10 @@ impl ASGIReceiveEvent {
     }
 }
 
+fn into_headers<K: Into<Bytes>, V: Into<Bytes>>(headers: impl IntoIterator<Item = (K, V)>) -> Headers {
+    headers.into_iter().map(|(name, value)| (name.into(), value.into())).collect()
+}
+
 impl std::fmt::Display for ASGISendEvent {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -163,6 +252,8 @@ impl std::fmt::Display for ASGISendEvent {
             Self::ShutdownFailed(s) => write!(f, "{:?}", s),
             Self::HTTPResponseStart(s) => write!(f, "{:?}", s),
             Self::HTTPResponseBody(s) => write!(f, "{}", s),
+            Self::HTTPResponsePathsend(s) => write!(f, "{:?}", s),
+            Self::HTTPResponseTrailers(s) => write!(f, "{:?}", s),
             Self::WebsocketAccept(s) => write!(f, "{:?}", s),
             Self::WebsocketClose(s) => write!(f, "{:?}", s),
             Self::WebsocketSend(s) => write!(f, "{:?}", s),
@@ -46,6 +46,23 @@ pub enum Error {
 
     #[error(transparent)]
     WebsocketError(#[from] fastwebsockets::WebSocketError),
+
+    #[error(transparent)]
+    TLS(#[from] rustls::Error),
+
+    #[error("{0} timed out")]
+    Timeout(String),
+
+    #[error("Request body exceeds the limit of {0} bytes")]
+    PayloadTooLarge(u64),
+
+    // The request body can't be decoded, answered with a 400
+    #[error("{0}")]
+    InvalidBody(String),
+
+    // The connection is closed without sending a response
+    #[error("Connection aborted. {0}")]
+    Aborted(String),
 }
 
 impl Error {
@@ -61,6 +78,14 @@ impl Error {
         Self::DisconnectedClient(String::from("Disconnected client"))
     }
 
+    pub fn timeout(src: impl std::fmt::Display) -> Self {
+        Self::Timeout(src.to_string())
+    }
+
+    pub fn aborted(reason: impl std::fmt::Display) -> Self {
+        Self::Aborted(reason.to_string())
+    }
+
     pub fn unexpected_shutdown(src: &str, reason: String) -> Self {
         Self::UnexpectedShutdown {
             src: src.to_string(),
@@ -0,0 +1,211 @@
+use std::io::{self, Write};
+
+use bytes::Bytes;
+use flate2::write::{GzDecoder, ZlibDecoder};
+use http::header::CONTENT_ENCODING;
+use http::HeaderMap;
+
+use crate::error::{Error, Result};
+use crate::server::ContentEncoding;
+
+// The encoding of a request body that can be decoded. Identity, unknown and
+// stacked encodings are passed on to the application as they are.
+pub fn request_body_encoding(headers: &HeaderMap) -> Option<ContentEncoding> {
+    let mut values = headers.get_all(CONTENT_ENCODING).iter();
+    let value = values.next()?.to_str().ok()?.trim().to_lowercase();
+    if values.next().is_some() || value.contains(',') {
+        return None;
+    };
+    match value.as_str() {
+        "x-gzip" => Some(ContentEncoding::Gzip),
+        value => value.parse().ok(),
+    }
+}
+
+// Decodes a request body while it is received. The decoded size counts towards the
+// body size limit, and is capped while decoding since a few bytes can expand to gigabytes.
+// The received size is limited as well, input such as empty deflate blocks or zstd
+// skippable frames decodes to nothing.
+pub struct BodyDecoder {
+    inner: Inner,
+    limit: Option<u64>,
+    received: u64,
+}
+
+enum Inner {
+    Gzip(GzDecoder<Output>),
+    Deflate(ZlibDecoder<Output>),
+    Brotli(Box<brotli::DecompressorWriter<Output>>),
+    // The writer rather than `zstd::stream::write::Decoder`, which can't tell an incomplete frame
+    Zstd(zstd::stream::zio::Writer<Output, zstd::stream::raw::Decoder<'static>>),
+}
+
+struct Output {
+    data: Vec<u8>,
+    remaining: Option<u64>,
+    exceeded: bool,
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if let Some(remaining) = &mut self.remaining {
+            if buf.len() as u64 > *remaining {
+                self.exceeded = true;
+                return Err(io::Error::other("Decoded body exceeds the limit"));
+            };
+            *remaining -= buf.len() as u64;
+        };
+        self.data.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl BodyDecoder {
+    pub fn new(encoding: ContentEncoding, limit: Option<u64>) -> Result<Self> {
+        let output = Output {
+            data: Vec::new(),
+            remaining: limit,
+            exceeded: false,
+        };
+        let inner = match encoding {
+            ContentEncoding::Gzip => Inner::Gzip(GzDecoder::new(output)),
+            ContentEncoding::Deflate => Inner::Deflate(ZlibDecoder::new(output)),
+            ContentEncoding::Brotli => Inner::Brotli(Box::new(brotli::DecompressorWriter::new(output, 4096))),
+            ContentEncoding::Zstd => {
+                Inner::Zstd(zstd::stream::zio::Writer::new(output, zstd::stream::raw::Decoder::new()?))
+            }
+        };
+        Ok(Self { inner, limit, received: 0 })
+    }
+
+    pub fn decode(&mut self, data: &[u8]) -> Result<Bytes> {
+        self.received += data.len() as u64;
+        if let Some(limit) = self.limit.filter(|limit| self.received > *limit) {
+            return Err(Error::PayloadTooLarge(limit));
+        };
+        let written = match &mut self.inner {
+            Inner::Gzip(decoder) => decoder.write_all(data).and_then(|_| decoder.flush()),
+            Inner::Deflate(decoder) => decoder.write_all(data).and_then(|_| decoder.flush()),
+            Inner::Brotli(decoder) => decoder.write_all(data).and_then(|_| decoder.flush()),
+            Inner::Zstd(decoder) => decoder.write_all(data).and_then(|_| decoder.flush()),
+        };
+        self.take_output(written)
+    }
+
+    // Whatever was left in the decoder, fails when the body ended early
+    pub fn finish(mut self) -> Result<Bytes> {
+        let finished = match &mut self.inner {
+            Inner::Gzip(decoder) => decoder.try_finish(),
+            Inner::Deflate(decoder) => decoder.try_finish(),
+            Inner::Brotli(decoder) => decoder.close(),
+            Inner::Zstd(decoder) => decoder.finish(),
+        };
+        self.take_output(finished)
+    }
+
+    fn take_output(&mut self, result: io::Result<()>) -> Result<Bytes> {
+        let output = match &mut self.inner {
+            Inner::Gzip(decoder) => decoder.get_mut(),
+            Inner::Deflate(decoder) => decoder.get_mut(),
+            Inner::Brotli(decoder) => decoder.get_mut(),
+            Inner::Zstd(decoder) => decoder.writer_mut(),
+        };
+        match (result, self.limit) {
+            (Ok(()), _) => Ok(Bytes::from(std::mem::take(&mut output.data))),
+            (Err(_), Some(limit)) if output.exceeded => Err(Error::PayloadTooLarge(limit)),
+            (Err(e), _) => Err(Error::InvalidBody(format!("Failed to decode the request body. {e}"))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use http::HeaderMap;
+
+    use super::{request_body_encoding, BodyDecoder};
+    use crate::error::Error;
+    use crate::server::ContentEncoding;
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn test_request_body_encoding() {
+        let encoding = |values: &[&str]| {
+            let mut headers = HeaderMap::new();
+            for value in values {
+                headers.append("content-encoding", value.parse().unwrap());
+            }
+            request_body_encoding(&headers)
+        };
+        assert!(encoding(&["gzip"]) == Some(ContentEncoding::Gzip));
+        assert!(encoding(&["X-GZIP"]) == Some(ContentEncoding::Gzip));
+        assert!(encoding(&["deflate"]) == Some(ContentEncoding::Deflate));
+        assert!(encoding(&["br"]) == Some(ContentEncoding::Brotli));
+        assert!(encoding(&[]).is_none());
+        assert!(encoding(&["identity"]).is_none());
+        assert!(encoding(&["gzip, br"]).is_none());
+        assert!(encoding(&["gzip", "br"]).is_none());
+    }
+
+    #[test]
+    fn test_decode_in_parts() {
+        let compressed = gzip(&b"{\"hello\": \"world\"}".repeat(100));
+        let mut decoder = BodyDecoder::new(ContentEncoding::Gzip, None).unwrap();
+        let mut decoded = Vec::new();
+        for part in compressed.chunks(7) {
+            decoded.extend_from_slice(&decoder.decode(part).unwrap());
+        }
+        decoded.extend_from_slice(&decoder.finish().unwrap());
+        assert!(decoded == b"{\"hello\": \"world\"}".repeat(100));
+    }
+
+    #[test]
+    fn test_decoded_size_is_limited() {
+        let bomb = gzip(&vec![0; 1_000_000]);
+        assert!(bomb.len() < 2000);
+        let mut decoder = BodyDecoder::new(ContentEncoding::Gzip, Some(10_000)).unwrap();
+        assert!(matches!(decoder.decode(&bomb), Err(Error::PayloadTooLarge(10_000))));
+    }
+
+    #[test]
+    fn test_invalid_and_truncated_bodies() {
+        let mut decoder = BodyDecoder::new(ContentEncoding::Brotli, None).unwrap();
+        assert!(matches!(decoder.decode(b"not brotli at all"), Err(Error::InvalidBody(_))));
+
+        let compressed = gzip(b"hello world");
+        let mut decoder = BodyDecoder::new(ContentEncoding::Gzip, None).unwrap();
+        _ = decoder.decode(&compressed[..compressed.len() - 4]).unwrap();
+        assert!(matches!(decoder.finish(), Err(Error::InvalidBody(_))));
+
+        let compressed = zstd::encode_all(&b"hello world".repeat(100)[..], 3).unwrap();
+        let mut decoder = BodyDecoder::new(ContentEncoding::Zstd, None).unwrap();
+        _ = decoder.decode(&compressed[..compressed.len() - 4]).unwrap();
+        assert!(matches!(decoder.finish(), Err(Error::InvalidBody(_))));
+
+        let mut decoder = BodyDecoder::new(ContentEncoding::Zstd, None).unwrap();
+        let decoded = decoder.decode(&compressed).unwrap();
+        assert!(decoded.len() + decoder.finish().unwrap().len() == 1100);
+    }
+
+    #[test]
+    fn test_received_size_is_limited() {
+        // Skippable zstd frames are read without producing any output
+        let mut skippable = 0x184D2A50u32.to_le_bytes().to_vec();
+        skippable.extend_from_slice(&1000u32.to_le_bytes());
+        skippable.extend_from_slice(&[0; 1000]);
+
+        let mut decoder = BodyDecoder::new(ContentEncoding::Zstd, Some(10_000)).unwrap();
+        let result = (0..20).try_for_each(|_| decoder.decode(&skippable).map(|decoded| assert!(decoded.is_empty())));
+        assert!(matches!(result, Err(Error::PayloadTooLarge(10_000))));
+    }
+}
@@ -1,12 +1,18 @@
+use std::path::PathBuf;
+
+use bytes::Bytes;
+
+use crate::asgispec::Headers;
+
 #[derive(Debug)]
 pub struct HTTPRequestEvent {
     pub type_: String,
-    pub body: Vec<u8>,
+    pub body: Bytes,
     pub more_body: bool,
 }
 
 impl HTTPRequestEvent {
-    pub fn new(body: Vec<u8>, more_body: bool) -> Self {
+    pub fn new(body: Bytes, more_body: bool) -> Self {
         Self {
             type_: "http.request".into(),
             body,
@@ -29,15 +35,18 @@ impl std::fmt::Display for &HTTPRequestEvent {
 pub struct HTTPResponseStartEvent {
     pub type_: String,
     pub status: u16,
-    pub headers: Vec<(Vec<u8>, Vec<u8>)>,
+    pub headers: Headers,
+    // The body is followed by `http.response.trailers` messages
+    pub trailers: bool,
 }
 
 impl HTTPResponseStartEvent {
-    pub fn new(status: u16, headers: Vec<(Vec<u8>, Vec<u8>)>) -> Self {
+    pub fn new(status: u16, headers: Headers, trailers: bool) -> Self {
         Self {
             type_: "http.response.start".into(),
             status,
             headers,
+            trailers,
         }
     }
 }
@@ -49,6 +58,7 @@ impl std::fmt::Display for &HTTPResponseStartEvent {
         for (name, value) in &self.headers {
             writeln!(f, "  {}: {}", String::from_utf8_lossy(name), String::from_utf8_lossy(value))?;
         }
+        writeln!(f, "trailers: {}", self.trailers)?;
         Ok(())
     }
 }
@@ -57,12 +67,12 @@ impl std::fmt::Display for &HTTPResponseStartEvent {
 #[derive(Debug)]
 pub struct HTTPResonseBodyEvent {
     pub type_: String,
-    pub body: Vec<u8>,
+    pub body: Bytes,
     pub more_body: bool,
 }
 
 impl HTTPResonseBodyEvent {
-    pub fn new(body: Vec<u8>, more_body: bool) -> Self {
+    pub fn new(body: Bytes, more_body: bool) -> Self {
         Self {
             type_: "http.response.body".into(),
             body,
@@ -81,6 +91,39 @@ impl std::fmt::Display for &HTTPResonseBodyEvent {
     }
 }
 
+// Sends the whole response body from a file, instead of `http.response.body` messages
+#[derive(Debug)]
+pub struct HTTPResponsePathsendEvent {
+    pub type_: String,
+    pub path: PathBuf,
+}
+
+impl HTTPResponsePathsendEvent {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            type_: "http.response.pathsend".into(),
+            path,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct HTTPResponseTrailersEvent {
+    pub type_: String,
+    pub headers: Headers,
+    pub more_trailers: bool,
+}
+
+impl HTTPResponseTrailersEvent {
+    pub fn new(headers: Headers, more_trailers: bool) -> Self {
+        Self {
+            type_: "http.response.trailers".into(),
+            headers,
+            more_trailers,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct HTTPDisconnectEvent {
     pub type_: String,
@@ -90,4 +133,10 @@ impl HTTPDisconnectEvent {
     pub fn new() -> Self {
         Self { type_: "http.disconnect".into() }
     }
-}
\ No newline at end of file
+}
+
+impl Default for HTTPDisconnectEvent {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -1,18 +1,39 @@
 use std::fmt::Debug;
+use std::future::Future;
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::task::{ready, Context, Poll};
+use std::time::Duration;
 
 use bytes::{Buf, Bytes};
 use futures::StreamExt;
 use http_body_util::combinators::BoxBody;
 use http_body_util::{BodyExt, StreamBody};
-use hyper::body::{Body, Frame};
-use hyper::Request;
+use hyper::body::{Body, Frame, SizeHint};
+use hyper::header::{HeaderName, HeaderValue, CONTENT_LENGTH, TRAILER};
+use hyper::{HeaderMap, Request};
+use log::warn;
 
+use super::decoder::BodyDecoder;
 use crate::application::Application;
 use crate::asgispec::{ASGICallable, ASGIReceiveEvent, ASGISendEvent, Scope, State};
 use crate::error::{Error, Result};
-use crate::types::Response;
+use crate::middleware_services::{file_body, RequestId};
+use crate::server::{ContentEncoding, Timeouts};
+use crate::types::{text_response, Response};
 
-pub async fn serve_http<B, S, T>(asgi_app: Application<S, T>, request: Request<B>, scope: Scope<S>) -> Result<Response>
+type AppFuture = Pin<Box<dyn Future<Output = Result<()>> + Send + Sync>>;
+
+pub async fn serve_http<B, S, T>(
+    asgi_app: Application<S, T>,
+    request: Request<B>,
+    scope: Scope<S>,
+    timeouts: Timeouts,
+    max_size: Option<u64>,
+    encoding: Option<ContentEncoding>,
+) -> Result<Response>
 where
     B: Body + Send + 'static,
     S: State + 'static,
@@ -20,34 +41,176 @@ where
     <B as hyper::body::Body>::Error: Debug,
 {
     let app_clone = asgi_app.clone();
-    match tokio::try_join!(app_clone.call(scope), transport(asgi_app, request)) {
-        Ok((_, response)) => Ok(response),
-        Err(e) => Err(e),
+    let disconnect_app = asgi_app.clone();
+    let path = request.uri().path().to_string();
+    let request_id = RequestId::of(&request);
+    let response_started = Arc::new(AtomicBool::new(false));
+    let mut running_app: AppFuture = Box::pin(async move { app_clone.call(scope).await });
+    let limits = BodyLimits { max_size, encoding };
+    let transport = transport(asgi_app, request, timeouts, limits, path.clone(), response_started.clone());
+    tokio::pin!(transport);
+
+    let response = tokio::select! {
+        biased;
+        // The application returned or failed before a response was started
+        out = &mut running_app => {
+            out?;
+            return transport.await;
+        }
+        out = &mut transport => Some(out),
+        _ = request_deadline(timeouts.request, &response_started) => {
+            warn!("[{request_id}] Application did not start a response for {path} in time");
+            disconnect_app.try_send_to(ASGIReceiveEvent::new_http_disconnect());
+            None
+        }
+    };
+
+    match response {
+        // The application keeps running while the response body is streamed, the
+        // response is finished once it returns and its errors are passed on to the body
+        Some(Ok(response)) => Ok(response.map(|body| AppBody::new(body, running_app).boxed())),
+        // Failed or timed out, the application is left to handle the disconnect
+        response => {
+            tokio::spawn(async move { _ = running_app.await });
+            response.unwrap_or_else(send_504)
+        }
+    }
+}
+
+// Drives the application together with the response body
+struct AppBody {
+    inner: BoxBody<Bytes, Error>,
+    app: Option<AppFuture>,
+    body_done: bool,
+}
+
+impl AppBody {
+    fn new(inner: BoxBody<Bytes, Error>, app: AppFuture) -> Self {
+        Self {
+            inner,
+            app: Some(app),
+            body_done: false,
+        }
+    }
+}
+
+impl Body for AppBody {
+    type Data = Bytes;
+    type Error = Error;
+
+    fn poll_frame(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<std::result::Result<Frame<Bytes>, Error>>> {
+        let this = self.get_mut();
+        if let Some(app) = this.app.as_mut() {
+            if let Poll::Ready(result) = app.as_mut().poll(cx) {
+                this.app = None;
+                if let Err(e) = result {
+                    this.body_done = true;
+                    return Poll::Ready(Some(Err(e)));
+                };
+            };
+        };
+        if !this.body_done {
+            match ready!(Pin::new(&mut this.inner).poll_frame(cx)) {
+                None => this.body_done = true,
+                frame => return Poll::Ready(frame),
+            };
+        };
+        match this.app {
+            Some(_) => Poll::Pending,
+            None => Poll::Ready(None),
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.app.is_none() && (self.body_done || self.inner.is_end_stream())
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+// The client went away or the body failed, the application still gets to handle the disconnect
+impl Drop for AppBody {
+    fn drop(&mut self) {
+        if let Some(app) = self.app.take() {
+            tokio::spawn(async move { _ = app.await });
+        };
     }
 }
 
-async fn transport<B, S, T>(mut asgi_app: Application<S, T>, request: Request<B>) -> Result<Response>
+// Resolves once the timeout elapsed without the application starting a response
+async fn request_deadline(timeout: Option<Duration>, response_started: &AtomicBool) {
+    match timeout {
+        Some(timeout) => tokio::time::sleep(timeout).await,
+        None => std::future::pending().await,
+    };
+    if response_started.load(Ordering::Acquire) {
+        std::future::pending::<()>().await;
+    };
+}
+
+fn send_504() -> Result<Response> {
+    text_response(hyper::Response::builder().status(504), "Gateway Timeout")
+}
+
+async fn transport<B, S, T>(
+    mut asgi_app: Application<S, T>,
+    request: Request<B>,
+    timeouts: Timeouts,
+    limits: BodyLimits,
+    path: String,
+    response_started: Arc<AtomicBool>,
+) -> Result<Response>
 where
     B: Body + Send + 'static,
     S: State + 'static,
     T: ASGICallable<S> + 'static,
     <B as hyper::body::Body>::Error: Debug,
 {
+    let body = BodyMessages {
+        timeout: timeouts.response_body,
+        trailers: false,
+        path: path.clone(),
+        request_id: RequestId::of(&request),
+    };
     let result = tokio::try_join!(
-        stream_request_body(asgi_app.clone(), request.into_body()),
-        build_response(asgi_app.clone()),
+        stream_request_body(asgi_app.clone(), request.into_body(), timeouts.body_read, limits),
+        build_response(asgi_app.clone(), body, response_started.clone()),
     );
 
-    asgi_app.send_to(ASGIReceiveEvent::new_http_disconnect()).await?;
-    asgi_app.disconnect_server();
-
+    // On success the body stream disconnects once the response is sent
     match result {
         Ok((_, response)) => Ok(response),
-        Err(e) => Err(e),
+        Err(e) => {
+            asgi_app.send_to(ASGIReceiveEvent::new_http_disconnect()).await?;
+            asgi_app.disconnect_server();
+            match e {
+                // Too late for a 413 once the application started its response
+                Error::PayloadTooLarge(limit) if response_started.load(Ordering::Acquire) => {
+                    Err(Error::aborted(format!("Request body for {path} exceeds the limit of {limit} bytes")))
+                }
+                Error::InvalidBody(reason) if response_started.load(Ordering::Acquire) => {
+                    Err(Error::aborted(format!("Invalid request body for {path}. {reason}")))
+                }
+                e => Err(e),
+            }
+        }
     }
 }
 
-async fn stream_request_body<B, S, T>(asgi_app: Application<S, T>, body: B) -> Result<()>
+// How much of the request body is accepted, and the encoding it is decoded from
+struct BodyLimits {
+    max_size: Option<u64>,
+    encoding: Option<ContentEncoding>,
+}
+
+async fn stream_request_body<B, S, T>(
+    asgi_app: Application<S, T>,
+    body: B,
+    read_timeout: Option<Duration>,
+    limits: BodyLimits,
+) -> Result<()>
 where
     B: Body + Send + 'static,
     S: State + 'static,
@@ -59,33 +222,58 @@ where
     let mut stream = body.into_data_stream().boxed();
     let mut part;
     let mut more_body = true;
+    let mut received: u64 = 0;
+    let max_size = limits.max_size;
+    let mut decoder = limits.encoding.map(|encoding| BodyDecoder::new(encoding, max_size)).transpose()?;
 
     loop {
-        if more_body == false {
+        if !more_body {
             break;
         }
 
-        part = stream.next().await;
+        part = match read_timeout {
+            Some(timeout) => tokio::time::timeout(timeout, stream.next())
+                .await
+                .map_err(|_| Error::timeout("Reading the request body"))?,
+            None => stream.next().await,
+        };
 
         let data = part.map_or_else(
             || {
                 more_body = false;
-                Ok(Vec::new())
+                Ok(Bytes::new())
             },
             |part_result| {
                 part_result
-                    .map(|mut data| data.copy_to_bytes(data.remaining()).to_vec())
+                    .map(|mut data| data.copy_to_bytes(data.remaining()))
                     .map_err(|e| Error::custom(format!("Failed to read body: {e:?}")))
             },
         )?;
 
+        // The application gets the decoded body, ending with what was left in the decoder
+        let data = match decoder.as_mut() {
+            Some(decoder) if more_body => decoder.decode(&data)?,
+            Some(_) => decoder.take().map_or(Ok(Bytes::new()), BodyDecoder::finish)?,
+            None => data,
+        };
+
+        // Counted on the bytes actually received, a declared length can't be trusted
+        received += data.len() as u64;
+        if let Some(limit) = max_size.filter(|limit| received > *limit) {
+            return Err(Error::PayloadTooLarge(limit));
+        };
+
         let msg = ASGIReceiveEvent::new_http_request(data, more_body);
         asgi_app.send_to(msg).await?;
     }
     Ok(())
 }
 
-async fn build_response<S, T>(mut asgi_app: Application<S, T>) -> Result<Response>
+async fn build_response<S, T>(
+    mut asgi_app: Application<S, T>,
+    mut body: BodyMessages,
+    response_started: Arc<AtomicBool>,
+) -> Result<Response>
 where
     S: State + 'static,
     T: ASGICallable<S> + 'static,
@@ -94,11 +282,33 @@ where
 
     let body = match asgi_app.receive_from().await? {
         Some(ASGISendEvent::HTTPResponseStart(msg)) => {
+            response_started.store(true, Ordering::Release);
             builder = builder.status(msg.status);
             for (bytes_key, bytes_value) in msg.headers.into_iter() {
-                builder = builder.header(bytes_key, bytes_value);
+                builder = builder.header(&bytes_key[..], HeaderValue::from_maybe_shared(bytes_value).map_err(http::Error::from)?);
+            }
+            // Trailers are only sent with a chunked body on HTTP/1.1, and only the fields
+            // named in the `Trailer` header, the names can't be known before they are sent
+            let trailers = msg.trailers;
+            if let Some(headers) = builder.headers_mut().filter(|_| trailers) {
+                headers.remove(CONTENT_LENGTH);
+                if !headers.contains_key(TRAILER) {
+                    let (request_id, path) = (&body.request_id, &body.path);
+                    warn!("[{request_id}] No Trailer header for the trailers of {path}, HTTP/1.1 drops them");
+                };
+            };
+            body.trailers = trailers;
+            // The headers depend on whether the body is sent from a file
+            match receive_body_message(&mut asgi_app, &body).await? {
+                Some(ASGISendEvent::HTTPResponsePathsend(msg)) => {
+                    let (file, len) = open_file(&msg.path).await?;
+                    if let Some(headers) = builder.headers_mut().filter(|_| !trailers) {
+                        headers.insert(CONTENT_LENGTH, HeaderValue::from(len));
+                    };
+                    build_file_body(asgi_app, body, file, len)
+                }
+                first => build_body_stream(asgi_app, body, first).await,
             }
-            build_body_stream(asgi_app).await
         }
         msg => return Err(Error::unexpected_asgi_message(Box::new(msg))),
     };
@@ -106,46 +316,178 @@ where
     Ok(builder.body(body)?)
 }
 
-async fn build_body_stream<S, T>(mut asgi_app: Application<S, T>) -> BoxBody<Bytes, Error>
+// Tells the application the request is finished once the response body is sent,
+// or when the body is dropped because the client went away or the stream failed
+struct DisconnectOnDrop<S: State, T: ASGICallable<S>>(Application<S, T>);
+
+impl<S: State, T: ASGICallable<S>> Drop for DisconnectOnDrop<S, T> {
+    fn drop(&mut self) {
+        self.0.try_send_to(ASGIReceiveEvent::new_http_disconnect());
+        self.0.disconnect_server();
+    }
+}
+
+// How the messages following `http.response.start` are received
+struct BodyMessages {
+    timeout: Option<Duration>,
+    trailers: bool,
+    path: String,
+    request_id: RequestId,
+}
+
+async fn receive_body_message<S, T>(
+    asgi_app: &mut Application<S, T>,
+    body: &BodyMessages,
+) -> Result<Option<ASGISendEvent>>
+where
+    S: State + 'static,
+    T: ASGICallable<S> + 'static,
+{
+    let (request_id, path) = (&body.request_id, &body.path);
+    match body.timeout {
+        Some(timeout) => match tokio::time::timeout(timeout, asgi_app.receive_from()).await {
+            Ok(message) => message,
+            Err(_) => {
+                // Abort the connection, the client can't tell the response is incomplete otherwise
+                warn!("[{request_id}] Application stalled while sending the response body for {path}, aborting");
+                Err(Error::timeout("Waiting for the response body"))
+            }
+        },
+        None => asgi_app.receive_from().await,
+    }
+}
+
+// Collects the trailers once the body is sent, they may be split over several messages
+async fn receive_trailers<S, T>(asgi_app: &mut Application<S, T>, body: &BodyMessages) -> Result<HeaderMap>
+where
+    S: State + 'static,
+    T: ASGICallable<S> + 'static,
+{
+    let mut trailers = HeaderMap::new();
+    loop {
+        match receive_body_message(asgi_app, body).await? {
+            Some(ASGISendEvent::HTTPResponseTrailers(msg)) => {
+                for (name, value) in msg.headers {
+                    let name = HeaderName::from_bytes(&name).map_err(http::Error::from)?;
+                    trailers.append(name, HeaderValue::from_maybe_shared(value).map_err(http::Error::from)?);
+                }
+                if !msg.more_trailers {
+                    return Ok(trailers);
+                };
+            }
+            msg => return Err(Error::unexpected_asgi_message(Box::new(msg))),
+        }
+    }
+}
+
+async fn build_body_stream<S, T>(
+    mut asgi_app: Application<S, T>,
+    body: BodyMessages,
+    first: Option<ASGISendEvent>,
+) -> BoxBody<Bytes, Error>
 where
     S: State + 'static,
     T: ASGICallable<S> + 'static,
 {
+    let disconnect = DisconnectOnDrop(asgi_app.clone());
     let stream = async_stream::stream! {
+        let _disconnect = disconnect;
+        let mut first = Some(first);
         let mut more_data = true;
         loop {
-            if more_data == false {
+            if !more_data {
                 break
             }
-            match asgi_app.receive_from().await? {
+            let message = match first.take() {
+                Some(message) => message,
+                None => match receive_body_message(&mut asgi_app, &body).await {
+                    Ok(message) => message,
+                    Err(e) => {
+                        yield Err(e);
+                        return
+                    }
+                },
+            };
+            match message {
                 Some(ASGISendEvent::HTTPResponseBody(msg)) => {
                     more_data = msg.more_body;
-                    yield Ok(msg.body)
+                    yield Ok(Frame::data(msg.body))
                 }
                 msg => yield Err(Error::unexpected_asgi_message(Box::new(msg))),
             }
         }
+        if body.trailers {
+            yield receive_trailers(&mut asgi_app, &body).await.map(Frame::trailers);
+        };
     };
 
-    let byte_frame_stream = stream.map(|item| match item {
-        Ok(data) => Ok(Frame::data(Bytes::from(data))),
-        Err(e) => Err(e),
-    });
+    BoxBody::new(StreamBody::new(stream))
+}
+
+// The spec requires an absolute path, the file is opened before the response starts
+// so a missing file still results in an error response
+async fn open_file(path: &Path) -> Result<(tokio::fs::File, u64)> {
+    if !path.is_absolute() {
+        return Err(Error::custom(format!("Path to send is not absolute: {}", path.display())));
+    };
+    let file = tokio::fs::File::open(path).await?;
+    let metadata = file.metadata().await?;
+    if !metadata.is_file() {
+        return Err(Error::custom(format!("Path to send is not a file: {}", path.display())));
+    };
+    Ok((file, metadata.len()))
+}
 
-    BoxBody::new(StreamBody::new(byte_frame_stream))
+fn build_file_body<S, T>(
+    mut asgi_app: Application<S, T>,
+    body: BodyMessages,
+    file: tokio::fs::File,
+    len: u64,
+) -> BoxBody<Bytes, Error>
+where
+    S: State + 'static,
+    T: ASGICallable<S> + 'static,
+{
+    let disconnect = DisconnectOnDrop(asgi_app.clone());
+    let stream = async_stream::stream! {
+        let _disconnect = disconnect;
+        let mut file_body = file_body(file, len);
+        while let Some(frame) = file_body.frame().await {
+            let failed = frame.is_err();
+            yield frame;
+            if failed {
+                return;
+            };
+        }
+        if body.trailers {
+            yield receive_trailers(&mut asgi_app, &body).await.map(Frame::trailers);
+        };
+    };
+    BoxBody::new(StreamBody::new(stream))
 }
 
 #[cfg(test)]
 mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use bytes::Bytes;
+    use futures::StreamExt;
     use http::StatusCode;
-    use http_body_util::BodyExt;
+    use http_body_util::{BodyExt, Empty, StreamBody};
+    use hyper::body::{Frame, Incoming};
+    use hyper::server::conn::http1;
+    use hyper::service::service_fn;
     use hyper::Request;
+    use hyper_util::rt::TokioIo;
 
     use super::serve_http;
     use crate::application::ApplicationFactory;
-    use crate::asgispec::{ASGICallable, ASGIReceiveEvent, ASGISendEvent, ReceiveFn, Scope, SendFn, State};
+    use crate::asgispec::{ASGICallable, ASGIReceiveEvent, ASGISendEvent, Headers, ReceiveFn, Scope, SendFn, State};
     use crate::error::{Error, Result};
     use crate::http::HTTPScope;
+    use crate::server::{ContentEncoding, Timeouts};
     use crate::types::Response;
 
     #[derive(Clone, Debug)]
@@ -179,7 +521,7 @@ mod tests {
             loop {
                 match (receive)().await {
                     Ok(ASGIReceiveEvent::HTTPRequest(msg)) => {
-                        body.extend(msg.body.into_iter());
+                        body.extend(msg.body);
                         if msg.more_body {
                             continue;
                         } else {
@@ -254,6 +596,104 @@ mod tests {
         }
     }
 
+    #[derive(Clone, Debug)]
+    struct SlowApp;
+
+    impl ASGICallable<MockState> for SlowApp {
+        async fn call(&self, _scope: Scope<MockState>, receive: ReceiveFn, send: SendFn) -> super::Result<()> {
+            _ = receive().await?;
+            tokio::time::sleep(Duration::from_secs(10)).await;
+            send(ASGISendEvent::new_http_response_start(200, Headers::new())).await?;
+            send(ASGISendEvent::new_http_response_body(Vec::new(), false)).await?;
+            Ok(())
+        }
+    }
+
+    // Sends part of the body and then stalls until the server disconnects
+    #[derive(Clone, Debug)]
+    struct StallingApp {
+        disconnected: Arc<AtomicBool>,
+    }
+
+    impl ASGICallable<MockState> for StallingApp {
+        async fn call(&self, _scope: Scope<MockState>, receive: ReceiveFn, send: SendFn) -> super::Result<()> {
+            while let ASGIReceiveEvent::HTTPRequest(msg) = receive().await? {
+                if !msg.more_body {
+                    break;
+                };
+            }
+            send(ASGISendEvent::new_http_response_start(200, Headers::new())).await?;
+            send(ASGISendEvent::new_http_response_body(b"hello".to_vec(), true)).await?;
+            if let ASGIReceiveEvent::HTTPDisconnect(_) = receive().await? {
+                self.disconnected.store(true, Ordering::Release);
+            };
+            Ok(())
+        }
+    }
+
+    // Fails after the whole response was sent
+    #[derive(Clone, Debug)]
+    struct ErrorAfterResponseApp;
+
+    impl ASGICallable<MockState> for ErrorAfterResponseApp {
+        async fn call(&self, _scope: Scope<MockState>, receive: ReceiveFn, send: SendFn) -> super::Result<()> {
+            _ = receive().await?;
+            send(ASGISendEvent::new_http_response_start(200, Headers::new())).await?;
+            send(ASGISendEvent::new_http_response_body("hello", false)).await?;
+            tokio::time::sleep(Duration::from_secs(1)).await;
+            Err(Error::custom("Error after response"))
+        }
+    }
+
+    // Keeps working after the response was sent
+    #[derive(Clone, Debug)]
+    struct BackgroundTaskApp {
+        finished: Arc<AtomicBool>,
+    }
+
+    impl ASGICallable<MockState> for BackgroundTaskApp {
+        async fn call(&self, _scope: Scope<MockState>, receive: ReceiveFn, send: SendFn) -> super::Result<()> {
+            _ = receive().await?;
+            send(ASGISendEvent::new_http_response_start(200, Headers::new())).await?;
+            send(ASGISendEvent::new_http_response_body("hello", false)).await?;
+            tokio::time::sleep(Duration::from_secs(5)).await;
+            self.finished.store(true, Ordering::Release);
+            Ok(())
+        }
+    }
+
+    // Sends the response body from a file through `http.response.pathsend`
+    #[derive(Clone, Debug)]
+    struct PathsendApp {
+        path: String,
+    }
+
+    impl ASGICallable<MockState> for PathsendApp {
+        async fn call(&self, _scope: Scope<MockState>, receive: ReceiveFn, send: SendFn) -> super::Result<()> {
+            _ = receive().await?;
+            let headers = Vec::from([("content-type", "text/plain")]);
+            send(ASGISendEvent::new_http_response_start(200, headers)).await?;
+            send(ASGISendEvent::new_http_response_pathsend(self.path.clone())).await?;
+            Ok(())
+        }
+    }
+
+    // Announces trailers and sends them in two messages after the body
+    #[derive(Clone, Debug)]
+    struct TrailersApp;
+
+    impl ASGICallable<MockState> for TrailersApp {
+        async fn call(&self, _scope: Scope<MockState>, receive: ReceiveFn, send: SendFn) -> super::Result<()> {
+            _ = receive().await?;
+            let headers = Vec::from([("content-length", "5"), ("trailer", "grpc-status, grpc-message")]);
+            send(ASGISendEvent::new_http_response_start_with_trailers(200, headers)).await?;
+            send(ASGISendEvent::new_http_response_body("hello", false)).await?;
+            send(ASGISendEvent::new_http_response_trailers([("grpc-status", "0")], true)).await?;
+            send(ASGISendEvent::new_http_response_trailers([("grpc-message", "ok")], false)).await?;
+            Ok(())
+        }
+    }
+
     async fn response_to_body_string(response: Response) -> String {
         String::from_utf8(response.into_body().collect().await.unwrap().to_bytes().to_vec()).unwrap()
     }
@@ -266,13 +706,35 @@ mod tests {
             .expect("Failed to build request");
         let scope = Scope::HTTP(HTTPScope::from_hyper_request(&request, MockState {}));
 
-        let response = serve_http(app, request, scope).await.unwrap();
+        let response = serve_http(app, request, scope, Timeouts::default(), None, None).await.unwrap();
         assert!(response.status() == StatusCode::OK);
         let response_body = response_to_body_string(response).await;
 
         assert!(response_body == "hello world")
     }
 
+    #[tokio::test]
+    async fn test_decoded_request_body() {
+        let app = ApplicationFactory::new(EchoApp::new()).build();
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        std::io::Write::write_all(&mut encoder, "hello world".repeat(10).as_bytes()).unwrap();
+        let compressed = encoder.finish().unwrap();
+        let parts = futures::stream::iter(compressed.chunks(5).map(Bytes::copy_from_slice).collect::<Vec<_>>())
+            .map(|part| Ok::<_, std::io::Error>(Frame::data(part)));
+        let request = Request::builder()
+            .header("content-encoding", "gzip")
+            .header("content-length", compressed.len())
+            .body(StreamBody::new(parts))
+            .expect("Failed to build request");
+        let mut scope = HTTPScope::from_hyper_request(&request, MockState {});
+        scope.set_body_decoded();
+        assert!(scope.headers.is_empty());
+
+        let encoding = Some(ContentEncoding::Gzip);
+        let response = serve_http(app, request, Scope::HTTP(scope), Timeouts::default(), Some(110), encoding).await;
+        assert!(response_to_body_string(response.unwrap()).await == "hello world".repeat(10));
+    }
+
     #[tokio::test]
     async fn test_body_sent_in_parts() {
         let app = ApplicationFactory::new(EchoApp::new_with_body(" more body")).build();
@@ -281,7 +743,7 @@ mod tests {
             .expect("Failed to build request");
         let scope = Scope::HTTP(HTTPScope::from_hyper_request(&request, MockState {}));
 
-        let response = serve_http(app, request, scope).await.unwrap();
+        let response = serve_http(app, request, scope, Timeouts::default(), None, None).await.unwrap();
         assert!(response.status() == StatusCode::OK);
         let response_body = response_to_body_string(response).await;
         println!("{}", response_body);
@@ -295,7 +757,7 @@ mod tests {
             .body("hello world".to_string())
             .expect("Failed to build request");
         let scope = Scope::HTTP(HTTPScope::from_hyper_request(&request, MockState {}));
-        let response = serve_http(app, request, scope).await;
+        let response = serve_http(app, request, scope, Timeouts::default(), None, None).await;
 
         assert!(response.is_err_and(|e| e.to_string() == "Unexpected ASGI message received. Some(AppReturned)"));
     }
@@ -307,7 +769,7 @@ mod tests {
             .body("hello world".to_string())
             .expect("Failed to build request");
         let scope = Scope::HTTP(HTTPScope::from_hyper_request(&request, MockState {}));
-        let response = serve_http(app, request, scope).await;
+        let response = serve_http(app, request, scope, Timeouts::default(), None, None).await;
 
         assert!(response.is_err_and(|e| e.to_string() == "Immediate error"));
     }
@@ -319,7 +781,7 @@ mod tests {
             .body("hello world".to_string())
             .expect("Failed to build request");
         let scope = Scope::HTTP(HTTPScope::from_hyper_request(&request, MockState {}));
-        let response = serve_http(app, request, scope).await;
+        let response = serve_http(app, request, scope, Timeouts::default(), None, None).await;
 
         assert!(response.is_err_and(|e| e.to_string() == "Error in loop"));
     }
@@ -332,7 +794,7 @@ mod tests {
             .expect("Failed to build request");
         let scope = Scope::HTTP(HTTPScope::from_hyper_request(&request, MockState {}));
 
-        let response = serve_http(app, request, scope).await.unwrap();
+        let response = serve_http(app, request, scope, Timeouts::default(), None, None).await.unwrap();
         let body = response.into_body().collect().await;
 
         assert!(body.is_err_and(|e| e.to_string() == "Unexpected ASGI message received. Some(StartupComplete(LifespanStartupComplete { type_: \"lifespan.startup.complete\" }))"));
@@ -346,11 +808,291 @@ mod tests {
             .expect("Failed to build request");
         let scope = Scope::HTTP(HTTPScope::from_hyper_request(&request, MockState {}));
 
-        let response = serve_http(app, request, scope).await.unwrap();
+        let response = serve_http(app, request, scope, Timeouts::default(), None, None).await.unwrap();
         assert!(response.status() == StatusCode::OK);
         let headers = response.headers();
 
-        assert!(headers.get("test").and_then(|v| Some(v.to_str().unwrap())) == Some("header"));
-        assert!(headers.get("another").and_then(|v| Some(v.to_str().unwrap()))  == Some("header"));
+        assert!(headers.get("test").map(|v| v.to_str().unwrap()) == Some("header"));
+        assert!(headers.get("another").map(|v| v.to_str().unwrap())  == Some("header"));
+    }
+    // Starts the response after the first part of the request body
+    #[derive(Clone, Debug)]
+    struct EarlyResponseApp {
+        disconnected: Arc<AtomicBool>,
+    }
+
+    impl ASGICallable<MockState> for EarlyResponseApp {
+        async fn call(&self, _scope: Scope<MockState>, receive: ReceiveFn, send: SendFn) -> super::Result<()> {
+            _ = receive().await?;
+            send(ASGISendEvent::new_http_response_start(200, Headers::new())).await?;
+            while let ASGIReceiveEvent::HTTPRequest(_) = receive().await? {}
+            self.disconnected.store(true, Ordering::Release);
+            Ok(())
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_request_timeout() {
+        let app = ApplicationFactory::new(SlowApp {}).build();
+        let request = Request::builder()
+            .body("hello world".to_string())
+            .expect("Failed to build request");
+        let scope = Scope::HTTP(HTTPScope::from_hyper_request(&request, MockState {}));
+        let timeouts = Timeouts {
+            request: Some(Duration::from_secs(5)),
+            ..Timeouts::default()
+        };
+
+        let start = tokio::time::Instant::now();
+        let response = serve_http(app, request, scope, timeouts, None, None).await.unwrap();
+        assert!(response.status() == StatusCode::GATEWAY_TIMEOUT);
+        assert!(start.elapsed() == Duration::from_secs(5));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_request_within_timeout() {
+        let app = ApplicationFactory::new(SlowApp {}).build();
+        let request = Request::builder()
+            .body("hello world".to_string())
+            .expect("Failed to build request");
+        let scope = Scope::HTTP(HTTPScope::from_hyper_request(&request, MockState {}));
+        let timeouts = Timeouts {
+            request: Some(Duration::from_secs(15)),
+            ..Timeouts::default()
+        };
+
+        let response = serve_http(app, request, scope, timeouts, None, None).await.unwrap();
+        assert!(response.status() == StatusCode::OK);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_body_read_timeout() {
+        let app = ApplicationFactory::new(EchoApp::new()).build();
+        // The client sends the first part of the body and then stalls
+        let first_part = futures::stream::iter([Ok::<_, std::io::Error>(Frame::data(Bytes::from("hello")))]);
+        let request = Request::builder()
+            .body(StreamBody::new(first_part.chain(futures::stream::pending())))
+            .expect("Failed to build request");
+        let scope = Scope::HTTP(HTTPScope::from_hyper_request(&request, MockState {}));
+        let timeouts = Timeouts {
+            body_read: Some(Duration::from_secs(5)),
+            ..Timeouts::default()
+        };
+
+        let start = tokio::time::Instant::now();
+        let response = serve_http(app, request, scope, timeouts, None, None).await;
+        assert!(response.is_err_and(|e| matches!(e, Error::Timeout(_))));
+        assert!(start.elapsed() == Duration::from_secs(5));
+    }
+
+    #[tokio::test]
+    async fn test_declared_body_too_large() {
+        let app = ApplicationFactory::new(EchoApp::new()).build();
+        let request = Request::builder()
+            .body("hello world".to_string())
+            .expect("Failed to build request");
+        let scope = Scope::HTTP(HTTPScope::from_hyper_request(&request, MockState {}));
+
+        let response = serve_http(app, request, scope, Timeouts::default(), Some(5), None).await;
+        assert!(response.is_err_and(|e| matches!(e, Error::PayloadTooLarge(5))));
+    }
+
+    #[tokio::test]
+    async fn test_streamed_body_too_large() {
+        let app = ApplicationFactory::new(EchoApp::new()).build();
+        // Without a content length, like a chunked request
+        let parts = futures::stream::iter([
+            Ok::<_, std::io::Error>(Frame::data(Bytes::from("hello"))),
+            Ok(Frame::data(Bytes::from(" world"))),
+        ]);
+        let request = Request::builder()
+            .body(StreamBody::new(parts))
+            .expect("Failed to build request");
+        let scope = Scope::HTTP(HTTPScope::from_hyper_request(&request, MockState {}));
+
+        let response = serve_http(app, request, scope, Timeouts::default(), Some(8), None).await;
+        assert!(response.is_err_and(|e| matches!(e, Error::PayloadTooLarge(8))));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_body_too_large_after_response_started() {
+        let disconnected = Arc::new(AtomicBool::new(false));
+        let app = ApplicationFactory::new(EarlyResponseApp { disconnected: disconnected.clone() }).build();
+        let first_part = futures::stream::iter([Ok::<_, std::io::Error>(Frame::data(Bytes::from("hello")))]);
+        let second_part = futures::stream::once(async {
+            tokio::time::sleep(Duration::from_secs(1)).await;
+            Ok(Frame::data(Bytes::from(" world")))
+        });
+        let request = Request::builder()
+            .body(StreamBody::new(first_part.chain(second_part)))
+            .expect("Failed to build request");
+        let scope = Scope::HTTP(HTTPScope::from_hyper_request(&request, MockState {}));
+
+        let response = serve_http(app, request, scope, Timeouts::default(), Some(8), None).await;
+        assert!(response.is_err_and(|e| matches!(e, Error::Aborted(_))));
+
+        // Let the application handle the disconnect
+        tokio::time::sleep(Duration::from_millis(1)).await;
+        assert!(disconnected.load(Ordering::Acquire));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_response_body_timeout() {
+        let disconnected = Arc::new(AtomicBool::new(false));
+        let app = ApplicationFactory::new(StallingApp { disconnected: disconnected.clone() }).build();
+        let request = Request::builder()
+            .body("hello world".to_string())
+            .expect("Failed to build request");
+        let scope = Scope::HTTP(HTTPScope::from_hyper_request(&request, MockState {}));
+        let timeouts = Timeouts {
+            response_body: Some(Duration::from_secs(5)),
+            ..Timeouts::default()
+        };
+
+        let start = tokio::time::Instant::now();
+        let response = serve_http(app, request, scope, timeouts, None, None).await.unwrap();
+        let body = response.into_body().collect().await;
+        assert!(body.is_err_and(|e| matches!(e, Error::Timeout(_))));
+        assert!(start.elapsed() == Duration::from_secs(5));
+
+        // Let the application handle the disconnect
+        tokio::time::sleep(Duration::from_millis(1)).await;
+        assert!(disconnected.load(Ordering::Acquire));
+    }
+
+    #[tokio::test]
+    async fn test_response_streamed_before_app_returns() {
+        let disconnected = Arc::new(AtomicBool::new(false));
+        let app = ApplicationFactory::new(StallingApp { disconnected: disconnected.clone() }).build();
+        let request = Request::builder()
+            .body("hello world".to_string())
+            .expect("Failed to build request");
+        let scope = Scope::HTTP(HTTPScope::from_hyper_request(&request, MockState {}));
+
+        let response = serve_http(app, request, scope, Timeouts::default(), None, None).await.unwrap();
+        let mut body = response.into_body();
+        let first = body.frame().await.unwrap().unwrap().into_data().unwrap();
+        assert!(first == "hello");
+        assert!(!disconnected.load(Ordering::Acquire));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_app_error_after_response_started() {
+        let app = ApplicationFactory::new(ErrorAfterResponseApp {}).build();
+        let request = Request::builder()
+            .body("hello world".to_string())
+            .expect("Failed to build request");
+        let scope = Scope::HTTP(HTTPScope::from_hyper_request(&request, MockState {}));
+
+        let response = serve_http(app, request, scope, Timeouts::default(), None, None).await.unwrap();
+        let body = response.into_body().collect().await;
+        assert!(body.is_err_and(|e| e.to_string() == "Error after response"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_response_finished_once_app_returns() {
+        let finished = Arc::new(AtomicBool::new(false));
+        let app = ApplicationFactory::new(BackgroundTaskApp { finished: finished.clone() }).build();
+        let request = Request::builder()
+            .body("hello world".to_string())
+            .expect("Failed to build request");
+        let scope = Scope::HTTP(HTTPScope::from_hyper_request(&request, MockState {}));
+
+        let start = tokio::time::Instant::now();
+        let response = serve_http(app, request, scope, Timeouts::default(), None, None).await.unwrap();
+        assert!(response_to_body_string(response).await == "hello");
+        assert!(finished.load(Ordering::Acquire));
+        assert!(start.elapsed() == Duration::from_secs(5));
+    }
+
+    #[tokio::test]
+    async fn test_pathsend() {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/Cargo.toml");
+        let app = ApplicationFactory::new(PathsendApp { path: path.to_string() }).build();
+        let request = Request::builder()
+            .body("hello world".to_string())
+            .expect("Failed to build request");
+        let scope = HTTPScope::from_hyper_request(&request, MockState {});
+        assert!(scope.extensions.pathsend);
+
+        let response = serve_http(app, request, Scope::HTTP(scope), Timeouts::default(), None, None).await.unwrap();
+        let expected = std::fs::read_to_string(path).unwrap();
+        let content_length = response.headers().get("content-length").map(|v| v.to_str().unwrap().to_string());
+        assert!(content_length == Some(expected.len().to_string()));
+        assert!(response.headers().get("content-type").unwrap() == "text/plain");
+        assert!(response_to_body_string(response).await == expected);
+    }
+
+    #[tokio::test]
+    async fn test_pathsend_invalid_path() {
+        for path in ["Cargo.toml", "/does/not/exist", env!("CARGO_MANIFEST_DIR")] {
+            let app = ApplicationFactory::new(PathsendApp { path: path.to_string() }).build();
+            let request = Request::builder()
+                .body("hello world".to_string())
+                .expect("Failed to build request");
+            let scope = Scope::HTTP(HTTPScope::from_hyper_request(&request, MockState {}));
+            let response = serve_http(app, request, scope, Timeouts::default(), None, None).await;
+            assert!(response.is_err());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_response_trailers() {
+        let app = ApplicationFactory::new(TrailersApp {}).build();
+        let request = Request::builder()
+            .body("hello world".to_string())
+            .expect("Failed to build request");
+        let scope = HTTPScope::from_hyper_request(&request, MockState {});
+        assert!(scope.extensions.trailers);
+
+        let response = serve_http(app, request, Scope::HTTP(scope), Timeouts::default(), None, None).await.unwrap();
+        // Chunked on HTTP/1.1, trailers can't follow a body with a declared length
+        assert!(response.headers().get("content-length").is_none());
+        let collected = response.into_body().collect().await.unwrap();
+        let trailers = collected.trailers().cloned().unwrap();
+        assert!(trailers.get("grpc-status").unwrap() == "0");
+        assert!(trailers.get("grpc-message").unwrap() == "ok");
+        assert!(collected.to_bytes() == "hello");
+    }
+
+    #[tokio::test]
+    async fn test_response_trailers_over_http1() {
+        let (client, server) = tokio::io::duplex(4096);
+        let service = service_fn(|request: Request<Incoming>| {
+            let app = ApplicationFactory::new(TrailersApp {}).build();
+            let scope = Scope::HTTP(HTTPScope::from_hyper_request(&request, MockState {}));
+            serve_http(app, request, scope, Timeouts::default(), None, None)
+        });
+        tokio::spawn(http1::Builder::new().serve_connection(TokioIo::new(server), service));
+        let (mut sender, connection) = hyper::client::conn::http1::handshake(TokioIo::new(client)).await.unwrap();
+        tokio::spawn(connection);
+
+        // Hyper only sends trailers to clients that accept them
+        let request = Request::builder()
+            .header("host", "localhost")
+            .header("te", "trailers")
+            .body(Empty::<Bytes>::new())
+            .expect("Failed to build request");
+        let response = sender.send_request(request).await.unwrap();
+        assert!(response.headers().get("transfer-encoding").unwrap() == "chunked");
+        let collected = response.into_body().collect().await.unwrap();
+        let trailers = collected.trailers().cloned().unwrap();
+        assert!(trailers.get("grpc-status").unwrap() == "0");
+        assert!(trailers.get("grpc-message").unwrap() == "ok");
+        assert!(collected.to_bytes() == "hello");
+    }
+
+    #[test]
+    fn test_scope_http_version() {
+        let http1_request = Request::builder()
+            .body("hello world".to_string())
+            .expect("Failed to build request");
+        let http2_request = Request::builder()
+            .version(http::Version::HTTP_2)
+            .body("hello world".to_string())
+            .expect("Failed to build request");
+
+        assert!(HTTPScope::from_hyper_request(&http1_request, MockState {}).http_version == "1.1");
+        assert!(HTTPScope::from_hyper_request(&http2_request, MockState {}).http_version == "2");
     }
 }
@@ -1,7 +1,9 @@
+mod decoder;
 mod events;
 mod handler;
 mod scope;
 
+pub(crate) use decoder::request_body_encoding;
 pub use events::*;
 pub use handler::serve_http;
 pub use scope::HTTPScope;
\ No newline at end of file
@@ -1,8 +1,10 @@
 use std::fmt::Debug;
 
+use bytes::Bytes;
 use hyper::body::Body;
 use hyper::Request;
-use crate::{asgispec::ASGIScope, server::ConnectionInfo};
+use crate::{asgispec::{http_version_to_scope, scope_headers, ASGIScope, Headers, ScopeExtensions}, server::{ConnectionInfo, Forwarded}};
+use crate::middleware_services::RequestId;
 
 #[derive(Debug, Clone)]
 pub struct HTTPScope<S: Clone + Send + Sync> {
@@ -12,19 +14,44 @@ pub struct HTTPScope<S: Clone + Send + Sync> {
     pub method: String,
     pub scheme: String,
     pub path: String,
-    pub raw_path: Vec<u8>,
-    pub query_string: Vec<u8>,
+    pub raw_path: Bytes,
+    pub query_string: Bytes,
     pub root_path: String,
-    pub headers: Vec<(Vec<u8>, Vec<u8>)>,
+    pub headers: Headers,
     pub client: Option<(String, u16)>,
-    pub server: Option<(String, u16)>,
+    pub server: Option<(String, Option<u16>)>,
+    pub extensions: ScopeExtensions,
     pub state: S,
 }
 
 impl<S: Clone + Send + Sync> HTTPScope<S> {
     pub fn set_conn_info(&mut self, info: &ConnectionInfo) {
-        self.client = Some((info.client_ip.to_owned(), info.client_port));
-        self.server = Some((info.server_ip.to_owned(), info.server_port));
+        self.client = info.scope_client();
+        self.server = info.scope_server();
+        if info.tls.is_some() {
+            self.scheme = String::from("https");
+        };
+        self.extensions.tls = info.tls.clone();
+    }
+
+    // Only holds what trusted proxies told, set after the connection info
+    pub fn set_forwarded(&mut self, forwarded: &Forwarded) {
+        if let Some((ip, port)) = forwarded.client {
+            self.client = Some((ip.to_string(), port));
+        };
+        if let Some(scheme) = forwarded.scheme(false) {
+            self.scheme = String::from(scheme);
+        };
+        if let Some(server) = forwarded.server(&self.scheme) {
+            self.server = Some(server);
+        };
+    }
+
+    // The application receives the decoded body, its encoding and original length no longer apply
+    pub fn set_body_decoded(&mut self) {
+        self.headers.retain(|(name, _)| {
+            !name.eq_ignore_ascii_case(b"content-encoding") && !name.eq_ignore_ascii_case(b"content-length")
+        });
     }
 
     pub fn from_hyper_request<B>(value: &Request<B>, state: S) -> Self
@@ -35,20 +62,22 @@ impl<S: Clone + Send + Sync> HTTPScope<S> {
         Self {
             type_: String::from("http"),
             asgi: ASGIScope::new(),
-            http_version: format!("{:?}", value.version()),
+            http_version: http_version_to_scope(value.version()),
             method: value.method().as_str().to_owned(),
             scheme: String::from("http"),
             path: value.uri().path().to_owned(),
-            raw_path: value.uri().to_string().as_bytes().to_vec(),
-            query_string: value.uri().query().unwrap_or("").as_bytes().to_vec(),
+            raw_path: Bytes::from(value.uri().to_string()),
+            query_string: Bytes::copy_from_slice(value.uri().query().unwrap_or("").as_bytes()),
             root_path: String::from(""), // Optional, default for now
-            headers: value
-                .headers()
-                .into_iter()
-                .map(|(name, value)| (name.as_str().as_bytes().to_vec(), value.as_bytes().to_vec()))
-                .collect(),
+            headers: scope_headers(value.headers()),
             client: None,
             server: None,
+            extensions: ScopeExtensions {
+                request_id: value.extensions().get::<RequestId>().map(RequestId::to_string),
+                pathsend: true,
+                trailers: true,
+                ..ScopeExtensions::default()
+            },
             state,
         }
     }
@@ -77,8 +106,11 @@ impl<S: Clone + Send + Sync + std::fmt::Debug> std::fmt::Display for &HTTPScope<
             writeln!(f, "client: None")?;
         }
 
-        if let Some((ip, port)) = &self.server {
-            writeln!(f, "server: {}:{}", ip, port)?;
+        if let Some((host, port)) = &self.server {
+            match port {
+                Some(port) => writeln!(f, "server: {}:{}", host, port)?,
+                None => writeln!(f, "server: {}", host)?,
+            };
         } else {
             writeln!(f, "server: None")?;
         }
@@ -8,7 +8,9 @@ mod websocket;
 mod application;
 mod middleware_services;
 
-pub use crate::asgispec::{ASGICallable, ASGIReceiveEvent, ASGISendEvent, ASGIScope, ReceiveFn, Scope, SendFn, State};
+pub use crate::asgispec::{
+    ASGICallable, ASGIReceiveEvent, ASGISendEvent, ASGIScope, Headers, ReceiveFn, Scope, ScopeExtensions, SendFn, State, TLSExtension,
+};
 pub use crate::error::{Error, Result};
 pub use crate::http::{
     HTTPDisconnectEvent, HTTPRequestEvent, HTTPResonseBodyEvent, HTTPResponseStartEvent, HTTPScope, serve_http,
@@ -22,10 +24,27 @@ pub use crate::websocket::{
     WebsocketScope, WebsocketSendEvent, serve_websocket,
 };
 pub use crate::application::{Application, ApplicationFactory};
-pub use crate::server::{Server, ServerConfig};
+pub use crate::middleware_services::{QueueMetrics, RequestId};
+pub use ipnet::IpNet;
+pub use crate::server::{
+    parse_networks, AccessLogConfig, AccessLogFormat, AccessLogTarget, BindTarget, BodySizeLimits, ClientCertMode,
+    CompressionConfig, ConcurrencyQueue, ContentEncoding, Forwarded, HttpProtocol, Metrics, MetricsConfig, ProxyProtocol,
+    RateLimit, Server, ServerConfig, ShutdownHandle, StaticMount, Timeouts, TlsConfig, UnixSocketConfig,
+    systemd_listen_fds,
+};
 
 pub async fn serve<S: State + 'static, T: ASGICallable<S> + 'static>(app: T, state: S, config: Option<ServerConfig>) -> Result<()> {
-    let mut server = Server::new(app, state);
+    serve_with_shutdown(app, state, config, ShutdownHandle::new()).await
+}
+
+// Serve until a shutdown is requested through the `ShutdownHandle` or a signal is received
+pub async fn serve_with_shutdown<S: State + 'static, T: ASGICallable<S> + 'static>(
+    app: T,
+    state: S,
+    config: Option<ServerConfig>,
+    shutdown: ShutdownHandle,
+) -> Result<()> {
+    let mut server = Server::new(app, state).with_shutdown_handle(shutdown);
     server.serve(config.unwrap_or_default()).await?;
     Ok(())
 }
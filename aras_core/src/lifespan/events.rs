@@ -9,6 +9,12 @@ impl LifespanStartup {
     }
 }
 
+impl Default for LifespanStartup {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 #[derive(Debug)]
 pub struct LifespanStartupComplete {
     pub type_: String,
@@ -20,6 +26,12 @@ impl LifespanStartupComplete {
     }
 }
 
+impl Default for LifespanStartupComplete {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 #[derive(Debug)]
 pub struct LifespanStartupFailed {
     pub type_: String,
@@ -43,6 +55,12 @@ impl LifespanShutdown {
     }
 }
 
+impl Default for LifespanShutdown {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 #[derive(Debug)]
 pub struct LifespanShutdownComplete {
     pub type_: String,
@@ -54,6 +72,12 @@ impl LifespanShutdownComplete {
     }
 }
 
+impl Default for LifespanShutdownComplete {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 #[derive(Debug)]
 pub struct LifespanShutdownFailed {
     pub type_: String,
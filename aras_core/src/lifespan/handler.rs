@@ -164,7 +164,7 @@ mod tests {
                 loop {
                     _ = receive().await?;
                     // Send an unrelated message, to mimick the protocol not being supported
-                    send(ASGISendEvent::new_http_response_body("oops".into(), false)).await?;
+                    send(ASGISendEvent::new_http_response_body("oops", false)).await?;
                 }
             };
             Err(Error::custom("Invalid scope"))
@@ -256,7 +256,7 @@ mod tests {
         let app = ApplicationFactory::new(LifespanUnsupportedApp {}).build();
         let lifespan_handler = LifespanHandler::new(app);
         let lifespan_handler = lifespan_handler.startup(MockState {}).await.unwrap();
-        assert!(lifespan_handler.enabled == false);
+        assert!(!lifespan_handler.enabled);
     }
 
     #[tokio::test]
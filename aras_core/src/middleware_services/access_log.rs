@@ -0,0 +1,456 @@
+use std::fmt::{Debug, Write as _};
+use std::io::Write;
+use std::net::IpAddr;
+use std::pin::Pin;
+use std::sync::mpsc::{SyncSender, TrySendError};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use bytes::Bytes;
+use derive_more::derive::Constructor;
+use http_body_util::BodyExt;
+use hyper::body::{Body, Frame, Incoming, SizeHint};
+use hyper::service::Service;
+use hyper::Request;
+use ipnet::IpNet;
+use log::{debug, error, info, warn};
+
+use crate::error::{Error, Result};
+use crate::middleware_services::RequestId;
+use crate::server::{AccessLogConfig, AccessLogFormat, AccessLogTarget, Forwarded, Metrics};
+use crate::types::{Response, ServiceFuture};
+
+// Lines waiting for the writer thread, further lines are dropped
+const BUFFERED_LINES: usize = 10_000;
+
+// Writes a line per request once its response body is sent, or the client went away.
+// Without a config nothing is logged.
+#[derive(Debug, Clone)]
+pub struct AccessLog {
+    format: AccessLogFormat,
+    sink: Option<Sink>,
+    forwarded_allow_ips: Arc<Vec<IpNet>>,
+    metrics: Metrics,
+}
+
+#[derive(Debug, Clone)]
+enum Sink {
+    Log,
+    // Lines are written by a separate thread, so a slow disk doesn't block the runtime
+    Writer(SyncSender<String>),
+}
+
+impl AccessLog {
+    pub fn new(config: Option<AccessLogConfig>, forwarded_allow_ips: Vec<IpNet>, metrics: Metrics) -> Result<Self> {
+        let format = config.as_ref().map_or(AccessLogFormat::Common, |config| config.format);
+        let sink = match config.map(|config| config.target) {
+            None => None,
+            Some(AccessLogTarget::Log) => Some(Sink::Log),
+            Some(AccessLogTarget::Stdout) => {
+                Some(Sink::Writer(spawn_writer(Box::new(std::io::stdout()), metrics.clone())?))
+            }
+            Some(AccessLogTarget::File(path)) => {
+                let file = std::fs::OpenOptions::new().create(true).append(true).open(&path).map_err(|e| {
+                    Error::custom(format!("Failed to open access log {}. {e}", path.display()))
+                })?;
+                Some(Sink::Writer(spawn_writer(Box::new(file), metrics.clone())?))
+            }
+        };
+        Ok(Self {
+            format,
+            sink,
+            forwarded_allow_ips: Arc::new(forwarded_allow_ips),
+            metrics,
+        })
+    }
+
+    pub fn as_layer<S>(&self, peer: Option<IpAddr>) -> impl Fn(S) -> AccessLogLayer<S>
+    where
+        S: Service<Request<Incoming>, Response = Response, Error = Error, Future = ServiceFuture>
+            + Send
+            + Sync
+            + 'static,
+    {
+        let log = self.clone();
+        move |inner: S| -> AccessLogLayer<S> {
+            AccessLogLayer::new(Arc::new(inner), log.clone(), peer)
+        }
+    }
+
+    // Lines in the server log carry the request id like all other lines of the request
+    fn write(&self, line: String, request_id: Option<&str>) {
+        match &self.sink {
+            Some(Sink::Log) => info!(target: "access", "[{}] {line}", request_id.unwrap_or("-")),
+            // Requests are not held up when the writer can't keep up
+            Some(Sink::Writer(lines)) => {
+                if let Err(TrySendError::Full(_)) = lines.try_send(line) {
+                    self.metrics.record_access_log_dropped();
+                };
+            }
+            None => (),
+        };
+    }
+}
+
+fn spawn_writer(mut out: Box<dyn Write + Send>, metrics: Metrics) -> Result<SyncSender<String>> {
+    let (lines, received) = std::sync::mpsc::sync_channel::<String>(BUFFERED_LINES);
+    std::thread::Builder::new()
+        .name(String::from("aras-access-log"))
+        .spawn(move || {
+            let mut reported = 0;
+            // Stops once all senders are dropped, when the server is done
+            while let Ok(line) = received.recv() {
+                let mut buffer = line + "\n";
+                // Flush once per burst of lines
+                while let Ok(line) = received.try_recv() {
+                    buffer += &line;
+                    buffer += "\n";
+                }
+                if let Err(e) = out.write_all(buffer.as_bytes()).and_then(|_| out.flush()) {
+                    error!("Failed to write access log: {e}");
+                };
+                let dropped = metrics.access_log_dropped_total();
+                if dropped > reported {
+                    warn!("Dropped {} access log line(s), writing the log can't keep up", dropped - reported);
+                    reported = dropped;
+                };
+            }
+        })?;
+    Ok(lines)
+}
+
+#[derive(Constructor, Debug, Clone)]
+pub struct AccessLogLayer<S> {
+    inner: Arc<S>,
+    log: AccessLog,
+    peer: Option<IpAddr>,
+}
+
+impl<S> Service<Request<Incoming>> for AccessLogLayer<S>
+where
+    S: Service<Request<Incoming>, Response = Response, Error = Error, Future = ServiceFuture> + Send + Sync + 'static,
+{
+    type Error = S::Error;
+    type Response = S::Response;
+    type Future = S::Future;
+
+    fn call(&self, req: Request<Incoming>) -> Self::Future {
+        let request_id = RequestId::of(&req);
+        debug!("[{request_id}] Processing request: {} {}", req.method(), req.uri().path());
+        let entry = self.log.sink.as_ref().map(|_| {
+            let forwarded = Forwarded::from_headers(self.peer, req.headers(), &self.log.forwarded_allow_ips);
+            Entry::new(&req, forwarded.client_ip().or(self.peer))
+        });
+        let inner_clone = self.inner.clone();
+        let log = self.log.clone();
+        Box::pin(async move {
+            match inner_clone.call(req).await {
+                Ok(res) => {
+                    debug!("[{request_id}] Response started: {}", res.status());
+                    let Some(mut entry) = entry else {
+                        return Ok(res);
+                    };
+                    entry.status = res.status().as_u16();
+                    Ok(res.map(|body| LoggedBody::new(body, entry, log).boxed()))
+                }
+                Err(e) => {
+                    error!("[{request_id}] Failed to send response: {e}");
+                    // Logged with status 0, no response was sent
+                    if let Some(entry) = entry {
+                        log.write(entry.format(log.format, 0), entry.request_id.as_deref());
+                    };
+                    Err(e)
+                }
+            }
+        })
+    }
+}
+
+// What is known about a request before its response is sent
+#[derive(Debug, Clone)]
+struct Entry {
+    client: Option<IpAddr>,
+    time: SystemTime,
+    start: Instant,
+    method: String,
+    target: String,
+    version: String,
+    status: u16,
+    referer: Option<String>,
+    user_agent: Option<String>,
+    request_id: Option<String>,
+}
+
+impl Entry {
+    fn new<B>(req: &Request<B>, client: Option<IpAddr>) -> Self {
+        let header = |name: &str| {
+            req.headers()
+                .get(name)
+                .map(|value| String::from_utf8_lossy(value.as_bytes()).into_owned())
+        };
+        Self {
+            client,
+            time: SystemTime::now(),
+            start: Instant::now(),
+            method: req.method().to_string(),
+            target: req.uri().path_and_query().map_or_else(|| req.uri().path().to_owned(), |pq| pq.to_string()),
+            version: format!("{:?}", req.version()),
+            status: 0,
+            referer: header("referer"),
+            user_agent: header("user-agent"),
+            request_id: header("x-request-id"),
+        }
+    }
+
+    fn format(&self, format: AccessLogFormat, bytes: u64) -> String {
+        let client = self.client.map_or_else(|| String::from("-"), |ip| ip.to_canonical().to_string());
+        let duration = self.start.elapsed().as_secs_f64();
+        let (year, month, day, hour, minute, second, millis) = civil_time(self.time);
+        let optional = |value: &Option<String>| value.as_deref().map_or_else(|| String::from("-"), quoted);
+        match format {
+            AccessLogFormat::Common | AccessLogFormat::Combined => {
+                let mut line = format!(
+                    "{client} - - [{day:02}/{}/{year}:{hour:02}:{minute:02}:{second:02} +0000] \"{} {} {}\" {} {}",
+                    MONTHS[month as usize - 1],
+                    quoted(&self.method),
+                    quoted(&self.target),
+                    self.version,
+                    self.status,
+                    if bytes == 0 { String::from("-") } else { bytes.to_string() },
+                );
+                if format == AccessLogFormat::Combined {
+                    _ = write!(
+                        line,
+                        " \"{}\" \"{}\" {duration:.6} \"{}\"",
+                        optional(&self.referer),
+                        optional(&self.user_agent),
+                        optional(&self.request_id),
+                    );
+                };
+                line
+            }
+            AccessLogFormat::Json => {
+                let string_or_null = |value: &Option<String>| value.as_deref().map_or_else(|| String::from("null"), json_string);
+                format!(
+                    "{{\"time\":\"{year:04}-{month:02}-{day:02}T{hour:02}:{minute:02}:{second:02}.{millis:03}Z\",\
+                     \"client\":{},\"method\":{},\"path\":{},\"http_version\":\"{}\",\"status\":{},\"bytes\":{bytes},\
+                     \"duration\":{duration:.6},\"referer\":{},\"user_agent\":{},\"request_id\":{}}}",
+                    string_or_null(&self.client.map(|ip| ip.to_canonical().to_string())),
+                    json_string(&self.method),
+                    json_string(&self.target),
+                    self.version,
+                    self.status,
+                    string_or_null(&self.referer),
+                    string_or_null(&self.user_agent),
+                    string_or_null(&self.request_id),
+                )
+            }
+        }
+    }
+}
+
+const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+// UTC date and time, see http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+fn civil_time(time: SystemTime) -> (i64, u32, u32, u32, u32, u32, u32) {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs() as i64;
+    let (days, secs_of_day) = (secs.div_euclid(86_400), secs.rem_euclid(86_400) as u32);
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (
+        year,
+        month,
+        day,
+        secs_of_day / 3600,
+        secs_of_day / 60 % 60,
+        secs_of_day % 60,
+        since_epoch.subsec_millis(),
+    )
+}
+
+// Escapes quotes, backslashes and control characters within quoted log fields
+fn quoted(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '"' | '\\' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            c if c.is_control() => _ = write!(escaped, "\\x{:02x}", c as u32),
+            c => escaped.push(c),
+        };
+    }
+    escaped
+}
+
+fn json_string(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len() + 2);
+    escaped.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if c.is_control() => _ = write!(escaped, "\\u{:04x}", c as u32),
+            c => escaped.push(c),
+        };
+    }
+    escaped.push('"');
+    escaped
+}
+
+// Counts the bytes of the response body, the line is written once the body ended
+// or is dropped before that because the client disconnected
+struct LoggedBody {
+    inner: http_body_util::combinators::BoxBody<Bytes, Error>,
+    bytes: u64,
+    entry: Option<Entry>,
+    log: AccessLog,
+}
+
+impl LoggedBody {
+    fn new(inner: http_body_util::combinators::BoxBody<Bytes, Error>, entry: Entry, log: AccessLog) -> Self {
+        Self { inner, bytes: 0, entry: Some(entry), log }
+    }
+
+    fn finish(&mut self) {
+        if let Some(entry) = self.entry.take() {
+            self.log.write(entry.format(self.log.format, self.bytes), entry.request_id.as_deref());
+        };
+    }
+}
+
+impl Body for LoggedBody {
+    type Data = Bytes;
+    type Error = Error;
+
+    fn poll_frame(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<std::result::Result<Frame<Bytes>, Error>>> {
+        let this = self.get_mut();
+        let polled = Pin::new(&mut this.inner).poll_frame(cx);
+        match &polled {
+            Poll::Ready(Some(Ok(frame))) => {
+                if let Some(data) = frame.data_ref() {
+                    this.bytes += data.len() as u64;
+                };
+            }
+            Poll::Ready(None | Some(Err(_))) => this.finish(),
+            Poll::Pending => (),
+        };
+        polled
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+impl Drop for LoggedBody {
+    fn drop(&mut self) {
+        self.finish();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use bytes::Bytes;
+    use http_body_util::{BodyExt, Full};
+
+    use super::{civil_time, AccessLog, Entry, LoggedBody, Sink};
+    use crate::server::{AccessLogFormat, Metrics};
+
+    fn entry() -> Entry {
+        let request = http::Request::builder()
+            .method("GET")
+            .uri("/search?q=%22x%22")
+            .header("user-agent", "curl \"8\"")
+            .header("x-request-id", "abc")
+            .body(())
+            .unwrap();
+        let mut entry = Entry::new(&request, Some("::ffff:10.0.0.1".parse().unwrap()));
+        entry.time = UNIX_EPOCH + Duration::from_millis(1_792_291_523_042);
+        entry.status = 200;
+        entry
+    }
+
+    #[test]
+    fn test_civil_time() {
+        assert!(civil_time(UNIX_EPOCH) == (1970, 1, 1, 0, 0, 0, 0));
+        assert!(civil_time(UNIX_EPOCH + Duration::from_millis(951_825_600_500)) == (2000, 2, 29, 12, 0, 0, 500));
+        assert!(civil_time(UNIX_EPOCH + Duration::from_secs(1_792_291_523)) == (2026, 10, 18, 2, 45, 23, 0));
+    }
+
+    #[test]
+    fn test_common_and_combined_format() {
+        let entry = entry();
+        let common = entry.format(AccessLogFormat::Common, 0);
+        assert!(common == r#"10.0.0.1 - - [18/Oct/2026:02:45:23 +0000] "GET /search?q=%22x%22 HTTP/1.1" 200 -"#);
+
+        let combined = entry.format(AccessLogFormat::Combined, 12);
+        assert!(combined.starts_with(r#"10.0.0.1 - - [18/Oct/2026:02:45:23 +0000] "GET /search?q=%22x%22 HTTP/1.1" 200 12 "-" "curl \"8\"" "#));
+        assert!(combined.ends_with(r#" "abc""#));
+    }
+
+    #[test]
+    fn test_json_format() {
+        let line = entry().format(AccessLogFormat::Json, 12);
+        assert!(line.starts_with(r#"{"time":"2026-10-18T02:45:23.042Z","client":"10.0.0.1","method":"GET","path":"/search?q=%22x%22","#));
+        assert!(line.contains(r#""http_version":"HTTP/1.1","status":200,"bytes":12,"duration":"#));
+        assert!(line.ends_with(r#""referer":null,"user_agent":"curl \"8\"","request_id":"abc"}"#));
+    }
+
+    fn writer_log(lines: std::sync::mpsc::SyncSender<String>) -> AccessLog {
+        AccessLog {
+            format: AccessLogFormat::Common,
+            sink: Some(Sink::Writer(lines)),
+            forwarded_allow_ips: Arc::new(Vec::new()),
+            metrics: Metrics::default(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_written_once_body_is_sent() {
+        let (lines, received) = std::sync::mpsc::sync_channel(10);
+        let log = writer_log(lines);
+        let body = Full::new(Bytes::from("hello")).map_err(|never| match never {}).boxed();
+        let mut body = LoggedBody::new(body, entry(), log);
+
+        assert!(received.try_recv().is_err());
+        assert!(body.frame().await.unwrap().is_ok());
+        assert!(body.frame().await.is_none());
+        assert!(received.try_recv().unwrap().ends_with(" 200 5"));
+
+        // Only a single line, also when dropped
+        drop(body);
+        assert!(received.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_lines_dropped_when_writer_is_behind() {
+        let (lines, received) = std::sync::mpsc::sync_channel(1);
+        let log = writer_log(lines);
+        log.write(String::from("first"), None);
+        log.write(String::from("second"), None);
+        assert!(log.metrics.access_log_dropped_total() == 1);
+        assert!(received.try_recv().unwrap() == "first");
+        assert!(received.try_recv().is_err());
+    }
+}
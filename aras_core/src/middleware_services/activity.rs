@@ -0,0 +1,120 @@
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use bytes::Bytes;
+use derive_more::derive::Constructor;
+use http_body_util::combinators::BoxBody;
+use hyper::body::{Body, Frame, Incoming, SizeHint};
+use hyper::service::Service;
+use hyper::Request;
+use tokio::sync::watch;
+
+use crate::error::Error;
+use crate::types::{Response, ServiceFuture};
+
+// Keeps track of the requests in flight on a single connection,
+// so connections that are idle for too long can be closed.
+#[derive(Clone)]
+pub struct ConnectionActivity {
+    in_flight: Arc<watch::Sender<usize>>,
+}
+
+impl ConnectionActivity {
+    pub fn new() -> Self {
+        Self {
+            in_flight: Arc::new(watch::Sender::new(0)),
+        }
+    }
+
+    pub fn as_layer<S>(&self) -> impl Fn(S) -> ConnectionActivityLayer<S>
+    where
+        S: Service<Request<Incoming>, Response = Response, Error = Error, Future = ServiceFuture>
+            + Send
+            + Sync
+            + 'static,
+    {
+        let activity = self.clone();
+        move |inner: S| -> ConnectionActivityLayer<S> { ConnectionActivityLayer::new(Arc::new(inner), activity.clone()) }
+    }
+
+    // Resolves once the connection had no request in flight for `timeout`
+    pub async fn idle_for(&self, timeout: Duration) {
+        let mut in_flight = self.in_flight.subscribe();
+        loop {
+            _ = in_flight.wait_for(|n| *n == 0).await;
+            tokio::select! {
+                _ = tokio::time::sleep(timeout) => return,
+                _ = in_flight.changed() => (),
+            };
+        }
+    }
+
+    fn start_request(&self) -> ActiveRequest {
+        self.in_flight.send_modify(|n| *n += 1);
+        ActiveRequest(self.in_flight.clone())
+    }
+}
+
+impl Default for ConnectionActivity {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Constructor, Clone)]
+pub struct ConnectionActivityLayer<S> {
+    inner: Arc<S>,
+    activity: ConnectionActivity,
+}
+
+impl<S> Service<Request<Incoming>> for ConnectionActivityLayer<S>
+where
+    S: Service<Request<Incoming>, Response = Response, Error = Error, Future = ServiceFuture> + Send + Sync + 'static,
+{
+    type Error = S::Error;
+    type Response = S::Response;
+    type Future = S::Future;
+
+    fn call(&self, req: Request<Incoming>) -> Self::Future {
+        let request = self.activity.start_request();
+        let inner_clone = self.inner.clone();
+        Box::pin(async move {
+            let response = inner_clone.call(req).await?;
+            // The request is in flight until the response body is sent
+            Ok(response.map(|body| BoxBody::new(TrackedBody { inner: body, _request: request })))
+        })
+    }
+}
+
+// Marks a request as finished when dropped
+struct ActiveRequest(Arc<watch::Sender<usize>>);
+
+impl Drop for ActiveRequest {
+    fn drop(&mut self) {
+        self.0.send_modify(|n| *n -= 1);
+    }
+}
+
+struct TrackedBody {
+    inner: BoxBody<Bytes, Error>,
+    _request: ActiveRequest,
+}
+
+impl Body for TrackedBody {
+    type Data = Bytes;
+    type Error = Error;
+
+    fn poll_frame(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        Pin::new(&mut self.get_mut().inner).poll_frame(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
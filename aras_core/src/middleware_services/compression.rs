@@ -0,0 +1,424 @@
+use std::fmt::Debug;
+use std::io::{self, Write};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{ready, Context, Poll};
+
+use bytes::Bytes;
+use derive_more::derive::Constructor;
+use flate2::write::{GzEncoder, ZlibEncoder};
+use http::header::{
+    ACCEPT_ENCODING, CACHE_CONTROL, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE, ETAG, VARY,
+};
+use http::{HeaderMap, HeaderValue, StatusCode};
+use http_body_util::combinators::BoxBody;
+use http_body_util::BodyExt;
+use hyper::body::{Body, Frame, Incoming};
+use hyper::service::Service;
+use hyper::Request;
+
+use crate::error::{Error, Result};
+use crate::server::{CompressionConfig, ContentEncoding};
+use crate::types::{Response, ServiceFuture};
+
+// Favours speed, responses are compressed on the fly
+const BROTLI_QUALITY: u32 = 4;
+const BROTLI_WINDOW: u32 = 22;
+
+// Compresses response bodies with the best encoding the client accepts. Without
+// a config responses are sent as they are.
+#[derive(Debug, Clone)]
+pub struct Compression {
+    config: Option<Arc<CompressionConfig>>,
+}
+
+impl Compression {
+    pub fn new(config: Option<CompressionConfig>) -> Self {
+        Self { config: config.map(Arc::new) }
+    }
+
+    pub fn as_layer<S>(&self) -> impl Fn(S) -> CompressionLayer<S>
+    where
+        S: Service<Request<Incoming>, Response = Response, Error = Error, Future = ServiceFuture>
+            + Send
+            + Sync
+            + 'static,
+    {
+        let compression = self.clone();
+        move |inner: S| -> CompressionLayer<S> {
+            CompressionLayer::new(Arc::new(inner), compression.clone())
+        }
+    }
+}
+
+#[derive(Constructor, Debug, Clone)]
+pub struct CompressionLayer<S> {
+    inner: Arc<S>,
+    compression: Compression,
+}
+
+impl<S> Service<Request<Incoming>> for CompressionLayer<S>
+where
+    S: Service<Request<Incoming>, Response = Response, Error = Error, Future = ServiceFuture> + Send + Sync + 'static,
+{
+    type Error = S::Error;
+    type Response = S::Response;
+    type Future = S::Future;
+
+    fn call(&self, req: Request<Incoming>) -> Self::Future {
+        let Some(config) = self.compression.config.clone() else {
+            return self.inner.call(req);
+        };
+        let encoding = req
+            .headers()
+            .get(ACCEPT_ENCODING)
+            .and_then(|accept| accept.to_str().ok())
+            .and_then(|accept| negotiate(accept, &config.encodings));
+        let inner_clone = self.inner.clone();
+        Box::pin(async move {
+            let res = inner_clone.call(req).await?;
+            compress_response(res, &config, encoding)
+        })
+    }
+}
+
+// The accepted encoding with the highest q-value, ties go to the configured order.
+// Encodings the client didn't list are only accepted through `*`.
+fn negotiate(accept: &str, encodings: &[ContentEncoding]) -> Option<ContentEncoding> {
+    let mut best: Option<(f32, ContentEncoding)> = None;
+    for encoding in encodings {
+        let q = q_value(accept, encoding.as_str());
+        if q > 0.0 && best.is_none_or(|(best_q, _)| q > best_q) {
+            best = Some((q, *encoding));
+        };
+    }
+    best.map(|(_, encoding)| encoding)
+}
+
+pub(super) fn q_value(accept: &str, token: &str) -> f32 {
+    let mut wildcard = None;
+    for item in accept.split(',') {
+        let mut params = item.split(';');
+        let name = params.next().unwrap_or_default().trim();
+        let q = params
+            .filter_map(|param| param.split_once('='))
+            .find(|(key, _)| key.trim().eq_ignore_ascii_case("q"))
+            .map_or(Some(1.0), |(_, value)| value.trim().parse::<f32>().ok())
+            .unwrap_or(0.0);
+        if name.eq_ignore_ascii_case(token) {
+            return q;
+        };
+        if name == "*" {
+            wildcard = Some(q);
+        };
+    }
+    wildcard.unwrap_or(0.0)
+}
+
+// Responses without a body, partial content, already encoded or small ones and
+// excluded content types are sent as they are
+fn compressible(res: &Response, config: &CompressionConfig) -> bool {
+    let status = res.status();
+    let no_body = matches!(status, StatusCode::NO_CONTENT | StatusCode::NOT_MODIFIED);
+    if status.is_informational() || no_body || status == StatusCode::PARTIAL_CONTENT {
+        return false;
+    };
+    let headers = res.headers();
+    if headers.contains_key(CONTENT_ENCODING) || headers.contains_key(CONTENT_RANGE) {
+        return false;
+    };
+    let header = |name| headers.get(name).and_then(|value: &HeaderValue| value.to_str().ok());
+    if header(CACHE_CONTROL).is_some_and(|value| value.to_lowercase().contains("no-transform")) {
+        return false;
+    };
+    if header(CONTENT_LENGTH)
+        .and_then(|value| value.parse::<u64>().ok())
+        .is_some_and(|length| length < config.min_size)
+    {
+        return false;
+    };
+    let content_type = header(CONTENT_TYPE)
+        .map(|value| value.split(';').next().unwrap_or_default().trim().to_lowercase())
+        .unwrap_or_default();
+    !config
+        .excluded_content_types
+        .iter()
+        .any(|excluded| content_type.starts_with(&excluded.to_lowercase()))
+}
+
+fn compress_response(res: Response, config: &CompressionConfig, encoding: Option<ContentEncoding>) -> Result<Response> {
+    if !compressible(&res, config) {
+        return Ok(res);
+    };
+    let (mut parts, body) = res.into_parts();
+    // Caches have to tell compressed and uncompressed responses apart, even when this client got none
+    add_vary(&mut parts.headers);
+    let Some(encoding) = encoding else {
+        return Ok(hyper::Response::from_parts(parts, body));
+    };
+
+    let encoder = Encoder::new(encoding)?;
+    parts.headers.remove(CONTENT_LENGTH);
+    parts.headers.insert(CONTENT_ENCODING, HeaderValue::from_static(encoding.as_str()));
+    // The compressed body is no longer byte for byte the same
+    if let Some(etag) = parts.headers.get(ETAG).filter(|etag| !etag.as_bytes().starts_with(b"W/")) {
+        let weak = [b"W/", etag.as_bytes()].concat();
+        if let Ok(weak) = HeaderValue::from_bytes(&weak) {
+            parts.headers.insert(ETAG, weak);
+        };
+    };
+    let body = CompressedBody {
+        inner: body,
+        encoder: Some(encoder),
+        trailers: None,
+    };
+    Ok(hyper::Response::from_parts(parts, body.boxed()))
+}
+
+fn add_vary(headers: &mut HeaderMap) {
+    let varies = headers.get_all(VARY).iter().any(|value| {
+        value
+            .to_str()
+            .unwrap_or_default()
+            .split(',')
+            .any(|name| name.trim() == "*" || name.trim().eq_ignore_ascii_case("accept-encoding"))
+    });
+    if !varies {
+        headers.append(VARY, HeaderValue::from_static("Accept-Encoding"));
+    };
+}
+
+enum Encoder {
+    Gzip(GzEncoder<Vec<u8>>),
+    Deflate(ZlibEncoder<Vec<u8>>),
+    Brotli(Box<brotli::CompressorWriter<Vec<u8>>>),
+    Zstd(zstd::stream::write::Encoder<'static, Vec<u8>>),
+}
+
+impl Encoder {
+    fn new(encoding: ContentEncoding) -> io::Result<Self> {
+        Ok(match encoding {
+            ContentEncoding::Gzip => Self::Gzip(GzEncoder::new(Vec::new(), flate2::Compression::default())),
+            ContentEncoding::Deflate => Self::Deflate(ZlibEncoder::new(Vec::new(), flate2::Compression::default())),
+            ContentEncoding::Brotli => Self::Brotli(Box::new(brotli::CompressorWriter::new(
+                Vec::new(),
+                4096,
+                BROTLI_QUALITY,
+                BROTLI_WINDOW,
+            ))),
+            ContentEncoding::Zstd => {
+                Self::Zstd(zstd::stream::write::Encoder::new(Vec::new(), zstd::DEFAULT_COMPRESSION_LEVEL)?)
+            }
+        })
+    }
+
+    // Flushes after every chunk, so the client can decode everything sent so far.
+    // Streamed responses like server-sent events would be held back otherwise.
+    fn compress(&mut self, data: &[u8]) -> io::Result<Bytes> {
+        let output = match self {
+            Self::Gzip(encoder) => {
+                encoder.write_all(data)?;
+                encoder.flush()?;
+                encoder.get_mut()
+            }
+            Self::Deflate(encoder) => {
+                encoder.write_all(data)?;
+                encoder.flush()?;
+                encoder.get_mut()
+            }
+            Self::Brotli(encoder) => {
+                encoder.write_all(data)?;
+                encoder.flush()?;
+                encoder.get_mut()
+            }
+            Self::Zstd(encoder) => {
+                encoder.write_all(data)?;
+                encoder.flush()?;
+                encoder.get_mut()
+            }
+        };
+        Ok(Bytes::from(std::mem::take(output)))
+    }
+
+    fn finish(self) -> io::Result<Bytes> {
+        let output = match self {
+            Self::Gzip(encoder) => encoder.finish()?,
+            Self::Deflate(encoder) => encoder.finish()?,
+            Self::Brotli(encoder) => encoder.into_inner(),
+            Self::Zstd(encoder) => encoder.finish()?,
+        };
+        Ok(Bytes::from(output))
+    }
+}
+
+struct CompressedBody {
+    inner: BoxBody<Bytes, Error>,
+    // Taken once the body ended
+    encoder: Option<Encoder>,
+    // Sent after the end of the compressed data
+    trailers: Option<HeaderMap>,
+}
+
+impl Body for CompressedBody {
+    type Data = Bytes;
+    type Error = Error;
+
+    fn poll_frame(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<std::result::Result<Frame<Bytes>, Error>>> {
+        let this = self.get_mut();
+        loop {
+            let Some(encoder) = this.encoder.as_mut() else {
+                return Poll::Ready(this.trailers.take().map(|trailers| Ok(Frame::trailers(trailers))));
+            };
+            let compressed = match ready!(Pin::new(&mut this.inner).poll_frame(cx)) {
+                Some(Ok(frame)) => match frame.into_data() {
+                    Ok(data) if data.is_empty() => continue,
+                    Ok(data) => encoder.compress(&data),
+                    Err(frame) => {
+                        this.trailers = frame.into_trailers().ok();
+                        this.encoder.take().map_or(Ok(Bytes::new()), Encoder::finish)
+                    }
+                },
+                Some(Err(e)) => return Poll::Ready(Some(Err(e))),
+                None => this.encoder.take().map_or(Ok(Bytes::new()), Encoder::finish),
+            };
+            match compressed {
+                Ok(data) if data.is_empty() => continue,
+                Ok(data) => return Poll::Ready(Some(Ok(Frame::data(data)))),
+                Err(e) => return Poll::Ready(Some(Err(e.into()))),
+            };
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.encoder.is_none() && self.trailers.is_none()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+
+    use bytes::Bytes;
+    use futures::StreamExt;
+    use http_body_util::{BodyExt, Full, StreamBody};
+    use hyper::body::Frame;
+
+    use super::{compress_response, negotiate};
+    use crate::error::Error;
+    use crate::server::{CompressionConfig, ContentEncoding};
+    use crate::types::Response;
+
+    fn response(content_type: &str, body: &'static str) -> Response {
+        hyper::Response::builder()
+            .header("content-type", content_type)
+            .header("content-length", body.len())
+            .header("etag", "\"v1\"")
+            .body(Full::new(Bytes::from(body)).map_err(|never| match never {}).boxed())
+            .unwrap()
+    }
+
+    #[test]
+    fn test_negotiate() {
+        let all = CompressionConfig::default().encodings;
+        assert!(negotiate("gzip, deflate", &all) == Some(ContentEncoding::Gzip));
+        assert!(negotiate("gzip, br, zstd", &all) == Some(ContentEncoding::Brotli));
+        assert!(negotiate("gzip;q=1.0, br;q=0.5", &all) == Some(ContentEncoding::Gzip));
+        assert!(negotiate("*;q=0.1, zstd;q=0.2", &all) == Some(ContentEncoding::Zstd));
+        assert!(negotiate("br;q=0, *", &all) == Some(ContentEncoding::Zstd));
+        assert!(negotiate("identity", &all).is_none());
+        assert!(negotiate("GZIP", &[ContentEncoding::Zstd]).is_none());
+    }
+
+    #[test]
+    fn test_skipped_responses() {
+        let config = CompressionConfig::default();
+        let small = compress_response(response("application/json", "{}"), &config, Some(ContentEncoding::Gzip)).unwrap();
+        assert!(!small.headers().contains_key("content-encoding"));
+        assert!(!small.headers().contains_key("vary"));
+
+        let body: &str = "x".repeat(1000).leak();
+        let image = compress_response(response("image/png", body), &config, Some(ContentEncoding::Gzip)).unwrap();
+        assert!(!image.headers().contains_key("content-encoding"));
+
+        let not_accepted = compress_response(response("text/plain; charset=utf-8", body), &config, None).unwrap();
+        assert!(!not_accepted.headers().contains_key("content-encoding"));
+        assert!(not_accepted.headers()["vary"] == "Accept-Encoding");
+    }
+
+    #[tokio::test]
+    async fn test_compressed_body() {
+        let config = CompressionConfig::default();
+        let body: &str = "hello world ".repeat(100).leak();
+        let res = compress_response(response("text/plain", body), &config, Some(ContentEncoding::Gzip)).unwrap();
+        assert!(res.headers()["content-encoding"] == "gzip");
+        assert!(res.headers()["etag"] == "W/\"v1\"");
+        assert!(!res.headers().contains_key("content-length"));
+
+        let compressed = res.into_body().collect().await.unwrap().to_bytes();
+        let mut decoded = String::new();
+        flate2::read::GzDecoder::new(&compressed[..]).read_to_string(&mut decoded).unwrap();
+        assert!(decoded == body);
+    }
+
+    #[tokio::test]
+    async fn test_streamed_chunks_are_flushed() {
+        for encoding in [ContentEncoding::Gzip, ContentEncoding::Deflate, ContentEncoding::Brotli, ContentEncoding::Zstd] {
+            let chunks = futures::stream::iter(["data: one\n\n", "data: two\n\n"])
+                .map(|chunk| Ok::<_, Error>(Frame::data(Bytes::from(chunk))));
+            let res = hyper::Response::builder()
+                .header("content-type", "text/event-stream")
+                .body(BodyExt::boxed(StreamBody::new(chunks)))
+                .unwrap();
+            let mut body = compress_response(res, &CompressionConfig::default(), Some(encoding)).unwrap().into_body();
+
+            // Each chunk can be decoded as soon as it arrives
+            let first = body.frame().await.unwrap().unwrap().into_data().unwrap();
+            let mut decoded = Vec::new();
+            match encoding {
+                ContentEncoding::Gzip => {
+                    let mut decoder = flate2::write::GzDecoder::new(&mut decoded);
+                    decoder.write_all(&first).unwrap();
+                    decoder.flush().unwrap();
+                }
+                ContentEncoding::Deflate => {
+                    let mut decoder = flate2::write::ZlibDecoder::new(&mut decoded);
+                    decoder.write_all(&first).unwrap();
+                    decoder.flush().unwrap();
+                }
+                ContentEncoding::Brotli => {
+                    let mut decoder = brotli::DecompressorWriter::new(&mut decoded, 4096);
+                    decoder.write_all(&first).unwrap();
+                    decoder.flush().unwrap();
+                }
+                ContentEncoding::Zstd => {
+                    let mut decoder = zstd::stream::write::Decoder::new(&mut decoded).unwrap();
+                    decoder.write_all(&first).unwrap();
+                    decoder.flush().unwrap();
+                }
+            };
+            assert!(decoded == b"data: one\n\n");
+
+            let rest = body.collect().await.unwrap().to_bytes();
+            let all = [first, rest].concat();
+            let decoded = match encoding {
+                ContentEncoding::Gzip => {
+                    let mut decoded = Vec::new();
+                    flate2::read::GzDecoder::new(&all[..]).read_to_end(&mut decoded).unwrap();
+                    decoded
+                }
+                ContentEncoding::Deflate => {
+                    let mut decoded = Vec::new();
+                    flate2::read::ZlibDecoder::new(&all[..]).read_to_end(&mut decoded).unwrap();
+                    decoded
+                }
+                ContentEncoding::Brotli => {
+                    let mut decoded = Vec::new();
+                    brotli::Decompressor::new(&all[..], 4096).read_to_end(&mut decoded).unwrap();
+                    decoded
+                }
+                ContentEncoding::Zstd => zstd::decode_all(&all[..]).unwrap(),
+            };
+            assert!(decoded == b"data: one\n\ndata: two\n\n");
+        }
+    }
+}
@@ -1,31 +1,126 @@
 use std::fmt::Debug;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::task::{Context, Poll};
 
+use bytes::Bytes;
 use derive_more::derive::Constructor;
-use http_body_util::{BodyExt, Full};
-use hyper::body::Incoming;
+use http_body_util::combinators::BoxBody;
+use http_body_util::BodyExt;
+use hyper::body::{Body, Frame, Incoming, SizeHint};
 use hyper::service::Service;
 use hyper::Request;
-use tokio::sync::Semaphore;
+use log::{debug, warn};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
 
 use crate::error::{Error, Result};
-use crate::types::{Response, ServiceFuture};
+use crate::middleware_services::RequestId;
+use crate::server::ConcurrencyQueue;
+use crate::types::{text_response, Response, ServiceFuture};
+
+// Depth and outcome counters of the queue in front of the concurrency limit
+#[derive(Debug, Clone, Default)]
+pub struct QueueMetrics(Arc<QueueCounters>);
+
+#[derive(Debug, Default)]
+struct QueueCounters {
+    depth: AtomicUsize,
+    queued: AtomicU64,
+    rejected: AtomicU64,
+    timed_out: AtomicU64,
+}
+
+impl QueueMetrics {
+    // Requests waiting right now
+    pub fn depth(&self) -> usize {
+        self.0.depth.load(Ordering::Relaxed)
+    }
+
+    // Requests that had to wait for a slot
+    pub fn queued_total(&self) -> u64 {
+        self.0.queued.load(Ordering::Relaxed)
+    }
+
+    // Requests refused because the queue was full
+    pub fn rejected_total(&self) -> u64 {
+        self.0.rejected.load(Ordering::Relaxed)
+    }
+
+    // Requests refused after waiting too long
+    pub fn timed_out_total(&self) -> u64 {
+        self.0.timed_out.load(Ordering::Relaxed)
+    }
+
+    fn enter(&self, max_length: usize) -> Option<QueueSlot> {
+        let entered = self
+            .0
+            .depth
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |depth| (depth < max_length).then_some(depth + 1))
+            .is_ok();
+        if !entered {
+            self.0.rejected.fetch_add(1, Ordering::Relaxed);
+            return None;
+        };
+        self.0.queued.fetch_add(1, Ordering::Relaxed);
+        Some(QueueSlot(self.0.clone()))
+    }
+
+    fn record_timeout(&self) {
+        self.0.timed_out.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+// Place in the queue, given up when the request stops waiting or is dropped
+struct QueueSlot(Arc<QueueCounters>);
+
+impl Drop for QueueSlot {
+    fn drop(&mut self) {
+        self.0.depth.fetch_sub(1, Ordering::Relaxed);
+    }
+}
 
 #[derive(Constructor, Debug, Clone)]
 pub struct ConcurrencyLimit {
     semaphore: Arc<Semaphore>,
+    queue: ConcurrencyQueue,
+    metrics: QueueMetrics,
 }
 
 impl ConcurrencyLimit {
-    pub fn as_layer<S>(self) -> impl Fn(S) -> ConcurrencyLimitLayer<S>
+    pub fn as_layer<S>(&self) -> impl Fn(S) -> ConcurrencyLimitLayer<S>
     where
         S: Service<Request<Incoming>, Response = Response, Error = Error, Future = ServiceFuture>
             + Send
             + Sync
             + 'static,
     {
+        let limit = self.clone();
         move |inner: S| -> ConcurrencyLimitLayer<S> {
-            ConcurrencyLimitLayer::new(Arc::new(inner), self.semaphore.clone())
+            ConcurrencyLimitLayer::new(Arc::new(inner), limit.clone())
+        }
+    }
+
+    // Take a free slot, or wait in the queue for one. `None` when the queue is
+    // full or no slot came free in time.
+    async fn acquire(&self, request_id: &RequestId) -> Option<OwnedSemaphorePermit> {
+        if let Ok(permit) = self.semaphore.clone().try_acquire_owned() {
+            return Some(permit);
+        };
+        let Some(_slot) = self.metrics.enter(self.queue.max_length) else {
+            warn!("[{request_id}] Concurrency limit reached and queue is full, refusing request");
+            return None;
+        };
+        debug!("Concurrency limit reached, {} request(s) waiting", self.metrics.depth());
+
+        // Waiting requests are served in order, the semaphore is fair
+        match tokio::time::timeout(self.queue.max_wait, self.semaphore.clone().acquire_owned()).await {
+            Ok(permit) => Some(permit.expect("Semaphore in `ConcurrencyLimit` closed, this should never happen!")),
+            Err(_) => {
+                warn!("[{request_id}] Request waited too long for the concurrency limit, refusing request");
+                self.metrics.record_timeout();
+                None
+            }
         }
     }
 }
@@ -33,7 +128,7 @@ impl ConcurrencyLimit {
 #[derive(Constructor, Debug, Clone)]
 pub struct ConcurrencyLimitLayer<S> {
     inner: Arc<S>,
-    semaphore: Arc<Semaphore>,
+    limit: ConcurrencyLimit,
 }
 
 impl<S> Service<Request<Incoming>> for ConcurrencyLimitLayer<S>
@@ -46,29 +141,126 @@ where
 
     fn call(&self, req: Request<Incoming>) -> Self::Future {
         let inner_clone = self.inner.clone();
-        let semaphore_clone = self.semaphore.clone();
+        let limit = self.limit.clone();
+        let request_id = RequestId::of(&req);
         Box::pin(async move {
-            if semaphore_clone.available_permits() == 0 {
-                return send_503().await;
+            let Some(permit) = limit.acquire(&request_id).await else {
+                return send_503(limit.queue.retry_after()).await;
             };
-            let _permit = semaphore_clone
-                .acquire()
-                .await
-                .expect("Semaphore in `ConcurrencyLimit` closed, this should never happen!");
-            inner_clone.call(req).await
+            let response = inner_clone.call(req).await?;
+            // The slot is taken until the response body is sent
+            Ok(response.map(|body| LimitedBody { inner: body, _permit: permit }.boxed()))
         })
     }
 }
 
-async fn send_503() -> Result<Response> {
-    let body_text = "Server busy";
-    let body = Full::new(body_text.as_bytes().to_vec().into())
-        .map_err(|never| match never {})
-        .boxed();
-    let response = hyper::Response::builder()
+struct LimitedBody {
+    inner: BoxBody<Bytes, Error>,
+    _permit: OwnedSemaphorePermit,
+}
+
+impl Body for LimitedBody {
+    type Data = Bytes;
+    type Error = Error;
+
+    fn poll_frame(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<std::result::Result<Frame<Bytes>, Error>>> {
+        Pin::new(&mut self.get_mut().inner).poll_frame(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+async fn send_503(retry_after: u64) -> Result<Response> {
+    let builder = hyper::Response::builder()
         .status(503)
-        .header(hyper::header::CONTENT_LENGTH, body_text.len())
-        .header(hyper::header::CONTENT_TYPE, "text/plain")
-        .body(body);
-    Ok(response?)
+        .header(hyper::header::RETRY_AFTER, retry_after);
+    text_response(builder, "Server busy")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use http_body_util::{BodyExt, Empty};
+    use tokio::sync::Semaphore;
+
+    use super::{ConcurrencyLimit, LimitedBody, QueueMetrics};
+    use crate::middleware_services::RequestId;
+    use crate::server::ConcurrencyQueue;
+
+    fn limit(permits: usize, max_length: usize, max_wait: Duration) -> ConcurrencyLimit {
+        let queue = ConcurrencyQueue { max_length, max_wait };
+        ConcurrencyLimit::new(Arc::new(Semaphore::new(permits)), queue, QueueMetrics::default())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_queued_request_gets_freed_slot() {
+        let limit = limit(1, 1, Duration::from_secs(5));
+        let running = limit.acquire(&RequestId::default()).await.unwrap();
+
+        let waiting = limit.clone();
+        let queued = tokio::spawn(async move {
+            let start = tokio::time::Instant::now();
+            waiting.acquire(&RequestId::default()).await.map(|_| start.elapsed())
+        });
+        tokio::time::sleep(Duration::from_secs(2)).await;
+        assert!(limit.metrics.depth() == 1);
+
+        drop(running);
+        assert!(queued.await.unwrap() == Some(Duration::from_secs(2)));
+        assert!(limit.metrics.depth() == 0);
+        assert!(limit.metrics.queued_total() == 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_full_queue_refuses() {
+        let limit = limit(1, 1, Duration::from_secs(5));
+        let _running = limit.acquire(&RequestId::default()).await.unwrap();
+
+        let waiting = limit.clone();
+        let _queued = tokio::spawn(async move { waiting.acquire(&RequestId::default()).await.is_some() });
+        tokio::time::sleep(Duration::from_millis(1)).await;
+
+        assert!(limit.acquire(&RequestId::default()).await.is_none());
+        assert!(limit.metrics.rejected_total() == 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_queue_wait_times_out() {
+        let limit = limit(1, 1, Duration::from_secs(5));
+        let _running = limit.acquire(&RequestId::default()).await.unwrap();
+
+        let start = tokio::time::Instant::now();
+        assert!(limit.acquire(&RequestId::default()).await.is_none());
+        assert!(start.elapsed() == Duration::from_secs(5));
+        assert!(limit.metrics.timed_out_total() == 1);
+        assert!(limit.metrics.depth() == 0);
+    }
+
+    #[tokio::test]
+    async fn test_slot_taken_until_body_is_sent() {
+        let limit = limit(1, 0, Duration::from_secs(5));
+        let permit = limit.acquire(&RequestId::default()).await.unwrap();
+        let inner = Empty::new().map_err(|never| match never {}).boxed();
+        let body = LimitedBody { inner, _permit: permit };
+        assert!(limit.acquire(&RequestId::default()).await.is_none());
+
+        _ = body.collect().await.unwrap();
+        assert!(limit.acquire(&RequestId::default()).await.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_without_queue_refuses_immediately() {
+        let limit = limit(1, 0, Duration::from_secs(5));
+        let _running = limit.acquire(&RequestId::default()).await.unwrap();
+        assert!(limit.acquire(&RequestId::default()).await.is_none());
+        assert!(limit.metrics.rejected_total() == 1);
+    }
 }
@@ -1,7 +1,20 @@
-mod logger;
+mod access_log;
+mod compression;
 mod concurrency_limiter;
+mod rate_limiter;
 mod max_size;
+mod activity;
+mod request_metrics;
+mod request_id;
+mod static_files;
 
-pub use logger::Logger;
-pub use concurrency_limiter::ConcurrencyLimit;
-pub use max_size::ContentLengthLimit;
\ No newline at end of file
+pub use access_log::AccessLog;
+pub use compression::Compression;
+pub use concurrency_limiter::{ConcurrencyLimit, QueueMetrics};
+pub use rate_limiter::RateLimiter;
+pub use max_size::ContentLengthLimit;
+pub use activity::ConnectionActivity;
+pub use request_metrics::RequestMetrics;
+pub use request_id::{RequestId, RequestIds};
+pub use static_files::StaticFiles;
+pub(crate) use static_files::file_body;
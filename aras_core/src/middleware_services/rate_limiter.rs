@@ -0,0 +1,259 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fmt::Debug;
+use std::net::{IpAddr, Ipv6Addr};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use derive_more::derive::Constructor;
+use hyper::body::Incoming;
+use hyper::service::Service;
+use hyper::Request;
+use ipnet::IpNet;
+use log::warn;
+use tokio::time::Instant;
+
+use crate::error::{Error, Result};
+use crate::middleware_services::RequestId;
+use crate::server::forwarded::{contains, Forwarded};
+use crate::server::RateLimit;
+use crate::types::{text_response, Response, ServiceFuture};
+
+// Token buckets per client IP, or per /64 for IPv6 clients, shared by all connections.
+// Without settings requests are not limited.
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    settings: Option<Arc<RateLimit>>,
+    forwarded_allow_ips: Arc<Vec<IpNet>>,
+    buckets: Arc<Mutex<Buckets>>,
+}
+
+#[derive(Debug)]
+struct Buckets {
+    clients: HashMap<IpAddr, Bucket>,
+    // The clients ordered by their last request, longest idle first
+    by_update: BTreeSet<(Instant, IpAddr)>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(settings: Option<RateLimit>, forwarded_allow_ips: Vec<IpNet>) -> Self {
+        Self {
+            settings: settings.map(Arc::new),
+            forwarded_allow_ips: Arc::new(forwarded_allow_ips),
+            buckets: Arc::new(Mutex::new(Buckets {
+                clients: HashMap::new(),
+                by_update: BTreeSet::new(),
+            })),
+        }
+    }
+
+    // Connections without a peer address, like unix domain sockets, are not limited
+    pub fn as_layer<S>(&self, peer: Option<IpAddr>) -> impl Fn(S) -> RateLimitLayer<S>
+    where
+        S: Service<Request<Incoming>, Response = Response, Error = Error, Future = ServiceFuture>
+            + Send
+            + Sync
+            + 'static,
+    {
+        let limiter = self.clone();
+        move |inner: S| -> RateLimitLayer<S> {
+            RateLimitLayer::new(Arc::new(inner), limiter.clone(), peer)
+        }
+    }
+
+    // Take a token for the client, or the time until the next one is available
+    fn check(&self, client: IpAddr, now: Instant) -> std::result::Result<(), Duration> {
+        let Some(settings) = &self.settings else {
+            return Ok(());
+        };
+        let client = client.to_canonical();
+        if contains(&settings.allow_ips, client) {
+            return Ok(());
+        };
+        let client = bucket_key(client);
+        let burst = f64::from(settings.burst.max(1));
+        let rate = settings.per_second;
+        let mut buckets = self.buckets.lock().expect("Rate limiter lock poisoned");
+        let Buckets { clients, by_update } = &mut *buckets;
+
+        // A bucket idle long enough to refill completely is the same as a new one, dropping it loses nothing
+        let refill = Duration::try_from_secs_f64(burst / rate).unwrap_or(Duration::MAX);
+        while let Some(&(updated, idle)) = by_update.first() {
+            if now.duration_since(updated) < refill {
+                break;
+            };
+            by_update.pop_first();
+            clients.remove(&idle);
+        }
+        if clients.len() >= settings.max_clients && !clients.contains_key(&client) {
+            if let Some((_, oldest)) = by_update.pop_first() {
+                clients.remove(&oldest);
+            };
+        };
+
+        let bucket = clients.entry(client).or_insert(Bucket { tokens: burst, updated: now });
+        by_update.remove(&(bucket.updated, client));
+        by_update.insert((now, client));
+        bucket.tokens = (bucket.tokens + now.duration_since(bucket.updated).as_secs_f64() * rate).min(burst);
+        bucket.updated = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::try_from_secs_f64((1.0 - bucket.tokens) / rate).unwrap_or(Duration::MAX))
+        }
+    }
+}
+
+// IPv6 clients usually get a whole /64, limiting single addresses in it is easily sidestepped
+fn bucket_key(client: IpAddr) -> IpAddr {
+    match client {
+        IpAddr::V6(ip) => IpAddr::V6(Ipv6Addr::from(u128::from(ip) & !(u128::MAX >> 64))),
+        ip => ip,
+    }
+}
+
+#[derive(Constructor, Debug, Clone)]
+pub struct RateLimitLayer<S> {
+    inner: Arc<S>,
+    limiter: RateLimiter,
+    peer: Option<IpAddr>,
+}
+
+impl<S> Service<Request<Incoming>> for RateLimitLayer<S>
+where
+    S: Service<Request<Incoming>, Response = Response, Error = Error, Future = ServiceFuture> + Send + Sync + 'static,
+{
+    type Error = S::Error;
+    type Response = S::Response;
+    type Future = S::Future;
+
+    fn call(&self, req: Request<Incoming>) -> Self::Future {
+        let inner_clone = self.inner.clone();
+        let limited = self.peer.filter(|_| self.limiter.settings.is_some()).and_then(|peer| {
+            let forwarded = Forwarded::from_headers(Some(peer), req.headers(), &self.limiter.forwarded_allow_ips);
+            let client = forwarded.client_ip().unwrap_or(peer);
+            self.limiter.check(client, Instant::now()).err().map(|retry_after| (client, retry_after))
+        });
+
+        let request_id = RequestId::of(&req);
+        Box::pin(async move {
+            if let Some((client, retry_after)) = limited {
+                warn!("[{request_id}] Rate limit exceeded for client {client}");
+                return send_429(retry_after).await;
+            };
+            inner_clone.call(req).await
+        })
+    }
+}
+
+async fn send_429(retry_after: Duration) -> Result<Response> {
+    let builder = hyper::Response::builder()
+        .status(429)
+        .header(hyper::header::RETRY_AFTER, retry_after.as_secs_f64().ceil().max(1.0) as u64);
+    text_response(builder, "Too many requests")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+    use std::time::Duration;
+
+    use tokio::time::Instant;
+
+    use super::RateLimiter;
+    use crate::server::forwarded::parse_networks;
+    use crate::server::RateLimit;
+
+    fn ip(value: &str) -> IpAddr {
+        value.parse().unwrap()
+    }
+
+    #[test]
+    fn test_burst_then_refill() {
+        let limiter = RateLimiter::new(Some(RateLimit::new(2.0, 3).unwrap()), Vec::new());
+        let start = Instant::now();
+        for _ in 0..3 {
+            assert!(limiter.check(ip("1.1.1.1"), start).is_ok());
+        }
+        assert!(limiter.check(ip("1.1.1.1"), start) == Err(Duration::from_millis(500)));
+        // Other clients have their own bucket
+        assert!(limiter.check(ip("2.2.2.2"), start).is_ok());
+
+        assert!(limiter.check(ip("1.1.1.1"), start + Duration::from_millis(500)).is_ok());
+        assert!(limiter.check(ip("1.1.1.1"), start + Duration::from_millis(500)).is_err());
+    }
+
+    #[test]
+    fn test_allowed_clients_are_not_limited() {
+        let mut settings = RateLimit::new(1.0, 1).unwrap();
+        settings.allow_ips = parse_networks("10.0.0.0/8").unwrap();
+        let limiter = RateLimiter::new(Some(settings), Vec::new());
+        let now = Instant::now();
+        for _ in 0..10 {
+            assert!(limiter.check(ip("10.0.0.1"), now).is_ok());
+        }
+    }
+
+    #[test]
+    fn test_idle_clients_are_evicted() {
+        let limiter = RateLimiter::new(Some(RateLimit::new(1.0, 1).unwrap()), Vec::new());
+        let start = Instant::now();
+        assert!(limiter.check(ip("1.1.1.1"), start).is_ok());
+        assert!(limiter.check(ip("2.2.2.2"), start + Duration::from_millis(9500)).is_ok());
+
+        // Only the bucket that refilled completely is dropped
+        assert!(limiter.check(ip("3.3.3.3"), start + Duration::from_secs(10)).is_ok());
+        let buckets = limiter.buckets.lock().unwrap();
+        assert!(!buckets.clients.contains_key(&ip("1.1.1.1")));
+        assert!(buckets.clients.contains_key(&ip("2.2.2.2")));
+    }
+
+    #[test]
+    fn test_tracked_clients_are_bounded() {
+        let mut settings = RateLimit::new(1.0, 1).unwrap();
+        settings.max_clients = 2;
+        let limiter = RateLimiter::new(Some(settings), Vec::new());
+        let start = Instant::now();
+        for (n, client) in ["1.1.1.1", "2.2.2.2", "3.3.3.3"].iter().enumerate() {
+            assert!(limiter.check(ip(client), start + Duration::from_millis(n as u64)).is_ok());
+        }
+
+        let buckets = limiter.buckets.lock().unwrap();
+        assert!(buckets.clients.len() == 2);
+        assert!(buckets.by_update.len() == 2);
+        assert!(!buckets.clients.contains_key(&ip("1.1.1.1")));
+    }
+
+    #[test]
+    fn test_ipv6_clients_share_their_64() {
+        let limiter = RateLimiter::new(Some(RateLimit::new(1.0, 1).unwrap()), Vec::new());
+        let now = Instant::now();
+        assert!(limiter.check(ip("2001:db8:1:2::1"), now).is_ok());
+        assert!(limiter.check(ip("2001:db8:1:2:ffff::2"), now).is_err());
+        assert!(limiter.check(ip("2001:db8:1:3::1"), now).is_ok());
+        // Mapped IPv4 addresses are still limited on their own
+        assert!(limiter.check(ip("::ffff:1.1.1.1"), now).is_ok());
+        assert!(limiter.check(ip("::ffff:1.1.1.2"), now).is_ok());
+    }
+
+    #[test]
+    fn test_tiny_rate() {
+        let limiter = RateLimiter::new(Some(RateLimit::new(1e-300, 1).unwrap()), Vec::new());
+        let now = Instant::now();
+        assert!(limiter.check(ip("1.1.1.1"), now).is_ok());
+        assert!(limiter.check(ip("1.1.1.1"), now) == Err(Duration::MAX));
+    }
+
+    #[test]
+    fn test_rate_must_be_positive() {
+        for rate in [0.0, -1.0, f64::NAN, f64::INFINITY] {
+            assert!(RateLimit::new(rate, 1).is_err());
+        }
+    }
+}
@@ -0,0 +1,174 @@
+use std::fmt::Debug;
+use std::hash::{BuildHasher, RandomState};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use derive_more::derive::{Constructor, Display};
+use http::HeaderValue;
+use hyper::body::Incoming;
+use hyper::service::Service;
+use hyper::Request;
+
+use crate::error::Error;
+use crate::types::{Response, ServiceFuture};
+
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+// Identifies a request in the logs, the response headers and the ASGI scope
+#[derive(Debug, Clone, PartialEq, Eq, Display)]
+pub struct RequestId(Arc<str>);
+
+// Stands in for the id of requests that didn't pass `RequestIds`
+impl Default for RequestId {
+    fn default() -> Self {
+        Self(Arc::from("-"))
+    }
+}
+
+impl RequestId {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    // The id assigned by `RequestIds`, the default for requests that didn't pass it
+    pub fn of<B>(req: &Request<B>) -> Self {
+        req.extensions()
+            .get::<RequestId>()
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    // Ids from clients or proxies are kept when they are short and safe to log
+    fn parse(value: &HeaderValue) -> Option<Self> {
+        let value = value.to_str().ok()?;
+        let valid = !value.is_empty()
+            && value.len() <= 128
+            && value.bytes().all(|b| b.is_ascii_alphanumeric() || b"-_.:+/=".contains(&b));
+        valid.then(|| Self(Arc::from(value)))
+    }
+}
+
+// Assigns every request an id, unless a valid one is passed in the `X-Request-ID` header.
+// Generated ids are a random per process prefix followed by a counter.
+#[derive(Debug, Clone)]
+pub struct RequestIds(Arc<Generator>);
+
+#[derive(Debug)]
+struct Generator {
+    prefix: u64,
+    counter: AtomicU64,
+}
+
+impl Default for RequestIds {
+    fn default() -> Self {
+        Self(Arc::new(Generator {
+            prefix: RandomState::new().hash_one(std::process::id()),
+            counter: AtomicU64::new(0),
+        }))
+    }
+}
+
+impl RequestIds {
+    pub fn as_layer<S>(&self) -> impl Fn(S) -> RequestIdLayer<S>
+    where
+        S: Service<Request<Incoming>, Response = Response, Error = Error, Future = ServiceFuture>
+            + Send
+            + Sync
+            + 'static,
+    {
+        let ids = self.clone();
+        move |inner: S| -> RequestIdLayer<S> {
+            RequestIdLayer::new(Arc::new(inner), ids.clone())
+        }
+    }
+
+    fn next(&self) -> RequestId {
+        let count = self.0.counter.fetch_add(1, Ordering::Relaxed);
+        RequestId(Arc::from(format!("{:016x}{count:016x}", self.0.prefix)))
+    }
+
+    fn assign<B>(&self, req: &mut Request<B>) -> RequestId {
+        let id = req
+            .headers()
+            .get(REQUEST_ID_HEADER)
+            .and_then(RequestId::parse)
+            .unwrap_or_else(|| self.next());
+        // Ids are validated or generated, so always a valid header value
+        let value = HeaderValue::from_str(id.as_str()).expect("Request id is a valid header value");
+        req.headers_mut().insert(REQUEST_ID_HEADER, value);
+        req.extensions_mut().insert(id.clone());
+        id
+    }
+}
+
+#[derive(Constructor, Debug, Clone)]
+pub struct RequestIdLayer<S> {
+    inner: Arc<S>,
+    ids: RequestIds,
+}
+
+impl<S> Service<Request<Incoming>> for RequestIdLayer<S>
+where
+    S: Service<Request<Incoming>, Response = Response, Error = Error, Future = ServiceFuture> + Send + Sync + 'static,
+{
+    type Error = S::Error;
+    type Response = S::Response;
+    type Future = S::Future;
+
+    fn call(&self, mut req: Request<Incoming>) -> Self::Future {
+        let id = self.ids.assign(&mut req);
+        let inner_clone = self.inner.clone();
+        Box::pin(async move {
+            let mut res = inner_clone.call(req).await?;
+            // Applications may set their own
+            if let Ok(value) = HeaderValue::from_str(id.as_str()) {
+                res.headers_mut().entry(REQUEST_ID_HEADER).or_insert(value);
+            };
+            Ok(res)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RequestId, RequestIds, REQUEST_ID_HEADER};
+
+    fn request(id: Option<&str>) -> http::Request<()> {
+        let mut builder = http::Request::builder();
+        if let Some(id) = id {
+            builder = builder.header(REQUEST_ID_HEADER, id);
+        };
+        builder.body(()).unwrap()
+    }
+
+    #[test]
+    fn test_generated_ids_are_unique() {
+        let ids = RequestIds::default();
+        let mut first = request(None);
+        let mut second = request(None);
+        let first_id = ids.assign(&mut first);
+        let second_id = ids.assign(&mut second);
+
+        assert!(first_id != second_id);
+        assert!(first_id.as_str().len() == 32);
+        assert!(RequestId::of(&first) == first_id);
+        assert!(first.headers()[REQUEST_ID_HEADER] == first_id.as_str());
+    }
+
+    #[test]
+    fn test_incoming_id_is_kept_when_valid() {
+        let ids = RequestIds::default();
+        let mut req = request(Some("abc-123"));
+        assert!(ids.assign(&mut req).as_str() == "abc-123");
+
+        let mut req = request(Some("no spaces or \"quotes\""));
+        assert!(ids.assign(&mut req).as_str() != "no spaces or \"quotes\"");
+        let mut req = request(Some(&"x".repeat(129)));
+        assert!(ids.assign(&mut req).as_str().len() == 32);
+    }
+
+    #[test]
+    fn test_unassigned_request() {
+        assert!(RequestId::of(&request(None)).as_str() == "-");
+    }
+}
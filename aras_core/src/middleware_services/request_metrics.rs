@@ -0,0 +1,93 @@
+use std::fmt::Debug;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use bytes::Bytes;
+use derive_more::derive::Constructor;
+use http_body_util::combinators::BoxBody;
+use http_body_util::BodyExt;
+use hyper::body::{Body, Frame, Incoming, SizeHint};
+use hyper::service::Service;
+use hyper::Request;
+
+use crate::error::Error;
+use crate::server::metrics::{metrics_response, RequestTimer};
+use crate::server::Metrics;
+use crate::types::{Response, ServiceFuture};
+
+// Counts requests and their duration. With a path the metrics are served on it as well,
+// scrapes are not counted.
+#[derive(Constructor, Debug, Clone)]
+pub struct RequestMetrics {
+    metrics: Metrics,
+    path: Option<Arc<String>>,
+}
+
+impl RequestMetrics {
+    pub fn as_layer<S>(&self) -> impl Fn(S) -> RequestMetricsLayer<S>
+    where
+        S: Service<Request<Incoming>, Response = Response, Error = Error, Future = ServiceFuture>
+            + Send
+            + Sync
+            + 'static,
+    {
+        let request_metrics = self.clone();
+        move |inner: S| -> RequestMetricsLayer<S> {
+            RequestMetricsLayer::new(Arc::new(inner), request_metrics.clone())
+        }
+    }
+}
+
+#[derive(Constructor, Debug, Clone)]
+pub struct RequestMetricsLayer<S> {
+    inner: Arc<S>,
+    request_metrics: RequestMetrics,
+}
+
+impl<S> Service<Request<Incoming>> for RequestMetricsLayer<S>
+where
+    S: Service<Request<Incoming>, Response = Response, Error = Error, Future = ServiceFuture> + Send + Sync + 'static,
+{
+    type Error = S::Error;
+    type Response = S::Response;
+    type Future = S::Future;
+
+    fn call(&self, req: Request<Incoming>) -> Self::Future {
+        let metrics = &self.request_metrics.metrics;
+        if self.request_metrics.path.as_deref().is_some_and(|path| req.uri().path() == path) {
+            let response = metrics_response(metrics);
+            return Box::pin(async move { response });
+        };
+        let mut timer = metrics.request_started(req.method());
+        let inner_clone = self.inner.clone();
+        Box::pin(async move {
+            let res = inner_clone.call(req).await?;
+            timer.status = Some(res.status().as_u16());
+            Ok(res.map(|body| MeteredBody { inner: body, _timer: timer }.boxed()))
+        })
+    }
+}
+
+// Holds the timer until the response body is sent or dropped
+struct MeteredBody {
+    inner: BoxBody<Bytes, Error>,
+    _timer: RequestTimer,
+}
+
+impl Body for MeteredBody {
+    type Data = Bytes;
+    type Error = Error;
+
+    fn poll_frame(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<std::result::Result<Frame<Bytes>, Error>>> {
+        Pin::new(&mut self.get_mut().inner).poll_frame(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
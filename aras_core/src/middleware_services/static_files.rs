@@ -0,0 +1,678 @@
+use std::cmp::Reverse;
+use std::fmt::{Debug, Write};
+use std::fs::Metadata;
+use std::io::SeekFrom;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use bytes::Bytes;
+use derive_more::derive::Constructor;
+use http::header::{
+    HeaderName, ACCEPT_ENCODING, ACCEPT_RANGES, ALLOW, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE,
+    ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, IF_RANGE, LAST_MODIFIED, LOCATION, RANGE, VARY,
+};
+use http::{HeaderMap, Method, StatusCode};
+use http_body_util::combinators::BoxBody;
+use http_body_util::{BodyExt, Full, StreamBody};
+use hyper::body::{Frame, Incoming};
+use hyper::service::Service;
+use hyper::Request;
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, CONTROLS};
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+use super::compression::q_value;
+use crate::error::{Error, Result};
+use crate::server::{ContentEncoding, StaticMount};
+use crate::types::{text_response, Response, ServiceFuture};
+
+const CHUNK_SIZE: u64 = 64 * 1024;
+
+// Characters escaped in the links of directory listings
+const PATH_SEGMENT: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'/')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'`')
+    .add(b'{')
+    .add(b'}');
+
+// Serves files of the mounted directories, other requests are passed on
+#[derive(Debug, Clone)]
+pub struct StaticFiles {
+    mounts: Arc<Vec<Arc<Mount>>>,
+}
+
+#[derive(Debug)]
+struct Mount {
+    // Without a trailing slash, empty when mounted on `/`
+    prefix: String,
+    // Canonical, files have to resolve to a path within it
+    root: PathBuf,
+    config: StaticMount,
+}
+
+impl StaticFiles {
+    pub fn new(mounts: Vec<StaticMount>) -> Result<Self> {
+        let mut mounts = mounts
+            .into_iter()
+            .map(|config| {
+                let root = std::fs::canonicalize(&config.directory)
+                    .ok()
+                    .filter(|root| root.is_dir())
+                    .ok_or_else(|| {
+                        Error::custom(format!("Static directory {} does not exist", config.directory.display()))
+                    })?;
+                let prefix = config.prefix.trim_end_matches('/').to_string();
+                Ok(Arc::new(Mount { prefix, root, config }))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        mounts.sort_by_key(|mount| Reverse(mount.prefix.len()));
+        Ok(Self { mounts: Arc::new(mounts) })
+    }
+
+    pub fn as_layer<S>(&self) -> impl Fn(S) -> StaticFilesLayer<S>
+    where
+        S: Service<Request<Incoming>, Response = Response, Error = Error, Future = ServiceFuture>
+            + Send
+            + Sync
+            + 'static,
+    {
+        let static_files = self.clone();
+        move |inner: S| -> StaticFilesLayer<S> {
+            StaticFilesLayer::new(Arc::new(inner), static_files.clone())
+        }
+    }
+
+    // The mount a path falls under, and the rest of the path within it
+    fn find<'a>(&self, path: &'a str) -> Option<(Arc<Mount>, &'a str)> {
+        self.mounts.iter().find_map(|mount| {
+            let rest = path.strip_prefix(mount.prefix.as_str())?;
+            (rest.is_empty() || rest.starts_with('/')).then(|| (mount.clone(), rest))
+        })
+    }
+}
+
+#[derive(Constructor, Debug, Clone)]
+pub struct StaticFilesLayer<S> {
+    inner: Arc<S>,
+    static_files: StaticFiles,
+}
+
+impl<S> Service<Request<Incoming>> for StaticFilesLayer<S>
+where
+    S: Service<Request<Incoming>, Response = Response, Error = Error, Future = ServiceFuture> + Send + Sync + 'static,
+{
+    type Error = S::Error;
+    type Response = S::Response;
+    type Future = S::Future;
+
+    fn call(&self, req: Request<Incoming>) -> Self::Future {
+        let Some((mount, rest)) = self.static_files.find(req.uri().path()) else {
+            return self.inner.call(req);
+        };
+        let rest = rest.to_string();
+        let inner = self.inner.clone();
+        Box::pin(async move {
+            match serve(&mount, &rest, &req).await? {
+                Some(response) => Ok(response),
+                // Mounted on `/` everything that isn't a file is left to the application
+                None if mount.prefix.is_empty() => inner.call(req).await,
+                None => not_found(),
+            }
+        })
+    }
+}
+
+// `None` when there is no file to send for the request
+async fn serve<B>(mount: &Mount, rest: &str, req: &Request<B>) -> Result<Option<Response>> {
+    if req.method() != Method::GET && req.method() != Method::HEAD {
+        if mount.prefix.is_empty() {
+            return Ok(None);
+        };
+        let response = hyper::Response::builder()
+            .status(StatusCode::METHOD_NOT_ALLOWED)
+            .header(ALLOW, "GET, HEAD");
+        return text_response(response, "Method Not Allowed").map(Some);
+    };
+    let Some(path) = resolve(&mount.root, rest) else {
+        return Ok(None);
+    };
+    // Symlinks are followed, as long as they stay within the mounted directory
+    let path = match tokio::fs::canonicalize(&path).await {
+        Ok(path) if path.starts_with(&mount.root) => path,
+        _ => return Ok(None),
+    };
+    let Ok(metadata) = tokio::fs::metadata(&path).await else {
+        return Ok(None);
+    };
+    if !metadata.is_dir() {
+        return send_file(mount, req, path, metadata).await.map(Some);
+    };
+
+    // Relative links in the index or listing only work with the trailing slash. The location
+    // is rebuilt from the mount, `//dir/` would point browsers to another host.
+    if !rest.ends_with('/') {
+        let path = format!("{}/{}/", mount.prefix, rest.trim_start_matches('/'));
+        let location = match req.uri().query() {
+            Some(query) => format!("{path}?{query}"),
+            None => path,
+        };
+        let response = hyper::Response::builder()
+            .status(StatusCode::MOVED_PERMANENTLY)
+            .header(LOCATION, location);
+        return text_response(response, "Moved Permanently").map(Some);
+    };
+    if let Some(index_file) = &mount.config.index_file {
+        if let Some((index, metadata)) = file_within(&mount.root, &path.join(index_file)).await {
+            return send_file(mount, req, index, metadata).await.map(Some);
+        };
+    };
+    match mount.config.list_directories {
+        true => list_directory(&path, req).await.map(Some),
+        false => Ok(None),
+    }
+}
+
+// The file a request path points to. Anything that could step outside of the
+// root is rejected, `..` segments and encoded separators included.
+fn resolve(root: &Path, rest: &str) -> Option<PathBuf> {
+    let decoded = percent_decode_str(rest).decode_utf8().ok()?;
+    let mut path = root.to_path_buf();
+    for segment in decoded.split('/').filter(|segment| !segment.is_empty() && *segment != ".") {
+        let mut components = Path::new(segment).components();
+        match (components.next(), components.next()) {
+            (Some(Component::Normal(name)), None) if !segment.contains('\\') => path.push(name),
+            _ => return None,
+        };
+    }
+    Some(path)
+}
+
+// A file that resolves to a path within the root, through symlinks as well
+async fn file_within(root: &Path, path: &Path) -> Option<(PathBuf, Metadata)> {
+    let path = tokio::fs::canonicalize(path).await.ok().filter(|path| path.starts_with(root))?;
+    let metadata = tokio::fs::metadata(&path).await.ok().filter(|metadata| metadata.is_file())?;
+    Some((path, metadata))
+}
+
+// A `.br` or `.gz` sibling the client accepts, preferring brotli when accepted equally
+async fn precompressed(
+    root: &Path,
+    path: &Path,
+    req_headers: &HeaderMap,
+) -> Option<(PathBuf, Metadata, ContentEncoding)> {
+    let accept = req_headers.get(ACCEPT_ENCODING)?.to_str().ok()?;
+    let mut candidates = [(ContentEncoding::Brotli, "br"), (ContentEncoding::Gzip, "gz")]
+        .map(|(encoding, extension)| (q_value(accept, encoding.as_str()), encoding, extension));
+    candidates.sort_by(|(a, _, _), (b, _, _)| b.total_cmp(a));
+    for (_, encoding, extension) in candidates.into_iter().filter(|(q, _, _)| *q > 0.0) {
+        let mut sibling = path.as_os_str().to_owned();
+        sibling.push(".");
+        sibling.push(extension);
+        if let Some((sibling, metadata)) = file_within(root, Path::new(&sibling)).await {
+            return Some((sibling, metadata, encoding));
+        };
+    }
+    None
+}
+
+async fn send_file<B>(mount: &Mount, req: &Request<B>, path: PathBuf, metadata: Metadata) -> Result<Response> {
+    let mime = mime_guess::from_path(&path).first_or_octet_stream();
+    let content_type = match mime.type_() == mime_guess::mime::TEXT && mime.get_param("charset").is_none() {
+        true => format!("{mime}; charset=utf-8"),
+        false => mime.to_string(),
+    };
+    let mut builder = hyper::Response::builder()
+        .header(CONTENT_TYPE, content_type)
+        .header(ACCEPT_RANGES, "bytes");
+    let (path, metadata) = match mount.config.precompressed {
+        true => {
+            builder = builder.header(VARY, "Accept-Encoding");
+            match precompressed(&mount.root, &path, req.headers()).await {
+                Some((sibling, sibling_metadata, encoding)) => {
+                    builder = builder.header(CONTENT_ENCODING, encoding.as_str());
+                    (sibling, sibling_metadata)
+                }
+                None => (path, metadata),
+            }
+        }
+        false => (path, metadata),
+    };
+
+    let etag = etag(&metadata);
+    let modified = metadata.modified().ok().map(whole_seconds);
+    builder = builder.header(ETAG, &etag);
+    if let Some(modified) = modified {
+        builder = builder.header(LAST_MODIFIED, httpdate::fmt_http_date(modified));
+    };
+    if not_modified(req.headers(), &etag, modified) {
+        let response = builder.status(StatusCode::NOT_MODIFIED).body(empty_body());
+        return Ok(response?);
+    };
+
+    let len = metadata.len();
+    let (start, end) = match byte_range(req.headers(), &etag, modified, len) {
+        ByteRange::Full => (0, len),
+        ByteRange::Partial(start, end) => {
+            builder = builder
+                .status(StatusCode::PARTIAL_CONTENT)
+                .header(CONTENT_RANGE, format!("bytes {start}-{end}/{len}"));
+            (start, end + 1)
+        }
+        ByteRange::Unsatisfiable => {
+            let response = builder
+                .status(StatusCode::RANGE_NOT_SATISFIABLE)
+                .header(CONTENT_RANGE, format!("bytes */{len}"))
+                .header(CONTENT_LENGTH, 0)
+                .body(empty_body());
+            return Ok(response?);
+        }
+    };
+    builder = builder.header(CONTENT_LENGTH, end - start);
+    if req.method() == Method::HEAD {
+        return Ok(builder.body(empty_body())?);
+    };
+
+    let mut file = tokio::fs::File::open(&path).await?;
+    if start > 0 {
+        file.seek(SeekFrom::Start(start)).await?;
+    };
+    Ok(builder.body(file_body(file, end - start))?)
+}
+
+// Streams `len` bytes from the current position, also used for `http.response.pathsend`
+pub(crate) fn file_body(mut file: tokio::fs::File, len: u64) -> BoxBody<Bytes, Error> {
+    let stream = async_stream::try_stream! {
+        let mut remaining = len;
+        while remaining > 0 {
+            let mut buf = vec![0; CHUNK_SIZE.min(remaining) as usize];
+            let read = file.read(&mut buf).await?;
+            if read == 0 {
+                Err(Error::aborted("File was truncated while it was sent"))?;
+            };
+            buf.truncate(read);
+            remaining -= read as u64;
+            yield Frame::data(Bytes::from(buf));
+        }
+    };
+    BoxBody::new(StreamBody::new(stream))
+}
+
+// Changes whenever the file is replaced or modified
+fn etag(metadata: &Metadata) -> String {
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .unwrap_or_default();
+    format!("\"{:x}-{:x}\"", modified.as_nanos(), metadata.len())
+}
+
+// HTTP dates have no fractions of a second
+fn whole_seconds(time: SystemTime) -> SystemTime {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    UNIX_EPOCH + Duration::from_secs(since_epoch.as_secs())
+}
+
+fn header(headers: &HeaderMap, name: HeaderName) -> Option<&str> {
+    headers.get(name).and_then(|value| value.to_str().ok())
+}
+
+// `If-None-Match` takes precedence over `If-Modified-Since`
+fn not_modified(headers: &HeaderMap, etag: &str, modified: Option<SystemTime>) -> bool {
+    if let Some(if_none_match) = header(headers, IF_NONE_MATCH) {
+        return if_none_match
+            .split(',')
+            .map(str::trim)
+            .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag);
+    };
+    let since = header(headers, IF_MODIFIED_SINCE).and_then(|since| httpdate::parse_http_date(since).ok());
+    matches!((since, modified), (Some(since), Some(modified)) if modified <= since)
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum ByteRange {
+    Full,
+    // First and last byte, inclusive
+    Partial(u64, u64),
+    Unsatisfiable,
+}
+
+// A single range of bytes. Multiple ranges, malformed ones and ranges of a file
+// that changed since (`If-Range`) get the whole file.
+fn byte_range(headers: &HeaderMap, etag: &str, modified: Option<SystemTime>, len: u64) -> ByteRange {
+    let Some(range) = header(headers, RANGE) else {
+        return ByteRange::Full;
+    };
+    if let Some(if_range) = header(headers, IF_RANGE) {
+        let unchanged = match if_range.starts_with('"') {
+            true => if_range == etag,
+            false => httpdate::parse_http_date(if_range).is_ok_and(|date| Some(date) == modified),
+        };
+        if !unchanged {
+            return ByteRange::Full;
+        };
+    };
+    let spec = range.trim().strip_prefix("bytes=").filter(|spec| !spec.contains(','));
+    let Some((start, end)) = spec.and_then(|spec| spec.split_once('-')) else {
+        return ByteRange::Full;
+    };
+    let (start, end) = (start.trim(), end.trim());
+    let parse = |value: &str| value.parse::<u64>().ok();
+    match (start.is_empty(), end.is_empty()) {
+        // The last bytes of the file
+        (true, false) => match parse(end) {
+            Some(0) => ByteRange::Unsatisfiable,
+            Some(_) if len == 0 => ByteRange::Unsatisfiable,
+            Some(suffix) => ByteRange::Partial(len.saturating_sub(suffix), len - 1),
+            None => ByteRange::Full,
+        },
+        (false, _) => match (parse(start), parse(end)) {
+            (Some(start), _) if start >= len => ByteRange::Unsatisfiable,
+            (Some(start), None) if end.is_empty() => ByteRange::Partial(start, len - 1),
+            (Some(start), Some(end)) if start <= end => ByteRange::Partial(start, end.min(len - 1)),
+            _ => ByteRange::Full,
+        },
+        (true, true) => ByteRange::Full,
+    }
+}
+
+async fn list_directory<B>(path: &Path, req: &Request<B>) -> Result<Response> {
+    let mut entries = Vec::new();
+    let mut dir = tokio::fs::read_dir(path).await?;
+    while let Some(entry) = dir.next_entry().await? {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        // Hidden files are served, but not listed
+        if name.starts_with('.') {
+            continue;
+        };
+        let is_dir = entry.file_type().await.is_ok_and(|file_type| file_type.is_dir());
+        entries.push((name, is_dir));
+    }
+    entries.sort();
+
+    let title = html_escape(&percent_decode_str(req.uri().path()).decode_utf8_lossy());
+    let mut html = format!("<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>Index of {title}</title></head>\n");
+    _ = writeln!(html, "<body>\n<h1>Index of {title}</h1>\n<ul>\n<li><a href=\"../\">../</a></li>");
+    for (name, is_dir) in entries {
+        let slash = if is_dir { "/" } else { "" };
+        let href = utf8_percent_encode(&name, PATH_SEGMENT);
+        _ = writeln!(html, "<li><a href=\"{href}{slash}\">{}{slash}</a></li>", html_escape(&name));
+    }
+    html.push_str("</ul>\n</body>\n</html>\n");
+
+    let response = hyper::Response::builder()
+        .header(CONTENT_TYPE, "text/html; charset=utf-8")
+        .header(CONTENT_LENGTH, html.len())
+        .body(Full::new(Bytes::from(html)).map_err(|never| match never {}).boxed());
+    Ok(response?)
+}
+
+fn html_escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        };
+    }
+    escaped
+}
+
+fn empty_body() -> BoxBody<Bytes, Error> {
+    Full::new(Bytes::new()).map_err(|never| match never {}).boxed()
+}
+
+fn not_found() -> Result<Response> {
+    text_response(hyper::Response::builder().status(StatusCode::NOT_FOUND), "Not Found")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use bytes::Bytes;
+    use http::{HeaderMap, Method, StatusCode};
+    use http_body_util::{BodyExt, Empty};
+    use hyper::body::Incoming;
+    use hyper::server::conn::http1;
+    use hyper::service::Service;
+    use hyper::Request;
+    use hyper_util::rt::TokioIo;
+
+    use super::{byte_range, not_found, resolve, serve, ByteRange, Mount, StaticFiles};
+    use crate::error::Error;
+    use crate::server::StaticMount;
+    use crate::types::{text_response, Response, ServiceFuture};
+
+    // A fresh directory per test, tests run in parallel
+    fn static_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("aras-static-{}-{name}", std::process::id()));
+        _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("docs")).unwrap();
+        std::fs::write(dir.join("hello.txt"), "hello world").unwrap();
+        std::fs::write(dir.join("app.js"), "console.log(1)").unwrap();
+        std::fs::write(dir.join("app.js.br"), "brotli").unwrap();
+        std::fs::write(dir.join("docs").join("index.html"), "<h1>docs</h1>").unwrap();
+        dir
+    }
+
+    fn mount(dir: &Path) -> std::sync::Arc<Mount> {
+        let files = StaticFiles::new(vec![StaticMount::new("/static/", dir)]).unwrap();
+        files.find("/static/").unwrap().0
+    }
+
+    async fn get(mount: &Mount, path: &str, headers: &[(&str, &str)]) -> Response {
+        let mut builder = http::Request::builder().uri(path);
+        for (name, value) in headers {
+            builder = builder.header(*name, *value);
+        }
+        let rest = path.strip_prefix("/static").unwrap().split('?').next().unwrap();
+        // Like the layer answers for mounts below `/`
+        let response = serve(mount, rest, &builder.body(()).unwrap()).await.unwrap();
+        response.unwrap_or_else(|| not_found().unwrap())
+    }
+
+    // Stands in for the application behind the static files
+    struct AppService;
+
+    impl Service<Request<Incoming>> for AppService {
+        type Response = Response;
+        type Error = Error;
+        type Future = ServiceFuture;
+
+        fn call(&self, _req: Request<Incoming>) -> Self::Future {
+            Box::pin(async { text_response(hyper::Response::builder(), "app") })
+        }
+    }
+
+    // Sends a request through the layer over HTTP/1.1
+    async fn request(files: &StaticFiles, method: Method, path: &str) -> (StatusCode, String) {
+        let (client, server) = tokio::io::duplex(4096);
+        let layer = files.as_layer()(AppService);
+        tokio::spawn(http1::Builder::new().serve_connection(TokioIo::new(server), layer));
+        let (mut sender, connection) = hyper::client::conn::http1::handshake(TokioIo::new(client)).await.unwrap();
+        tokio::spawn(connection);
+        let request = Request::builder()
+            .method(method)
+            .uri(path)
+            .header("host", "localhost")
+            .body(Empty::<Bytes>::new())
+            .unwrap();
+        let response = sender.send_request(request).await.unwrap();
+        let status = response.status();
+        let bytes = response.into_body().collect().await.unwrap().to_bytes();
+        (status, String::from_utf8(bytes.to_vec()).unwrap())
+    }
+
+    async fn body(response: Response) -> String {
+        let bytes = response.into_body().collect().await.unwrap().to_bytes();
+        String::from_utf8(bytes.to_vec()).unwrap()
+    }
+
+    #[test]
+    fn test_resolve() {
+        let root = Path::new("/srv/static");
+        assert!(resolve(root, "/css/site.css") == Some(root.join("css/site.css")));
+        assert!(resolve(root, "/a%20b.txt") == Some(root.join("a b.txt")));
+        assert!(resolve(root, "//./x") == Some(root.join("x")));
+        assert!(resolve(root, "/../etc/passwd").is_none());
+        assert!(resolve(root, "/css/%2e%2e/%2e%2e/etc/passwd").is_none());
+        assert!(resolve(root, "/css%2F..%2F..%2Fetc").is_none());
+        assert!(resolve(root, "/..%5C..%5Cetc").is_none());
+    }
+
+    #[test]
+    fn test_byte_range() {
+        let range = |value: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert("range", value.parse().unwrap());
+            byte_range(&headers, "\"tag\"", None, 100)
+        };
+        assert!(byte_range(&HeaderMap::new(), "\"tag\"", None, 100) == ByteRange::Full);
+        assert!(range("bytes=0-9") == ByteRange::Partial(0, 9));
+        assert!(range("bytes=90-") == ByteRange::Partial(90, 99));
+        assert!(range("bytes=-10") == ByteRange::Partial(90, 99));
+        assert!(range("bytes=50-500") == ByteRange::Partial(50, 99));
+        assert!(range("bytes=100-") == ByteRange::Unsatisfiable);
+        assert!(range("bytes=0-1,5-6") == ByteRange::Full);
+        assert!(range("bytes=9-0") == ByteRange::Full);
+        assert!(range("lines=1-2") == ByteRange::Full);
+
+        let mut headers = HeaderMap::new();
+        headers.insert("range", "bytes=0-9".parse().unwrap());
+        headers.insert("if-range", "\"old\"".parse().unwrap());
+        assert!(byte_range(&headers, "\"tag\"", None, 100) == ByteRange::Full);
+    }
+
+    #[tokio::test]
+    async fn test_file_and_conditional_requests() {
+        let dir = static_dir("conditional");
+        let mount = mount(&dir);
+
+        let response = get(&mount, "/static/hello.txt", &[]).await;
+        assert!(response.status() == StatusCode::OK);
+        assert!(response.headers()["content-type"] == "text/plain; charset=utf-8");
+        assert!(response.headers()["content-length"] == "11");
+        let etag = response.headers()["etag"].to_str().unwrap().to_string();
+        let modified = response.headers()["last-modified"].to_str().unwrap().to_string();
+        assert!(body(response).await == "hello world");
+
+        let response = get(&mount, "/static/hello.txt", &[("if-none-match", &etag)]).await;
+        assert!(response.status() == StatusCode::NOT_MODIFIED);
+        let response = get(&mount, "/static/hello.txt", &[("if-modified-since", &modified)]).await;
+        assert!(response.status() == StatusCode::NOT_MODIFIED);
+        let response = get(&mount, "/static/hello.txt", &[("if-none-match", "\"other\"")]).await;
+        assert!(response.status() == StatusCode::OK);
+
+        let response = get(&mount, "/static/hello.txt", &[("range", "bytes=6-")]).await;
+        assert!(response.status() == StatusCode::PARTIAL_CONTENT);
+        assert!(response.headers()["content-range"] == "bytes 6-10/11");
+        assert!(body(response).await == "world");
+        let response = get(&mount, "/static/hello.txt", &[("range", "bytes=20-")]).await;
+        assert!(response.status() == StatusCode::RANGE_NOT_SATISFIABLE);
+        assert!(response.headers()["content-range"] == "bytes */11");
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_precompressed_and_directories() {
+        let dir = static_dir("directories");
+        let mount = mount(&dir);
+
+        let response = get(&mount, "/static/app.js", &[("accept-encoding", "gzip, br")]).await;
+        assert!(response.headers()["content-encoding"] == "br");
+        assert!(response.headers()["vary"] == "Accept-Encoding");
+        assert!(body(response).await == "brotli");
+        let response = get(&mount, "/static/app.js", &[("accept-encoding", "gzip")]).await;
+        assert!(!response.headers().contains_key("content-encoding"));
+        assert!(body(response).await == "console.log(1)");
+
+        let response = get(&mount, "/static/docs?page=1", &[]).await;
+        assert!(response.status() == StatusCode::MOVED_PERMANENTLY);
+        assert!(response.headers()["location"] == "/static/docs/?page=1");
+        let response = get(&mount, "/static/docs/", &[]).await;
+        assert!(body(response).await == "<h1>docs</h1>");
+        let response = get(&mount, "/static/", &[]).await;
+        assert!(response.status() == StatusCode::NOT_FOUND);
+        let response = get(&mount, "/static/missing.txt", &[]).await;
+        assert!(response.status() == StatusCode::NOT_FOUND);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_longest_prefix_wins() {
+        let dir = static_dir("prefixes");
+        let files = StaticFiles::new(vec![
+            StaticMount::new("/", &dir),
+            StaticMount::new("/static", dir.join("docs")),
+        ])
+        .unwrap();
+        assert!(files.find("/static/index.html").is_some_and(|(mount, rest)| mount.prefix == "/static" && rest == "/index.html"));
+        assert!(files.find("/staticfile").is_some_and(|(mount, rest)| mount.prefix.is_empty() && rest == "/staticfile"));
+        assert!(StaticFiles::new(vec![StaticMount::new("/", dir.join("missing"))]).is_err());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_symlinks_outside_root_are_not_served() {
+        let dir = static_dir("symlinks");
+        let outside = static_dir("symlinks-outside");
+        std::fs::write(outside.join("secret.txt"), "secret").unwrap();
+        std::fs::write(dir.join("page.txt"), "page").unwrap();
+        std::os::unix::fs::symlink(outside.join("secret.txt"), dir.join("page.txt.br")).unwrap();
+        std::fs::create_dir(dir.join("linked")).unwrap();
+        std::os::unix::fs::symlink(outside.join("secret.txt"), dir.join("linked").join("index.html")).unwrap();
+        let mount = mount(&dir);
+
+        let response = get(&mount, "/static/page.txt", &[("accept-encoding", "br")]).await;
+        assert!(!response.headers().contains_key("content-encoding"));
+        assert!(body(response).await == "page");
+        let response = get(&mount, "/static/linked/", &[]).await;
+        assert!(response.status() == StatusCode::NOT_FOUND);
+        std::fs::remove_dir_all(dir).unwrap();
+        std::fs::remove_dir_all(outside).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_directory_redirect_stays_on_host() {
+        let dir = static_dir("redirect");
+        let files = StaticFiles::new(vec![StaticMount::new("/", &dir)]).unwrap();
+        for path in ["//docs", "/docs", "///docs"] {
+            let (mount, rest) = files.find(path).unwrap();
+            let request = http::Request::builder().uri(path).body(()).unwrap();
+            let response = serve(&mount, rest, &request).await.unwrap().unwrap();
+            assert!(response.status() == StatusCode::MOVED_PERMANENTLY);
+            assert!(response.headers()["location"] == "/docs/");
+        }
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_root_mount_falls_through() {
+        let dir = static_dir("root");
+        let files = StaticFiles::new(vec![StaticMount::new("/", &dir)]).unwrap();
+        assert!(request(&files, Method::GET, "/hello.txt").await == (StatusCode::OK, "hello world".into()));
+        assert!(request(&files, Method::GET, "/api/users").await == (StatusCode::OK, "app".into()));
+        assert!(request(&files, Method::GET, "/").await == (StatusCode::OK, "app".into()));
+        assert!(request(&files, Method::POST, "/hello.txt").await == (StatusCode::OK, "app".into()));
+
+        // Below `/` the mount owns its paths
+        let files = StaticFiles::new(vec![StaticMount::new("/static", &dir)]).unwrap();
+        assert!(request(&files, Method::GET, "/static/missing.txt").await.0 == StatusCode::NOT_FOUND);
+        assert!(request(&files, Method::POST, "/static/hello.txt").await.0 == StatusCode::METHOD_NOT_ALLOWED);
+        assert!(request(&files, Method::GET, "/api/users").await == (StatusCode::OK, "app".into()));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,29 +1,414 @@
-use std::net::IpAddr;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
 
+use ipnet::IpNet;
 use tokio::sync::Semaphore;
 
+use super::proxy_protocol::ProxyProtocol;
+use super::tls::TlsConfig;
+use crate::error::Error;
+
+// HTTP versions the server is willing to speak on a connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HttpProtocol {
+    Http1,
+    Http2,
+    // Detect the version per connection, using the HTTP/2 connection preface (prior knowledge)
+    Auto,
+}
+
+impl FromStr for HttpProtocol {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_lowercase().as_str() {
+            "h1" | "http1" => Ok(Self::Http1),
+            "h2" | "http2" => Ok(Self::Http2),
+            "auto" => Ok(Self::Auto),
+            _ => Err(Error::custom(format!("Invalid http protocol '{value}', expected one of 'h1', 'h2' or 'auto'"))),
+        }
+    }
+}
+
+// Listen on a unix domain socket
+#[derive(Debug, Clone)]
+pub struct UnixSocketConfig {
+    pub path: PathBuf,
+    // File permissions of the socket, e.g. `0o660`
+    pub mode: Option<u32>,
+}
+
+impl UnixSocketConfig {
+    pub fn new(path: impl Into<PathBuf>, mode: Option<u32>) -> Self {
+        Self { path: path.into(), mode }
+    }
+}
+
+// An address the server listens on
+#[derive(Debug, Clone)]
+pub enum BindTarget {
+    // IPv4/IPv6 address or a host name, resolved at startup.
+    // Binding to "::" accepts IPv4 connections as well (dual-stack)
+    // unless "0.0.0.0" is bound to the same port.
+    Tcp { host: String, port: u16 },
+    Unix(UnixSocketConfig),
+    // An already bound and listening socket inherited from the parent process,
+    // either a TCP or a unix domain socket
+    #[cfg(unix)]
+    Fd(std::os::fd::RawFd),
+    // A socket passed by systemd socket activation, the server takes ownership of it
+    // and closes it once done. Only to be bound once.
+    #[cfg(unix)]
+    Activated(std::os::fd::RawFd),
+}
+
+impl BindTarget {
+    pub fn tcp(host: impl Into<String>, port: u16) -> Self {
+        Self::Tcp { host: host.into(), port }
+    }
+}
+
+// Connection and request timeouts, `None` disables a timeout
+#[derive(Debug, Clone, Copy)]
+pub struct Timeouts {
+    // Time for a client to send the request headers
+    pub header_read: Option<Duration>,
+    // Time an idle keep-alive connection is kept open
+    pub keep_alive: Option<Duration>,
+    // Time to wait for the next chunk of the request body
+    pub body_read: Option<Duration>,
+    // Time for the application to start a response, a 504 is sent otherwise
+    pub request: Option<Duration>,
+    // Time between two `http.response.body` messages, the connection is aborted otherwise.
+    // Off by default since idle streams such as server-sent events would be aborted as well
+    pub response_body: Option<Duration>,
+    // Time a write to the client may be blocked
+    pub write: Option<Duration>,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Self {
+            header_read: Some(Duration::from_secs(60)),
+            keep_alive: Some(Duration::from_secs(5)),
+            body_read: Some(Duration::from_secs(60)),
+            request: None,
+            response_body: None,
+            write: Some(Duration::from_secs(60)),
+        }
+    }
+}
+
+// Requests over the concurrency limit wait here for a free slot instead of
+// getting a 503 right away
+#[derive(Debug, Clone, Copy)]
+pub struct ConcurrencyQueue {
+    // Requests that may wait at the same time, 0 (the default) disables the queue
+    pub max_length: usize,
+    // Time a request may wait for a free slot
+    pub max_wait: Duration,
+}
+
+impl ConcurrencyQueue {
+    // Seconds a refused client should wait before retrying
+    pub fn retry_after(&self) -> u64 {
+        self.max_wait.as_secs_f64().ceil().max(1.0) as u64
+    }
+}
+
+impl Default for ConcurrencyQueue {
+    fn default() -> Self {
+        Self {
+            max_length: 0,
+            max_wait: Duration::from_secs(5),
+        }
+    }
+}
+
+// Token bucket rate limit per client IP
+#[derive(Debug, Clone)]
+pub struct RateLimit {
+    // Requests per second a client may keep sending
+    pub per_second: f64,
+    // Requests a client may send at once
+    pub burst: u32,
+    // Clients that are never limited
+    pub allow_ips: Vec<IpNet>,
+    // Clients tracked at most, the longest idle ones make room for new ones
+    pub max_clients: usize,
+}
+
+impl RateLimit {
+    pub fn new(per_second: f64, burst: u32) -> Result<Self, Error> {
+        if !per_second.is_finite() || per_second <= 0.0 {
+            let message = format!("Invalid rate limit {per_second}, expected a positive number of requests per second");
+            return Err(Error::custom(message));
+        };
+        Ok(Self {
+            per_second,
+            burst,
+            allow_ips: Vec::new(),
+            max_clients: 100_000,
+        })
+    }
+}
+
+// Line format of the access log
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessLogFormat {
+    // Common Log Format
+    Common,
+    // Combined Log Format, followed by the duration in seconds and the request id
+    Combined,
+    // One JSON object per line
+    Json,
+}
+
+impl FromStr for AccessLogFormat {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_lowercase().as_str() {
+            "common" => Ok(Self::Common),
+            "combined" => Ok(Self::Combined),
+            "json" => Ok(Self::Json),
+            _ => Err(Error::custom(format!(
+                "Invalid access log format '{value}', expected one of 'common', 'combined' or 'json'"
+            ))),
+        }
+    }
+}
+
+// Where access log lines are written to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AccessLogTarget {
+    // The `log` crate at info level, with the `access` target
+    Log,
+    Stdout,
+    // Appended to, the file is created when missing
+    File(PathBuf),
+}
+
+// One line per completed request, written once the response body is sent
+#[derive(Debug, Clone)]
+pub struct AccessLogConfig {
+    pub format: AccessLogFormat,
+    pub target: AccessLogTarget,
+}
+
+impl Default for AccessLogConfig {
+    fn default() -> Self {
+        Self {
+            format: AccessLogFormat::Common,
+            target: AccessLogTarget::Log,
+        }
+    }
+}
+
+// Prometheus metrics endpoint
+#[derive(Debug, Clone)]
+pub struct MetricsConfig {
+    pub path: String,
+    // Serve the metrics on a separate listener, otherwise on `path` of the application listeners
+    pub bind: Option<BindTarget>,
+}
+
+impl MetricsConfig {
+    pub fn new(path: impl Into<String>, bind: Option<BindTarget>) -> Self {
+        Self { path: path.into(), bind }
+    }
+}
+
+// Content codings the server can compress responses with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContentEncoding {
+    Gzip,
+    // zlib format, as specified for `deflate` in HTTP
+    Deflate,
+    Brotli,
+    Zstd,
+}
+
+impl ContentEncoding {
+    // Token used in `Accept-Encoding` and `Content-Encoding`
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Gzip => "gzip",
+            Self::Deflate => "deflate",
+            Self::Brotli => "br",
+            Self::Zstd => "zstd",
+        }
+    }
+}
+
+impl FromStr for ContentEncoding {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_lowercase().as_str() {
+            "gzip" => Ok(Self::Gzip),
+            "deflate" => Ok(Self::Deflate),
+            "br" => Ok(Self::Brotli),
+            "zstd" => Ok(Self::Zstd),
+            _ => Err(Error::custom(format!(
+                "Invalid content encoding '{value}', expected one of 'gzip', 'deflate', 'br' or 'zstd'"
+            ))),
+        }
+    }
+}
+
+// Compress responses for clients that accept it in `Accept-Encoding`
+#[derive(Debug, Clone)]
+pub struct CompressionConfig {
+    // In order of preference, used when the client accepts several equally
+    pub encodings: Vec<ContentEncoding>,
+    // Responses with a smaller `Content-Length` are sent as they are
+    pub min_size: u64,
+    // Content types, or prefixes like `video/`, that are already compressed
+    pub excluded_content_types: Vec<String>,
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        Self {
+            encodings: vec![ContentEncoding::Brotli, ContentEncoding::Zstd, ContentEncoding::Gzip],
+            min_size: 500,
+            excluded_content_types: [
+                "image/png",
+                "image/jpeg",
+                "image/gif",
+                "image/webp",
+                "image/avif",
+                "audio/",
+                "video/",
+                "font/woff",
+                "application/zip",
+                "application/gzip",
+                "application/zstd",
+                "application/octet-stream",
+            ]
+            .map(String::from)
+            .to_vec(),
+        }
+    }
+}
+
+// Files under `directory` served on the URL `prefix`, without involving the application.
+// Mounted on `/`, requests without a matching file are passed on to the application.
+#[derive(Debug, Clone)]
+pub struct StaticMount {
+    pub prefix: String,
+    pub directory: PathBuf,
+    // Served for requests to a directory, like `index.html`
+    pub index_file: Option<String>,
+    // List the contents of directories without an index file
+    pub list_directories: bool,
+    // Serve a `.br` or `.gz` sibling of a file to clients that accept it
+    pub precompressed: bool,
+}
+
+impl StaticMount {
+    pub fn new(prefix: impl Into<String>, directory: impl Into<PathBuf>) -> Self {
+        Self {
+            prefix: prefix.into(),
+            directory: directory.into(),
+            index_file: Some(String::from("index.html")),
+            list_directories: false,
+            precompressed: true,
+        }
+    }
+}
+
+// Maximum request body size in bytes, optionally per path prefix.
+// The longest matching prefix wins, other paths use the default.
+#[derive(Debug, Clone)]
+pub struct BodySizeLimits {
+    pub default: u64,
+    pub prefixes: Vec<(String, u64)>,
+}
+
+impl BodySizeLimits {
+    pub fn new(default: u64) -> Self {
+        Self { default, prefixes: Vec::new() }
+    }
+
+    pub fn with_prefix(mut self, prefix: impl Into<String>, limit: u64) -> Self {
+        self.prefixes.push((prefix.into(), limit));
+        self
+    }
+
+    // Prefixes match whole path segments, `/upload` doesn't cover `/uploads`
+    pub fn for_path(&self, path: &str) -> u64 {
+        self.prefixes
+            .iter()
+            .map(|(prefix, limit)| (prefix.trim_end_matches('/'), limit))
+            .filter(|(prefix, _)| {
+                path.strip_prefix(prefix).is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+            })
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map_or(self.default, |(_, limit)| *limit)
+    }
+}
+
 pub struct ServerConfig {
     pub keep_alive: bool,
     pub limit_concurrency: usize,
-    pub addr: IpAddr,
-    pub port: u16,
-    pub max_size: u64,
+    pub concurrency_queue: ConcurrencyQueue,
+    // Open websocket connections, counted separately from requests for as long as they are open
+    pub max_websockets: usize,
+    // Limits the requests per client, `None` disables rate limiting
+    pub rate_limit: Option<RateLimit>,
+    // Proxies trusted to tell the client address, scheme and host in the `Forwarded`
+    // or `X-Forwarded-*` headers
+    pub forwarded_allow_ips: Vec<IpNet>,
+    pub binds: Vec<BindTarget>,
+    // Read the client address from a PROXY protocol header at the start of each connection
+    pub proxy_protocol: ProxyProtocol,
+    pub max_size: BodySizeLimits,
+    pub http_protocol: HttpProtocol,
+    // `None` disables the access log
+    pub access_log: Option<AccessLogConfig>,
+    // `None` disables the metrics endpoint, metrics are collected regardless
+    pub metrics: Option<MetricsConfig>,
+    // `None` sends responses as the application produced them
+    pub compression: Option<CompressionConfig>,
+    // Decode request bodies with a `Content-Encoding` before passing them to the application
+    pub decompress_request_body: bool,
+    // Served before requests reach the application, the longest matching prefix wins
+    pub static_mounts: Vec<StaticMount>,
+    pub tls: Option<TlsConfig>,
+    // Time in-flight requests and websockets get to finish when shutting down
+    pub graceful_shutdown_timeout: Duration,
+    pub timeouts: Timeouts,
 }
 
 impl ServerConfig {
     pub fn new(
         keep_alive: bool,
         max_concurrency: Option<usize>,
-        addr: IpAddr,
-        port: u16,
+        binds: Vec<BindTarget>,
         max_size: u64,
     ) -> Self {
-        Self { 
+        Self {
             keep_alive,
             limit_concurrency: max_concurrency.unwrap_or(Semaphore::MAX_PERMITS) ,
-            addr,
-            port,
-            max_size,
+            concurrency_queue: ConcurrencyQueue::default(),
+            max_websockets: Semaphore::MAX_PERMITS,
+            rate_limit: None,
+            forwarded_allow_ips: Vec::new(),
+            binds,
+            proxy_protocol: ProxyProtocol::None,
+            max_size: BodySizeLimits::new(max_size),
+            http_protocol: HttpProtocol::Auto,
+            access_log: Some(AccessLogConfig::default()),
+            metrics: None,
+            compression: None,
+            decompress_request_body: false,
+            static_mounts: Vec::new(),
+            tls: None,
+            graceful_shutdown_timeout: Duration::from_secs(30),
+            timeouts: Timeouts::default(),
         }
     }
 }
@@ -33,9 +418,42 @@ impl Default for ServerConfig {
         Self {
             keep_alive: true,
             limit_concurrency: Semaphore::MAX_PERMITS,
-            addr: [127, 0, 0, 1].into(),
-            port: 8080,
-            max_size: 1_000_000_000 
+            concurrency_queue: ConcurrencyQueue::default(),
+            max_websockets: Semaphore::MAX_PERMITS,
+            rate_limit: None,
+            forwarded_allow_ips: Vec::new(),
+            binds: vec![BindTarget::tcp("127.0.0.1", 8080)],
+            proxy_protocol: ProxyProtocol::None,
+            max_size: BodySizeLimits::new(1_000_000_000),
+            http_protocol: HttpProtocol::Auto,
+            access_log: Some(AccessLogConfig::default()),
+            metrics: None,
+            compression: None,
+            decompress_request_body: false,
+            static_mounts: Vec::new(),
+            tls: None,
+            graceful_shutdown_timeout: Duration::from_secs(30),
+            timeouts: Timeouts::default(),
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BodySizeLimits;
+
+    #[test]
+    fn test_body_size_limit_for_path() {
+        let limits = BodySizeLimits::new(100)
+            .with_prefix("/upload", 1000)
+            .with_prefix("/upload/large", 5000);
+        assert!(limits.for_path("/") == 100);
+        assert!(limits.for_path("/api/upload") == 100);
+        assert!(limits.for_path("/upload") == 1000);
+        assert!(limits.for_path("/upload/small") == 1000);
+        assert!(limits.for_path("/upload/large/file") == 5000);
+        assert!(limits.for_path("/uploads") == 100);
+        assert!(limits.for_path("/upload-anything") == 100);
+        assert!(limits.for_path("/upload/larger") == 1000);
+    }
+}
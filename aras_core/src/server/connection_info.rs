@@ -1,20 +1,56 @@
 use std::net::SocketAddr;
+use std::path::PathBuf;
+
+use crate::asgispec::TLSExtension;
+
+// Address a connection was accepted on
+#[derive(Clone, Debug)]
+pub enum ServerAddr {
+    Tcp(SocketAddr),
+    Unix(PathBuf),
+}
+
+impl std::fmt::Display for ServerAddr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Tcp(addr) => write!(f, "{addr}"),
+            Self::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
 
 #[derive(Clone, Debug)]
 pub struct ConnectionInfo {
-    pub client_ip: String,
-    pub server_ip: String,
-    pub client_port: u16,
-    pub server_port: u16,
+    // Unix domain socket peers are unnamed, so the client is not always known
+    pub client: Option<SocketAddr>,
+    pub server: ServerAddr,
+    pub tls: Option<TLSExtension>,
 }
 
 impl ConnectionInfo {
-    pub fn new(client: SocketAddr, server: SocketAddr) -> Self {
-        Self {
-            client_ip: client.ip().to_string(),
-            server_ip: server.ip().to_string(),
-            client_port: client.port(),
-            server_port: server.port(),
+    pub fn new(client: Option<SocketAddr>, server: ServerAddr) -> Self {
+        Self { client, server, tls: None }
+    }
+
+    // The `client` field of the ASGI scope
+    pub fn scope_client(&self) -> Option<(String, u16)> {
+        self.client.map(|addr| (addr.ip().to_string(), addr.port()))
+    }
+
+    // The `server` field of the ASGI scope, `[path, None]` for unix domain sockets
+    pub fn scope_server(&self) -> Option<(String, Option<u16>)> {
+        match &self.server {
+            ServerAddr::Tcp(addr) => Some((addr.ip().to_string(), Some(addr.port()))),
+            ServerAddr::Unix(path) => Some((path.to_string_lossy().into_owned(), None)),
+        }
+    }
+}
+
+impl std::fmt::Display for ConnectionInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.client {
+            Some(addr) => write!(f, "{addr}"),
+            None => write!(f, "{}", self.server),
         }
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,348 @@
+use std::net::IpAddr;
+
+use http::HeaderMap;
+use ipnet::IpNet;
+
+use crate::error::{Error, Result};
+
+// Parse a comma separated list of IP addresses and networks, `*` matches all addresses
+pub fn parse_networks(value: &str) -> Result<Vec<IpNet>> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .flat_map(|item| match item {
+            "*" => vec![Ok(IpNet::V4(Default::default())), Ok(IpNet::V6(Default::default()))],
+            _ => vec![item
+                .parse::<IpNet>()
+                .or_else(|_| item.parse::<IpAddr>().map(IpNet::from))
+                .map_err(|_| Error::custom(format!("Invalid IP address or network '{item}'")))],
+        })
+        .collect()
+}
+
+pub(crate) fn contains(networks: &[IpNet], ip: IpAddr) -> bool {
+    // Dual stack sockets report IPv4 peers as IPv4-mapped IPv6 addresses
+    let ip = ip.to_canonical();
+    networks.iter().any(|network| network.contains(&ip))
+}
+
+// The original request as told by trusted proxies, from the `Forwarded` header
+// (RFC 7239) or else the `X-Forwarded-For`, `X-Forwarded-Proto` and `X-Forwarded-Host` headers
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Forwarded {
+    // The port is 0 when the proxies don't pass it on
+    pub client: Option<(IpAddr, u16)>,
+    pub proto: Option<String>,
+    pub host: Option<String>,
+}
+
+// One element of a `Forwarded` header, describing the request a proxy received
+#[derive(Debug, Default)]
+struct Hop {
+    for_: Option<String>,
+    proto: Option<String>,
+    host: Option<String>,
+}
+
+impl Forwarded {
+    // Headers are only believed from trusted peers. Unix domain socket peers
+    // have no address and are only trusted when all addresses are (`*`).
+    pub fn from_headers(peer: Option<IpAddr>, headers: &HeaderMap, trusted: &[IpNet]) -> Self {
+        let peer_trusted = match peer {
+            Some(ip) => contains(trusted, ip),
+            None => trusted.iter().filter(|network| network.prefix_len() == 0).count() == 2,
+        };
+        if !peer_trusted {
+            return Self::default();
+        };
+
+        if headers.contains_key(http::header::FORWARDED) {
+            let hops = headers
+                .get_all(http::header::FORWARDED)
+                .iter()
+                .filter_map(|value| value.to_str().ok())
+                .flat_map(parse_forwarded_header)
+                .collect();
+            Self::from_hops(hops, trusted)
+        } else {
+            // Each proxy appends to the lists, so entries line up from the right. A proxy
+            // that only passes on the proto and host adds no entry, and the last value
+            // recorded by a trusted hop is kept.
+            let mut protos = header_list(headers, "x-forwarded-proto").rev();
+            let mut hosts = header_list(headers, "x-forwarded-host").rev();
+            let mut hops = header_list(headers, "x-forwarded-for")
+                .rev()
+                .map(|for_| Hop {
+                    for_: Some(for_.to_owned()),
+                    proto: protos.next().map(str::to_owned),
+                    host: hosts.next().map(str::to_owned),
+                })
+                .collect::<Vec<_>>();
+            hops.reverse();
+            Self::from_hops(hops, trusted)
+        }
+    }
+
+    // Hops are read from the right, each one added by a proxy that is trusted so far.
+    // Stops at the first untrusted address, so clients can't spoof their address by
+    // sending the headers themselves.
+    fn from_hops(hops: Vec<Hop>, trusted: &[IpNet]) -> Self {
+        let mut forwarded = Self::default();
+        for hop in hops.into_iter().rev() {
+            forwarded.proto = hop.proto.map(|proto| proto.to_lowercase()).or(forwarded.proto);
+            forwarded.host = hop.host.or(forwarded.host);
+            match hop.for_.as_deref().and_then(parse_node) {
+                Some((ip, port)) => forwarded.client = Some((ip, port.unwrap_or(0))),
+                // Obfuscated or unknown, the client can't be told
+                None => break,
+            };
+            if forwarded.client.is_some_and(|(ip, _)| !contains(trusted, ip)) {
+                break;
+            };
+        }
+        forwarded
+    }
+
+    pub fn client_ip(&self) -> Option<IpAddr> {
+        self.client.map(|(ip, _)| ip)
+    }
+
+    // The scheme of the ASGI scope, `ws` or `wss` for websockets
+    pub fn scheme(&self, websocket: bool) -> Option<&'static str> {
+        match (self.proto.as_deref()?, websocket) {
+            ("http" | "ws", false) => Some("http"),
+            ("https" | "wss", false) => Some("https"),
+            ("http" | "ws", true) => Some("ws"),
+            ("https" | "wss", true) => Some("wss"),
+            _ => None,
+        }
+    }
+
+    // The `server` field of the ASGI scope, the port defaults to the one of the scheme
+    pub fn server(&self, scheme: &str) -> Option<(String, Option<u16>)> {
+        let host = self.host.as_deref()?;
+        let default_port = match scheme {
+            "https" | "wss" => 443,
+            _ => 80,
+        };
+        let (name, port) = match host.strip_prefix('[') {
+            Some(rest) => {
+                let (name, port) = rest.split_once(']')?;
+                (name, port.strip_prefix(':'))
+            }
+            None => match host.split_once(':') {
+                Some((name, port)) => (name, Some(port)),
+                None => (host, None),
+            },
+        };
+        let port = match port {
+            Some(port) => port.parse().ok()?,
+            None => default_port,
+        };
+        Some((name.to_owned(), Some(port)))
+    }
+}
+
+fn header_list<'a>(headers: &'a HeaderMap, name: &str) -> impl DoubleEndedIterator<Item = &'a str> {
+    headers
+        .get_all(name)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .collect::<Vec<_>>()
+        .into_iter()
+}
+
+// Elements are separated by commas and pairs by semicolons, values might be quoted
+fn parse_forwarded_header(value: &str) -> Vec<Hop> {
+    let mut hops = vec![Hop::default()];
+    let mut pair = String::new();
+    let mut quoted = false;
+    let mut escaped = false;
+    for c in value.chars().chain([';']) {
+        match c {
+            _ if escaped => {
+                pair.push(c);
+                escaped = false;
+            }
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            ';' | ',' if !quoted => {
+                if let Some((name, value)) = pair.split_once('=') {
+                    let hop = hops.last_mut().expect("There is always a hop");
+                    let value = Some(value.trim().to_owned());
+                    match name.trim().to_lowercase().as_str() {
+                        "for" => hop.for_ = value,
+                        "proto" => hop.proto = value,
+                        "host" => hop.host = value,
+                        _ => (),
+                    };
+                };
+                pair.clear();
+                if c == ',' {
+                    hops.push(Hop::default());
+                };
+            }
+            _ => pair.push(c),
+        };
+    }
+    hops
+}
+
+// A node is an IP address with an optional port, IPv6 addresses are in brackets
+fn parse_node(node: &str) -> Option<(IpAddr, Option<u16>)> {
+    if let Ok(ip) = node.parse::<IpAddr>() {
+        return Some((ip, None));
+    };
+    if let Some(rest) = node.strip_prefix('[') {
+        let (ip, port) = rest.split_once(']')?;
+        let port = match port.strip_prefix(':') {
+            Some(port) => Some(port.parse().ok()?),
+            None => None,
+        };
+        return Some((ip.parse().ok()?, port));
+    };
+    let (ip, port) = node.split_once(':')?;
+    Some((ip.parse().ok()?, Some(port.parse().ok()?)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+
+    use http::HeaderMap;
+
+    use super::{contains, parse_networks, Forwarded};
+    use crate::http::HTTPScope;
+
+    fn ip(value: &str) -> IpAddr {
+        value.parse().unwrap()
+    }
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.append(*name, value.parse().unwrap());
+        }
+        headers
+    }
+
+    #[test]
+    fn test_parse_networks() {
+        let networks = parse_networks("127.0.0.1, 10.0.0.0/8,::1").unwrap();
+        assert!(contains(&networks, ip("127.0.0.1")));
+        assert!(contains(&networks, ip("10.1.2.3")));
+        assert!(contains(&networks, ip("::1")));
+        assert!(contains(&networks, ip("::ffff:10.1.2.3")));
+        assert!(!contains(&networks, ip("127.0.0.2")));
+        assert!(!contains(&networks, ip("11.0.0.1")));
+
+        let all = parse_networks("*").unwrap();
+        assert!(contains(&all, ip("8.8.8.8")));
+        assert!(contains(&all, ip("2001:db8::1")));
+
+        assert!(parse_networks("10.0.0.0/33").is_err());
+        assert!(parse_networks("localhost").is_err());
+    }
+
+    #[test]
+    fn test_x_forwarded_headers() {
+        let trusted = parse_networks("10.0.0.0/8").unwrap();
+        let headers = headers(&[
+            ("x-forwarded-for", "1.1.1.1, 2.2.2.2, 10.0.0.2"),
+            ("x-forwarded-proto", "HTTPS"),
+            ("x-forwarded-host", "example.com"),
+        ]);
+
+        // The closest untrusted hop is the client
+        let forwarded = Forwarded::from_headers(Some(ip("10.0.0.1")), &headers, &trusted);
+        assert!(forwarded.client == Some((ip("2.2.2.2"), 0)));
+        assert!(forwarded.scheme(false) == Some("https"));
+        assert!(forwarded.server("https") == Some((String::from("example.com"), Some(443))));
+
+        // Headers from untrusted peers are ignored
+        let forwarded = Forwarded::from_headers(Some(ip("3.3.3.3")), &headers, &trusted);
+        assert!(forwarded == Forwarded::default());
+    }
+
+    #[test]
+    fn test_x_forwarded_proto_from_client_is_ignored() {
+        let trusted = parse_networks("10.0.0.0/8").unwrap();
+        // The client sent its own proto and host ahead of the ones set by the proxy
+        let spoofed = headers(&[
+            ("x-forwarded-for", "1.1.1.1"),
+            ("x-forwarded-proto", "https"),
+            ("x-forwarded-proto", "http"),
+            ("x-forwarded-host", "evil.com, example.com"),
+        ]);
+
+        let forwarded = Forwarded::from_headers(Some(ip("10.0.0.1")), &spoofed, &trusted);
+        assert!(forwarded.client == Some((ip("1.1.1.1"), 0)));
+        assert!(forwarded.scheme(false) == Some("http"));
+        assert!(forwarded.server("http") == Some((String::from("example.com"), Some(80))));
+
+        // The proto seen by the proxy facing the client wins over the one between proxies
+        let chain = headers(&[
+            ("x-forwarded-for", "2.2.2.2, 10.0.0.2"),
+            ("x-forwarded-proto", "http, https, http"),
+        ]);
+        let forwarded = Forwarded::from_headers(Some(ip("10.0.0.1")), &chain, &trusted);
+        assert!(forwarded.client == Some((ip("2.2.2.2"), 0)));
+        assert!(forwarded.scheme(false) == Some("https"));
+    }
+
+    #[test]
+    fn test_forwarded_header() {
+        let trusted = parse_networks("10.0.0.0/8").unwrap();
+        let headers = headers(&[
+            ("forwarded", r#"for="[2001:db8::1]:4711";proto=https;host="example.com:8443""#),
+            ("forwarded", "for=10.0.0.2;proto=http, For=10.0.0.3"),
+        ]);
+
+        let forwarded = Forwarded::from_headers(Some(ip("10.0.0.1")), &headers, &trusted);
+        assert!(forwarded.client == Some((ip("2001:db8::1"), 4711)));
+        assert!(forwarded.scheme(true) == Some("wss"));
+        assert!(forwarded.server("wss") == Some((String::from("example.com"), Some(8443))));
+    }
+
+    #[test]
+    fn test_forwarded_header_stops_at_unknown_client() {
+        let trusted = parse_networks("10.0.0.0/8").unwrap();
+        let headers = headers(&[("forwarded", "for=1.1.1.1, for=_hidden, for=10.0.0.2")]);
+
+        let forwarded = Forwarded::from_headers(Some(ip("10.0.0.1")), &headers, &trusted);
+        assert!(forwarded.client == Some((ip("10.0.0.2"), 0)));
+    }
+
+    #[test]
+    fn test_unix_socket_peer_needs_wildcard() {
+        let headers = headers(&[("x-forwarded-for", "1.1.1.1")]);
+        let localhost = parse_networks("127.0.0.1").unwrap();
+        assert!(Forwarded::from_headers(None, &headers, &localhost).client.is_none());
+
+        let all = parse_networks("*").unwrap();
+        assert!(Forwarded::from_headers(None, &headers, &all).client == Some((ip("1.1.1.1"), 0)));
+    }
+
+    #[test]
+    fn test_set_forwarded_on_scope() {
+        let request = http::Request::builder().uri("/path").body(String::new()).unwrap();
+        let mut scope = HTTPScope::from_hyper_request(&request, ());
+        scope.client = Some((String::from("10.0.0.1"), 5000));
+        scope.server = Some((String::from("10.0.0.9"), Some(8080)));
+
+        // Without a host the server stays the same
+        scope.set_forwarded(&Forwarded { client: Some((ip("1.1.1.1"), 0)), ..Forwarded::default() });
+        assert!(scope.client == Some((String::from("1.1.1.1"), 0)));
+        assert!(scope.scheme == "http");
+        assert!(scope.server == Some((String::from("10.0.0.9"), Some(8080))));
+
+        let forwarded = Forwarded { proto: Some(String::from("https")), host: Some(String::from("[::1]")), ..Forwarded::default() };
+        scope.set_forwarded(&forwarded);
+        assert!(scope.scheme == "https");
+        assert!(scope.server == Some((String::from("::1"), Some(443))));
+    }
+}
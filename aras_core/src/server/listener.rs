@@ -0,0 +1,519 @@
+use std::io;
+use std::net::SocketAddr;
+#[cfg(unix)]
+use std::os::fd::RawFd;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::{TcpListener, TcpStream};
+#[cfg(unix)]
+use tokio::net::{UnixListener, UnixStream};
+
+use super::config::BindTarget;
+use super::connection_info::{ConnectionInfo, ServerAddr};
+
+// Bind all configured targets, resolving host names to every address they map to
+pub async fn bind_all(targets: &[BindTarget]) -> io::Result<Vec<Listener>> {
+    let mut tcp_addrs: Vec<SocketAddr> = Vec::new();
+    let mut listeners = Vec::new();
+
+    for target in targets {
+        match target {
+            BindTarget::Tcp { host, port } => {
+                // IPv6 literals may be given with brackets, e.g. "[::1]"
+                let host = host.trim_start_matches('[').trim_end_matches(']');
+                for addr in tokio::net::lookup_host((host, *port)).await? {
+                    if !tcp_addrs.contains(&addr) {
+                        tcp_addrs.push(addr);
+                    };
+                }
+            }
+            #[cfg(unix)]
+            BindTarget::Unix(uds) => listeners.push(Listener::bind_unix(&uds.path, uds.mode)?),
+            #[cfg(not(unix))]
+            BindTarget::Unix(_) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "Unix domain sockets are not supported on this platform",
+                ))
+            }
+            #[cfg(unix)]
+            BindTarget::Fd(fd) => listeners.push(Listener::from_fd(*fd)?),
+            #[cfg(unix)]
+            BindTarget::Activated(fd) => listeners.push(Listener::from_activated_fd(*fd)?),
+        }
+    }
+
+    for addr in tcp_addrs.iter() {
+        // The IPv6 wildcard also accepts IPv4 connections, unless the IPv4 wildcard is bound separately
+        let ipv4_wildcard = SocketAddr::new(std::net::Ipv4Addr::UNSPECIFIED.into(), addr.port());
+        let dual_stack = addr.ip().is_unspecified() && !tcp_addrs.contains(&ipv4_wildcard);
+        listeners.push(Listener::bind_tcp(*addr, dual_stack)?);
+    }
+
+    if listeners.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "No addresses to bind to"));
+    };
+    Ok(listeners)
+}
+
+// First file descriptor passed by systemd socket activation (SD_LISTEN_FDS_START)
+#[cfg(unix)]
+const LISTEN_FDS_START: RawFd = 3;
+
+// Sockets passed by systemd socket activation, see sd_listen_fds(3).
+// The variables are removed afterwards so child processes don't pick them up,
+// which is why this has to be called before any other thread is started,
+// in particular before the tokio runtime is built.
+#[cfg(unix)]
+pub fn systemd_listen_fds() -> Option<Vec<BindTarget>> {
+    let pid = std::env::var("LISTEN_PID").ok()?;
+    let fds = std::env::var("LISTEN_FDS").ok()?;
+    for var in ["LISTEN_PID", "LISTEN_FDS", "LISTEN_FDNAMES"] {
+        std::env::remove_var(var);
+    }
+    let fds = parse_listen_fds(&pid, &fds, std::process::id())?;
+    Some(fds.into_iter().map(BindTarget::Activated).collect())
+}
+
+#[cfg(not(unix))]
+pub fn systemd_listen_fds() -> Option<Vec<BindTarget>> {
+    None
+}
+
+// The sockets are only meant for us if `LISTEN_PID` matches our own pid
+#[cfg(unix)]
+fn parse_listen_fds(listen_pid: &str, listen_fds: &str, own_pid: u32) -> Option<Vec<RawFd>> {
+    if listen_pid.trim().parse::<u32>().ok()? != own_pid {
+        return None;
+    };
+    let count = listen_fds.trim().parse::<RawFd>().ok().filter(|n| *n > 0)?;
+    Some((LISTEN_FDS_START..LISTEN_FDS_START + count).collect())
+}
+
+// A bound socket the server accepts connections on
+pub enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix {
+        listener: UnixListener,
+        path: PathBuf,
+        // Only set if we created the socket file, inherited sockets are left alone
+        _guard: Option<UnixSocketGuard>,
+    },
+}
+
+impl Listener {
+    pub fn bind_tcp(addr: SocketAddr, dual_stack: bool) -> io::Result<Self> {
+        let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+        #[cfg(unix)]
+        socket.set_reuse_address(true)?;
+        if addr.is_ipv6() {
+            socket.set_only_v6(!dual_stack)?;
+        };
+        socket.bind(&addr.into())?;
+        socket.listen(1024)?;
+        socket.set_nonblocking(true)?;
+        Ok(Self::Tcp(TcpListener::from_std(socket.into())?))
+    }
+
+    // Bind to a unix domain socket, removing a stale socket file left behind by
+    // a previous run. Fails if another process is still listening on the path.
+    #[cfg(unix)]
+    pub fn bind_unix(path: &Path, mode: Option<u32>) -> io::Result<Self> {
+        use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+
+        if let Ok(metadata) = std::fs::symlink_metadata(path) {
+            if !metadata.file_type().is_socket() {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!("{} exists and is not a socket", path.display()),
+                ));
+            };
+            match std::os::unix::net::UnixStream::connect(path) {
+                Ok(_) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::AddrInUse,
+                        format!("{} is in use by another process", path.display()),
+                    ))
+                }
+                Err(_) => std::fs::remove_file(path)?,
+            }
+        };
+
+        let listener = UnixListener::bind(path)?;
+        let guard = UnixSocketGuard(path.to_path_buf());
+        if let Some(mode) = mode {
+            std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))?;
+        };
+        Ok(Self::Unix { listener, path: path.to_path_buf(), _guard: Some(guard) })
+    }
+
+    // Use a listening socket inherited from the parent process. The descriptor is
+    // duplicated (close-on-exec), the original is left open for its owner.
+    #[cfg(unix)]
+    pub fn from_fd(fd: RawFd) -> io::Result<Self> {
+        use std::os::fd::BorrowedFd;
+
+        check_listening_socket(fd)?;
+        // Safety: the descriptor was just checked to be an open socket, it is only borrowed to duplicate it
+        let owned = unsafe { BorrowedFd::borrow_raw(fd) }.try_clone_to_owned()?;
+        Self::from_socket(fd, Socket::from(owned))
+    }
+
+    // Take over a socket passed by systemd socket activation, nothing else in this
+    // process owns it. It is closed together with the listener.
+    #[cfg(unix)]
+    pub fn from_activated_fd(fd: RawFd) -> io::Result<Self> {
+        use std::os::fd::{FromRawFd, OwnedFd};
+
+        check_listening_socket(fd)?;
+        // Safety: the descriptor is an open socket passed to this process only, and
+        // `systemd_listen_fds` hands it out once
+        let owned = unsafe { OwnedFd::from_raw_fd(fd) };
+        Self::from_socket(fd, Socket::from(owned))
+    }
+
+    #[cfg(unix)]
+    fn from_socket(fd: RawFd, socket: Socket) -> io::Result<Self> {
+        // Activated sockets are inherited without close-on-exec, don't leak them to child processes
+        socket.set_cloexec(true)?;
+        socket.set_nonblocking(true)?;
+        match socket.domain()? {
+            Domain::IPV4 | Domain::IPV6 => Ok(Self::Tcp(TcpListener::from_std(socket.into())?)),
+            Domain::UNIX => {
+                let listener: std::os::unix::net::UnixListener = socket.into();
+                let path = listener.local_addr()?.as_pathname().map(Path::to_path_buf).unwrap_or_default();
+                Ok(Self::Unix { listener: UnixListener::from_std(listener)?, path, _guard: None })
+            }
+            _ => Err(invalid_fd(fd, "has an unsupported address family")),
+        }
+    }
+
+    pub fn local_addr(&self) -> io::Result<ServerAddr> {
+        match self {
+            Self::Tcp(listener) => Ok(ServerAddr::Tcp(listener.local_addr()?)),
+            #[cfg(unix)]
+            Self::Unix { path, .. } => Ok(ServerAddr::Unix(path.clone())),
+        }
+    }
+
+    pub async fn accept(&self) -> io::Result<(Stream, ConnectionInfo)> {
+        match self {
+            Self::Tcp(listener) => {
+                let (stream, client) = listener.accept().await?;
+                let server = ServerAddr::Tcp(stream.local_addr()?);
+                Ok((Stream::Tcp(stream), ConnectionInfo::new(Some(client), server)))
+            }
+            #[cfg(unix)]
+            Self::Unix { listener, path, .. } => {
+                let (stream, _) = listener.accept().await?;
+                let server = ServerAddr::Unix(path.clone());
+                Ok((Stream::Unix(stream), ConnectionInfo::new(None, server)))
+            }
+        }
+    }
+}
+
+// Inherited descriptors are checked without taking them over, so one that is
+// not open or not a listening socket is left alone
+#[cfg(unix)]
+fn check_listening_socket(fd: RawFd) -> io::Result<()> {
+    use std::os::fd::BorrowedFd;
+
+    if fd < 0 {
+        return Err(invalid_fd(fd, "is invalid"));
+    };
+    // Safety: the descriptor is only borrowed for these checks, an fd that is not open
+    // makes them fail with EBADF
+    let borrowed = unsafe { BorrowedFd::borrow_raw(fd) };
+    let socket = socket2::SockRef::from(&borrowed);
+    if socket.r#type().map_err(|_| invalid_fd(fd, "is not an open socket"))? != Type::STREAM {
+        return Err(invalid_fd(fd, "is not a stream socket"));
+    };
+    if socket.local_addr()?.is_unnamed() {
+        return Err(invalid_fd(fd, "is not bound to an address"));
+    };
+    // SO_ACCEPTCONN is not available everywhere, accepting fails later on the others
+    #[cfg(any(target_os = "android", target_os = "freebsd", target_os = "linux"))]
+    if !socket.is_listener()? {
+        return Err(invalid_fd(fd, "is not listening"));
+    };
+    Ok(())
+}
+
+#[cfg(unix)]
+fn invalid_fd(fd: RawFd, msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, format!("File descriptor {fd} {msg}"))
+}
+
+// Removes the socket file once the listener is dropped
+#[cfg(unix)]
+pub struct UnixSocketGuard(PathBuf);
+
+#[cfg(unix)]
+impl Drop for UnixSocketGuard {
+    fn drop(&mut self) {
+        _ = std::fs::remove_file(&self.0);
+    }
+}
+
+// A connection accepted by a `Listener`
+pub enum Stream {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl AsyncRead for Stream {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Self::Tcp(s) => Pin::new(s).poll_read(cx, buf),
+            #[cfg(unix)]
+            Self::Unix(s) => Pin::new(s).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for Stream {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Self::Tcp(s) => Pin::new(s).poll_write(cx, buf),
+            #[cfg(unix)]
+            Self::Unix(s) => Pin::new(s).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Self::Tcp(s) => Pin::new(s).poll_write_vectored(cx, bufs),
+            #[cfg(unix)]
+            Self::Unix(s) => Pin::new(s).poll_write_vectored(cx, bufs),
+        }
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        match self {
+            Self::Tcp(s) => s.is_write_vectored(),
+            #[cfg(unix)]
+            Self::Unix(s) => s.is_write_vectored(),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Self::Tcp(s) => Pin::new(s).poll_flush(cx),
+            #[cfg(unix)]
+            Self::Unix(s) => Pin::new(s).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Self::Tcp(s) => Pin::new(s).poll_shutdown(cx),
+            #[cfg(unix)]
+            Self::Unix(s) => Pin::new(s).poll_shutdown(cx),
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::os::unix::fs::PermissionsExt;
+
+    use std::os::fd::{AsRawFd, IntoRawFd};
+
+    use super::{bind_all, parse_listen_fds, Listener};
+    use crate::server::config::BindTarget;
+    use crate::server::connection_info::ServerAddr;
+
+    fn tcp_port(listener: &Listener) -> u16 {
+        match listener.local_addr().unwrap() {
+            ServerAddr::Tcp(addr) => addr.port(),
+            ServerAddr::Unix(_) => panic!("Expected a TCP listener"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_bind_ipv4_and_ipv6() {
+        let ipv4 = Listener::bind_tcp("127.0.0.1:0".parse().unwrap(), false).unwrap();
+        let port = tcp_port(&ipv4);
+        let targets = vec![BindTarget::tcp("[::1]", port)];
+        let ipv6 = match bind_all(&targets).await {
+            Ok(l) => l,
+            Err(_) => return, // IPv6 not available in this environment
+        };
+
+        assert!(ipv6.len() == 1);
+        let _ipv4_client = tokio::net::TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+        let (_, conn_info) = ipv4.accept().await.unwrap();
+        assert!(conn_info.scope_server() == Some(("127.0.0.1".to_string(), Some(port))));
+
+        let _ipv6_client = tokio::net::TcpStream::connect(("::1", port)).await.unwrap();
+        let (_, conn_info) = ipv6[0].accept().await.unwrap();
+        assert!(conn_info.scope_server() == Some(("::1".to_string(), Some(port))));
+    }
+
+    #[tokio::test]
+    async fn test_bind_hostname() {
+        let targets = vec![BindTarget::tcp("localhost", 0)];
+        let listeners = bind_all(&targets).await.unwrap();
+        assert!(!listeners.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_wildcard_reports_local_address() {
+        let listener = Listener::bind_tcp("0.0.0.0:0".parse().unwrap(), false).unwrap();
+        let port = tcp_port(&listener);
+
+        let _client = tokio::net::TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+        let (_, conn_info) = listener.accept().await.unwrap();
+        assert!(conn_info.scope_server() == Some(("127.0.0.1".to_string(), Some(port))));
+    }
+
+    #[tokio::test]
+    async fn test_inherited_tcp_fd() {
+        let std_listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = std_listener.local_addr().unwrap().port();
+        let fd = std_listener.into_raw_fd();
+
+        let listeners = bind_all(&[BindTarget::Fd(fd)]).await.unwrap();
+        assert!(tcp_port(&listeners[0]) == port);
+
+        let _client = tokio::net::TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+        let (_, conn_info) = listeners[0].accept().await.unwrap();
+        assert!(conn_info.scope_server() == Some(("127.0.0.1".to_string(), Some(port))));
+    }
+
+    #[tokio::test]
+    async fn test_activated_fd_is_taken_over() {
+        let std_listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let fd = std_listener.into_raw_fd();
+
+        let listeners = bind_all(&[BindTarget::Activated(fd)]).await.unwrap();
+        assert!(matches!(&listeners[0], Listener::Tcp(listener) if listener.as_raw_fd() == fd));
+
+        let inherited = Listener::from_fd(fd).unwrap();
+        assert!(matches!(&inherited, Listener::Tcp(listener) if listener.as_raw_fd() != fd));
+    }
+
+    #[tokio::test]
+    async fn test_inherited_unix_fd() {
+        let path = socket_path("inherited");
+        let std_listener = std::os::unix::net::UnixListener::bind(&path).unwrap();
+
+        let listener = Listener::from_fd(std_listener.as_raw_fd()).unwrap();
+        assert!(matches!(listener.local_addr().unwrap(), ServerAddr::Unix(p) if p == path));
+
+        let _client = tokio::net::UnixStream::connect(&path).await.unwrap();
+        assert!(listener.accept().await.is_ok());
+
+        // The socket file belongs to whoever created it
+        drop(listener);
+        assert!(path.exists());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_inherited_fd_not_a_socket() {
+        let file = std::fs::File::open("/dev/null").unwrap();
+        let result = Listener::from_fd(file.as_raw_fd());
+        assert!(result.is_err_and(|e| e.kind() == std::io::ErrorKind::InvalidInput));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_inherited_fd_not_listening() {
+        let socket = socket2::Socket::new(socket2::Domain::IPV4, socket2::Type::STREAM, None).unwrap();
+        socket.bind(&"127.0.0.1:0".parse::<std::net::SocketAddr>().unwrap().into()).unwrap();
+        let result = Listener::from_fd(socket.as_raw_fd());
+        assert!(result.is_err_and(|e| e.kind() == std::io::ErrorKind::InvalidInput));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_activated_fd_is_closed_on_exec() {
+        let std_listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        socket2::SockRef::from(&std_listener).set_cloexec(false).unwrap();
+        let fd = std_listener.into_raw_fd();
+
+        let listener = Listener::from_activated_fd(fd).unwrap();
+        // O_CLOEXEC shows up in the octal file status flags
+        let fdinfo = std::fs::read_to_string(format!("/proc/self/fdinfo/{fd}")).unwrap();
+        let flags = fdinfo.lines().find_map(|line| line.strip_prefix("flags:")).unwrap();
+        assert!(u32::from_str_radix(flags.trim(), 8).unwrap() & 0o2000000 != 0);
+        drop(listener);
+    }
+
+    #[tokio::test]
+    async fn test_inherited_fd_closed() {
+        assert!(Listener::from_fd(-1).is_err());
+    }
+
+    #[test]
+    fn test_parse_listen_fds() {
+        assert!(parse_listen_fds("42", "2", 42) == Some(vec![3, 4]));
+        assert!(parse_listen_fds("41", "2", 42).is_none());
+        assert!(parse_listen_fds("42", "0", 42).is_none());
+        assert!(parse_listen_fds("42", "x", 42).is_none());
+    }
+
+    fn socket_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("aras-{name}-{}.sock", std::process::id()))
+    }
+
+    #[tokio::test]
+    async fn test_unix_socket_accept() {
+        let path = socket_path("accept");
+        let listener = Listener::bind_unix(&path, Some(0o660)).unwrap();
+        assert!(std::fs::metadata(&path).unwrap().permissions().mode() & 0o777 == 0o660);
+
+        let _client = tokio::net::UnixStream::connect(&path).await.unwrap();
+        let (_, conn_info) = listener.accept().await.unwrap();
+
+        assert!(conn_info.scope_client().is_none());
+        assert!(conn_info.scope_server() == Some((path.to_string_lossy().into_owned(), None)));
+        assert!(matches!(conn_info.server, ServerAddr::Unix(_)));
+
+        drop(listener);
+        assert!(!path.exists());
+    }
+
+    #[tokio::test]
+    async fn test_unix_socket_stale_cleanup() {
+        let path = socket_path("stale");
+        // Leave a socket file behind without anyone listening on it
+        let stale = std::os::unix::net::UnixListener::bind(&path).unwrap();
+        drop(stale);
+        assert!(path.exists());
+
+        let listener = Listener::bind_unix(&path, None);
+        assert!(listener.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_unix_socket_in_use() {
+        let path = socket_path("in-use");
+        let _listener = Listener::bind_unix(&path, None).unwrap();
+
+        let result = Listener::bind_unix(&path, None);
+        assert!(result.is_err_and(|e| e.kind() == std::io::ErrorKind::AddrInUse));
+    }
+
+    #[tokio::test]
+    async fn test_unix_socket_refuses_regular_file() {
+        let path = socket_path("regular-file");
+        std::fs::write(&path, "not a socket").unwrap();
+
+        let result = Listener::bind_unix(&path, None);
+        assert!(result.is_err_and(|e| e.kind() == std::io::ErrorKind::AlreadyExists));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,293 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use http_body_util::{BodyExt, Full};
+use hyper::body::Incoming;
+use hyper::server::conn::http1;
+use hyper::Request;
+use hyper_util::rt::TokioIo;
+use log::{error, info};
+
+use super::listener::Listener;
+use crate::error::Result;
+use crate::middleware_services::QueueMetrics;
+use crate::types::{text_response, Response};
+
+// Upper bounds of the request duration histogram, in seconds
+const DURATION_BUCKETS: [f64; 11] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+// Methods are reported as they are, anything else as `OTHER` to keep the number of series bounded
+const METHODS: [&str; 9] = ["GET", "HEAD", "POST", "PUT", "DELETE", "CONNECT", "OPTIONS", "TRACE", "PATCH"];
+
+// Server wide counters, rendered in the Prometheus text format
+#[derive(Debug, Clone, Default)]
+pub struct Metrics(Arc<Counters>);
+
+#[derive(Debug, Default)]
+struct Counters {
+    requests: Mutex<BTreeMap<(&'static str, u16), u64>>,
+    duration: Histogram,
+    in_flight: AtomicUsize,
+    connections: AtomicUsize,
+    websockets: AtomicUsize,
+    payload_too_large: AtomicU64,
+    timeouts: AtomicU64,
+    app_errors: AtomicU64,
+    access_log_dropped: AtomicU64,
+    queue: QueueMetrics,
+}
+
+#[derive(Debug, Default)]
+struct Histogram {
+    // Not cumulative, summed up when rendered
+    buckets: [AtomicU64; DURATION_BUCKETS.len()],
+    count: AtomicU64,
+    sum_micros: AtomicU64,
+}
+
+impl Histogram {
+    fn observe(&self, duration: Duration) {
+        let secs = duration.as_secs_f64();
+        if let Some(bucket) = DURATION_BUCKETS.iter().position(|bound| secs <= *bound) {
+            self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        };
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_micros.fetch_add(duration.as_micros() as u64, Ordering::Relaxed);
+    }
+}
+
+impl Metrics {
+    // Queue in front of the concurrency limit, its refusals are the 503s
+    pub fn queue(&self) -> QueueMetrics {
+        self.0.queue.clone()
+    }
+
+    pub(crate) fn connection_opened(&self) -> Tracked {
+        self.track(|counters| &counters.connections)
+    }
+
+    pub(crate) fn websocket_opened(&self) -> Tracked {
+        self.track(|counters| &counters.websockets)
+    }
+
+    pub(crate) fn request_started(&self, method: &http::Method) -> RequestTimer {
+        RequestTimer {
+            _in_flight: self.track(|counters| &counters.in_flight),
+            metrics: self.clone(),
+            method: METHODS.into_iter().find(|known| *known == method.as_str()).unwrap_or("OTHER"),
+            start: Instant::now(),
+            status: None,
+        }
+    }
+
+    pub(crate) fn record_payload_too_large(&self) {
+        self.0.payload_too_large.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_timeout(&self) {
+        self.0.timeouts.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_app_error(&self) {
+        self.0.app_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_access_log_dropped(&self) {
+        self.0.access_log_dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn access_log_dropped_total(&self) -> u64 {
+        self.0.access_log_dropped.load(Ordering::Relaxed)
+    }
+
+    fn track(&self, gauge: fn(&Counters) -> &AtomicUsize) -> Tracked {
+        gauge(&self.0).fetch_add(1, Ordering::Relaxed);
+        Tracked { counters: self.0.clone(), gauge }
+    }
+
+    pub fn render(&self) -> String {
+        let counters = &self.0;
+        let mut out = String::new();
+
+        header(&mut out, "aras_requests_total", "counter", "Completed HTTP requests");
+        let requests = counters.requests.lock().expect("Metrics lock poisoned").clone();
+        for ((method, status), count) in requests {
+            _ = writeln!(out, "aras_requests_total{{method=\"{method}\",status=\"{status}\"}} {count}");
+        }
+
+        header(&mut out, "aras_request_duration_seconds", "histogram", "Time until the response body was sent");
+        let mut cumulative = 0;
+        for (bound, bucket) in DURATION_BUCKETS.iter().zip(counters.duration.buckets.iter()) {
+            cumulative += bucket.load(Ordering::Relaxed);
+            _ = writeln!(out, "aras_request_duration_seconds_bucket{{le=\"{bound}\"}} {cumulative}");
+        }
+        let count = counters.duration.count.load(Ordering::Relaxed);
+        let sum = counters.duration.sum_micros.load(Ordering::Relaxed) as f64 / 1_000_000.0;
+        _ = writeln!(out, "aras_request_duration_seconds_bucket{{le=\"+Inf\"}} {count}");
+        _ = writeln!(out, "aras_request_duration_seconds_sum {sum}");
+        _ = writeln!(out, "aras_request_duration_seconds_count {count}");
+
+        let gauges = [
+            ("aras_requests_in_flight", "Requests being served", &counters.in_flight),
+            ("aras_connections_active", "Open client connections", &counters.connections),
+            ("aras_websockets_active", "Open websocket connections", &counters.websockets),
+        ];
+        for (name, help, value) in gauges {
+            header(&mut out, name, "gauge", help);
+            _ = writeln!(out, "{name} {}", value.load(Ordering::Relaxed));
+        }
+        header(&mut out, "aras_queue_depth", "gauge", "Requests waiting for the concurrency limit");
+        _ = writeln!(out, "aras_queue_depth {}", counters.queue.depth());
+
+        let totals = [
+            ("aras_queued_requests_total", "Requests that waited for the concurrency limit", counters.queue.queued_total()),
+            (
+                "aras_payload_too_large_total",
+                "Requests refused with a 413 because of their body size",
+                counters.payload_too_large.load(Ordering::Relaxed),
+            ),
+            ("aras_request_timeouts_total", "Requests that timed out", counters.timeouts.load(Ordering::Relaxed)),
+            ("aras_app_errors_total", "Requests failed by the application", counters.app_errors.load(Ordering::Relaxed)),
+            (
+                "aras_access_log_dropped_total",
+                "Access log lines dropped because the writer fell behind",
+                counters.access_log_dropped.load(Ordering::Relaxed),
+            ),
+        ];
+        for (name, help, value) in totals {
+            header(&mut out, name, "counter", help);
+            _ = writeln!(out, "{name} {value}");
+        }
+        header(&mut out, "aras_concurrency_rejected_total", "counter", "Requests refused with a 503 by the concurrency limit");
+        _ = writeln!(out, "aras_concurrency_rejected_total{{reason=\"queue_full\"}} {}", counters.queue.rejected_total());
+        _ = writeln!(out, "aras_concurrency_rejected_total{{reason=\"queue_timeout\"}} {}", counters.queue.timed_out_total());
+        out
+    }
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    _ = writeln!(out, "# HELP {name} {help}");
+    _ = writeln!(out, "# TYPE {name} {kind}");
+}
+
+// Keeps a gauge raised until dropped
+pub(crate) struct Tracked {
+    counters: Arc<Counters>,
+    gauge: fn(&Counters) -> &AtomicUsize,
+}
+
+impl Drop for Tracked {
+    fn drop(&mut self) {
+        (self.gauge)(&self.counters).fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+// Records the request once dropped, which is when the response body is sent.
+// Requests that got no response at all are not counted.
+pub(crate) struct RequestTimer {
+    _in_flight: Tracked,
+    metrics: Metrics,
+    method: &'static str,
+    start: Instant,
+    pub(crate) status: Option<u16>,
+}
+
+impl Drop for RequestTimer {
+    fn drop(&mut self) {
+        let Some(status) = self.status else {
+            return;
+        };
+        let counters = &self.metrics.0;
+        *counters
+            .requests
+            .lock()
+            .expect("Metrics lock poisoned")
+            .entry((self.method, status))
+            .or_default() += 1;
+        counters.duration.observe(self.start.elapsed());
+    }
+}
+
+pub(crate) fn metrics_response(metrics: &Metrics) -> Result<Response> {
+    let body_text = metrics.render();
+    let response = hyper::Response::builder()
+        .status(200)
+        .header(hyper::header::CONTENT_LENGTH, body_text.len())
+        .header(hyper::header::CONTENT_TYPE, "text/plain; version=0.0.4; charset=utf-8")
+        .body(Full::new(body_text.into()).map_err(|never| match never {}).boxed());
+    Ok(response?)
+}
+
+fn not_found() -> Result<Response> {
+    text_response(hyper::Response::builder().status(404), "Not Found")
+}
+
+// Separate listener that only serves the metrics, out of reach of the application
+pub(crate) async fn serve_metrics(listener: Listener, path: String, metrics: Metrics) -> Result<()> {
+    info!("Serving metrics on http://{}{path}", listener.local_addr()?);
+    let path = Arc::new(path);
+    loop {
+        let (stream, conn_info) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                error!("Failed to connect to metrics client: {e}");
+                continue;
+            }
+        };
+        let path = path.clone();
+        let metrics = metrics.clone();
+        tokio::task::spawn(async move {
+            let svc = hyper::service::service_fn(move |req: Request<Incoming>| {
+                let response = match req.uri().path() == path.as_str() {
+                    true => metrics_response(&metrics),
+                    false => not_found(),
+                };
+                async move { response }
+            });
+            if let Err(e) = http1::Builder::new().serve_connection(TokioIo::new(stream), svc).await {
+                error!("Error serving metrics to {conn_info}: {e}");
+            };
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::Metrics;
+
+    #[test]
+    fn test_render() {
+        let metrics = Metrics::default();
+        let connection = metrics.connection_opened();
+        let mut request = metrics.request_started(&http::Method::GET);
+        request.status = Some(200);
+
+        let rendered = metrics.render();
+        assert!(rendered.contains("aras_connections_active 1\n"));
+        assert!(rendered.contains("aras_requests_in_flight 1\n"));
+
+        drop(request);
+        drop(connection);
+        let mut unanswered = metrics.request_started(&http::Method::from_bytes(b"PURGE").unwrap());
+        unanswered.status = Some(405);
+        drop(unanswered);
+        // Dropped without a response, not counted
+        drop(metrics.request_started(&http::Method::POST));
+        metrics.0.duration.observe(Duration::from_millis(300));
+
+        let rendered = metrics.render();
+        assert!(rendered.contains("aras_connections_active 0\n"));
+        assert!(rendered.contains("aras_requests_in_flight 0\n"));
+        assert!(rendered.contains("aras_requests_total{method=\"GET\",status=\"200\"} 1\n"));
+        assert!(rendered.contains("aras_requests_total{method=\"OTHER\",status=\"405\"} 1\n"));
+        assert!(!rendered.contains("method=\"POST\""));
+        assert!(rendered.contains("aras_request_duration_seconds_bucket{le=\"0.25\"} 2\n"));
+        assert!(rendered.contains("aras_request_duration_seconds_bucket{le=\"0.5\"} 3\n"));
+        assert!(rendered.contains("aras_request_duration_seconds_count 3\n"));
+    }
+}
@@ -1,8 +1,25 @@
 mod config;
 mod connection_info;
+pub(crate) mod forwarded;
+mod listener;
+pub(crate) mod metrics;
+mod proxy_protocol;
+#[allow(clippy::module_inception)]
 mod server;
 mod service;
+mod shutdown;
+mod tls;
+mod write_timeout;
 
 pub use server::Server;
-pub use config::ServerConfig;
-pub use connection_info::ConnectionInfo;
\ No newline at end of file
+pub use config::{
+    AccessLogConfig, AccessLogFormat, AccessLogTarget, BindTarget, BodySizeLimits, CompressionConfig, ConcurrencyQueue,
+    ContentEncoding, HttpProtocol, MetricsConfig, RateLimit, ServerConfig, StaticMount, Timeouts, UnixSocketConfig,
+};
+pub use connection_info::ConnectionInfo;
+pub use forwarded::{parse_networks, Forwarded};
+pub use listener::systemd_listen_fds;
+pub use metrics::Metrics;
+pub use proxy_protocol::ProxyProtocol;
+pub use shutdown::{ShutdownHandle, ShutdownWatcher};
+pub use tls::{ClientCertMode, TlsConfig};
\ No newline at end of file
@@ -0,0 +1,292 @@
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::pin::Pin;
+use std::str::FromStr;
+use std::task::{Context, Poll};
+
+use bytes::{Buf, Bytes, BytesMut};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, ReadBuf};
+
+use crate::error::Error;
+
+// Whether connections start with a PROXY protocol header (v1 or v2) from a load balancer,
+// see https://www.haproxy.org/download/2.9/doc/proxy-protocol.txt
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProxyProtocol {
+    None,
+    // Connections without a header are served as they are
+    Optional,
+    // Connections without a valid header are closed
+    Required,
+}
+
+impl FromStr for ProxyProtocol {
+    type Err = Error;
+
+    fn from_str(value: &str) -> std::result::Result<Self, Self::Err> {
+        match value.to_lowercase().as_str() {
+            "none" => Ok(Self::None),
+            "optional" => Ok(Self::Optional),
+            "required" => Ok(Self::Required),
+            _ => Err(Error::custom(format!(
+                "Invalid PROXY protocol mode '{value}', expected one of 'none', 'optional' or 'required'"
+            ))),
+        }
+    }
+}
+
+const V1_PREFIX: &[u8] = b"PROXY ";
+// Including the CRLF
+const V1_MAX_LENGTH: usize = 107;
+const V2_SIGNATURE: &[u8] = b"\r\n\r\n\0\r\nQUIT\n";
+const V2_HEADER_LENGTH: usize = 16;
+
+// Source and destination address of the connection the proxy accepted
+pub type ProxyAddrs = (SocketAddr, SocketAddr);
+
+#[derive(Debug, PartialEq)]
+enum Parsed {
+    // More bytes are needed to tell
+    Incomplete,
+    // The stream doesn't start with a PROXY header
+    Missing,
+    Invalid(&'static str),
+    // Length of the header and the addresses it carries, `None` for connections the
+    // proxy made itself (health checks) or unknown protocols
+    Complete(usize, Option<ProxyAddrs>),
+}
+
+// Read the PROXY header at the start of the stream. Bytes read past the header are
+// handed back by the returned stream.
+pub async fn read_proxy_header<T>(mut stream: T, mode: ProxyProtocol) -> io::Result<(Option<ProxyAddrs>, Rewind<T>)>
+where
+    T: AsyncRead + Unpin,
+{
+    let mut buf = BytesMut::new();
+    if mode == ProxyProtocol::None {
+        return Ok((None, Rewind::new(stream, buf.freeze())));
+    };
+    loop {
+        match parse(&buf) {
+            Parsed::Incomplete => {
+                if stream.read_buf(&mut buf).await? == 0 {
+                    return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Connection closed before the PROXY header"));
+                };
+            }
+            Parsed::Missing if mode == ProxyProtocol::Optional => return Ok((None, Rewind::new(stream, buf.freeze()))),
+            Parsed::Missing => return Err(invalid_data("Missing PROXY protocol header")),
+            Parsed::Invalid(msg) => return Err(invalid_data(msg)),
+            Parsed::Complete(length, addrs) => {
+                buf.advance(length);
+                return Ok((addrs, Rewind::new(stream, buf.freeze())));
+            }
+        };
+    }
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("Invalid PROXY protocol header. {msg}"))
+}
+
+fn parse(buf: &[u8]) -> Parsed {
+    let starts_with = |prefix: &[u8]| buf[..buf.len().min(prefix.len())] == prefix[..buf.len().min(prefix.len())];
+    if buf.is_empty() {
+        Parsed::Incomplete
+    } else if starts_with(V2_SIGNATURE) {
+        parse_v2(buf)
+    } else if starts_with(V1_PREFIX) {
+        parse_v1(buf)
+    } else {
+        Parsed::Missing
+    }
+}
+
+// PROXY TCP4 192.0.2.1 192.0.2.2 56324 443\r\n
+fn parse_v1(buf: &[u8]) -> Parsed {
+    let Some(end) = buf.windows(2).take(V1_MAX_LENGTH - 1).position(|w| w == b"\r\n") else {
+        return match buf.len() < V1_MAX_LENGTH {
+            true => Parsed::Incomplete,
+            false => Parsed::Invalid("Header line is too long"),
+        };
+    };
+    let Ok(line) = std::str::from_utf8(&buf[V1_PREFIX.len()..end]) else {
+        return Parsed::Invalid("Header line is not ASCII");
+    };
+    let parts: Vec<&str> = line.split(' ').collect();
+    let addrs = match parts[..] {
+        ["UNKNOWN", ..] => None,
+        [protocol @ ("TCP4" | "TCP6"), source, destination, source_port, destination_port] => {
+            let ip = |value: &str| match protocol {
+                "TCP4" => value.parse::<Ipv4Addr>().ok().map(IpAddr::from),
+                _ => value.parse::<Ipv6Addr>().ok().map(IpAddr::from),
+            };
+            let (Some(source), Some(destination), Ok(source_port), Ok(destination_port)) =
+                (ip(source), ip(destination), source_port.parse::<u16>(), destination_port.parse::<u16>())
+            else {
+                return Parsed::Invalid("Malformed addresses");
+            };
+            Some((SocketAddr::new(source, source_port), SocketAddr::new(destination, destination_port)))
+        }
+        _ => return Parsed::Invalid("Unsupported protocol"),
+    };
+    Parsed::Complete(end + 2, addrs)
+}
+
+// Binary header: signature, version and command, family, length, then the addresses and TLVs
+fn parse_v2(buf: &[u8]) -> Parsed {
+    if buf.len() < V2_HEADER_LENGTH {
+        return Parsed::Incomplete;
+    };
+    let length = V2_HEADER_LENGTH + usize::from(u16::from_be_bytes([buf[14], buf[15]]));
+    if buf.len() < length {
+        return Parsed::Incomplete;
+    };
+    if buf[12] >> 4 != 2 {
+        return Parsed::Invalid("Unsupported version");
+    };
+    let addresses = &buf[V2_HEADER_LENGTH..length];
+    let addrs = match (buf[12] & 0x0F, buf[13]) {
+        // LOCAL, connections made by the proxy itself
+        (0x0, _) => None,
+        // PROXY over TCP/IPv4
+        (0x1, 0x11) if addresses.len() >= 12 => {
+            let ip = |at: usize| IpAddr::from(<[u8; 4]>::try_from(&addresses[at..at + 4]).expect("Slice of 4 bytes"));
+            let port = |at: usize| u16::from_be_bytes([addresses[at], addresses[at + 1]]);
+            Some((SocketAddr::new(ip(0), port(8)), SocketAddr::new(ip(4), port(10))))
+        }
+        // PROXY over TCP/IPv6
+        (0x1, 0x21) if addresses.len() >= 36 => {
+            let ip = |at: usize| IpAddr::from(<[u8; 16]>::try_from(&addresses[at..at + 16]).expect("Slice of 16 bytes"));
+            let port = |at: usize| u16::from_be_bytes([addresses[at], addresses[at + 1]]);
+            Some((SocketAddr::new(ip(0), port(32)), SocketAddr::new(ip(16), port(34))))
+        }
+        (0x1, 0x11 | 0x21) => return Parsed::Invalid("Addresses are truncated"),
+        // Unspecified, UDP or unix sockets, the connection is served as is
+        (0x1, _) => None,
+        _ => return Parsed::Invalid("Unsupported command"),
+    };
+    Parsed::Complete(length, addrs)
+}
+
+// Replays bytes that were read ahead before reading from the stream again
+pub struct Rewind<T> {
+    prefix: Bytes,
+    inner: T,
+}
+
+impl<T> Rewind<T> {
+    pub fn new(inner: T, prefix: Bytes) -> Self {
+        Self { prefix, inner }
+    }
+}
+
+impl<T: AsyncRead + Unpin> AsyncRead for Rewind<T> {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if !this.prefix.is_empty() {
+            let length = this.prefix.len().min(buf.remaining());
+            buf.put_slice(&this.prefix.split_to(length));
+            return Poll::Ready(Ok(()));
+        };
+        Pin::new(&mut this.inner).poll_read(cx, buf)
+    }
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for Rewind<T> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().inner).poll_write(cx, buf)
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().inner).poll_write_vectored(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.inner.is_write_vectored()
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::{parse, read_proxy_header, Parsed, ProxyProtocol};
+
+    fn addr(value: &str) -> SocketAddr {
+        value.parse().unwrap()
+    }
+
+    #[test]
+    fn test_parse_v1() {
+        let header = b"PROXY TCP4 192.0.2.1 192.0.2.2 56324 443\r\nGET /";
+        assert!(parse(header) == Parsed::Complete(42, Some((addr("192.0.2.1:56324"), addr("192.0.2.2:443")))));
+
+        let header = b"PROXY TCP6 2001:db8::1 2001:db8::2 56324 443\r\n";
+        assert!(parse(header) == Parsed::Complete(header.len(), Some((addr("[2001:db8::1]:56324"), addr("[2001:db8::2]:443")))));
+
+        assert!(parse(b"PROXY UNKNOWN\r\n") == Parsed::Complete(15, None));
+        assert!(parse(b"PROXY TCP4 192.0.2.1") == Parsed::Incomplete);
+        assert!(matches!(parse(b"PROXY TCP4 2001:db8::1 192.0.2.2 1 2\r\n"), Parsed::Invalid(_)));
+        assert!(matches!(parse(&[b'P'; 200]), Parsed::Missing));
+        assert!(matches!(parse(&[&b"PROXY "[..], &[b'X'; 200]].concat()), Parsed::Invalid(_)));
+    }
+
+    #[test]
+    fn test_parse_v2() {
+        let mut header = b"\r\n\r\n\0\r\nQUIT\n\x21\x11\x00\x0F".to_vec();
+        header.extend([192, 0, 2, 1, 192, 0, 2, 2, 0xDC, 0x04, 0x01, 0xBB]);
+        // A TLV that is skipped
+        header.extend([0x04, 0x00, 0x00]);
+        assert!(parse(&header) == Parsed::Complete(31, Some((addr("192.0.2.1:56324"), addr("192.0.2.2:443")))));
+        assert!(parse(&header[..20]) == Parsed::Incomplete);
+
+        let local = b"\r\n\r\n\0\r\nQUIT\n\x20\x00\x00\x00";
+        assert!(parse(local) == Parsed::Complete(16, None));
+
+        let truncated = b"\r\n\r\n\0\r\nQUIT\n\x21\x21\x00\x04\x00\x00\x00\x00";
+        assert!(matches!(parse(truncated), Parsed::Invalid(_)));
+    }
+
+    #[tokio::test]
+    async fn test_read_proxy_header() {
+        let (mut client, server) = tokio::io::duplex(1024);
+        client.write_all(b"PROXY TCP4 192.0.2.1 192.0.2.2 56324 443\r\nGET / HTTP/1.1\r\n").await.unwrap();
+        drop(client);
+
+        let (addrs, mut stream) = read_proxy_header(server, ProxyProtocol::Required).await.unwrap();
+        assert!(addrs == Some((addr("192.0.2.1:56324"), addr("192.0.2.2:443"))));
+        let mut rest = String::new();
+        stream.read_to_string(&mut rest).await.unwrap();
+        assert!(rest == "GET / HTTP/1.1\r\n");
+    }
+
+    #[tokio::test]
+    async fn test_missing_proxy_header() {
+        let (mut client, server) = tokio::io::duplex(1024);
+        client.write_all(b"GET / HTTP/1.1\r\n").await.unwrap();
+        assert!(read_proxy_header(server, ProxyProtocol::Required).await.is_err());
+
+        let (mut client, server) = tokio::io::duplex(1024);
+        client.write_all(b"GET / HTTP/1.1\r\n").await.unwrap();
+        drop(client);
+        let (addrs, mut stream) = read_proxy_header(server, ProxyProtocol::Optional).await.unwrap();
+        assert!(addrs.is_none());
+        let mut rest = String::new();
+        stream.read_to_string(&mut rest).await.unwrap();
+        assert!(rest == "GET / HTTP/1.1\r\n");
+    }
+}
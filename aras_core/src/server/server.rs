@@ -1,26 +1,45 @@
-use std::net::SocketAddr;
+use std::error::Error as StdError;
+use std::future::Future;
+use std::pin::Pin;
 use std::sync::Arc;
 use std::time::Duration;
 
 use futures::TryFutureExt;
-use hyper::server::conn::http1;
-use hyper_util::rt::{TokioIo, TokioTimer};
-use log::{error, info};
-use tokio::net::TcpListener;
+use hyper::body::Incoming;
+use hyper::server::conn::{http1, http2};
+use hyper::service::Service;
+use hyper::Request;
+use hyper_util::rt::{TokioExecutor, TokioIo, TokioTimer};
+use hyper_util::server::conn::auto;
+use log::{debug, error, info};
 use tokio::sync::Semaphore;
 
-use super::config::ServerConfig;
-use super::connection_info::ConnectionInfo;
+use super::config::{HttpProtocol, MetricsConfig, ServerConfig};
+use super::connection_info::{ConnectionInfo, ServerAddr};
+use super::listener::{bind_all, Listener};
+use super::metrics::{serve_metrics, Metrics};
+use super::proxy_protocol::read_proxy_header;
 use super::service::ASGIService;
+use super::shutdown::{forward_signals, Shutdown, ShutdownHandle, ShutdownWatcher};
+use super::tls::{negotiated_protocol, TlsAcceptor};
+use super::write_timeout::WriteTimeout;
 use crate::application::ApplicationFactory;
 use crate::asgispec::{ASGICallable, State};
 use crate::error::{Error, Result};
 use crate::lifespan::LifespanHandler;
-use crate::middleware_services::{ConcurrencyLimit, ContentLengthLimit, Logger};
+use crate::middleware_services::{
+    AccessLog, Compression, ConcurrencyLimit, ConnectionActivity, ContentLengthLimit, QueueMetrics, RateLimiter,
+    RequestIds, RequestMetrics, StaticFiles,
+};
+use crate::types::{Response, ServiceFuture};
+
+type ConnectionError = Box<dyn StdError + Send + Sync>;
 
 pub struct Server<S: State, T: ASGICallable<S>> {
     app_factory: ApplicationFactory<S, T>,
     state: S,
+    shutdown_handle: ShutdownHandle,
+    metrics: Metrics,
 }
 
 impl<S: State, T: ASGICallable<S>> Server<S, T> {
@@ -28,8 +47,30 @@ impl<S: State, T: ASGICallable<S>> Server<S, T> {
         Self {
             app_factory: ApplicationFactory::new(asgi_callable),
             state,
+            shutdown_handle: ShutdownHandle::new(),
+            metrics: Metrics::default(),
         }
     }
+
+    pub fn with_shutdown_handle(mut self, shutdown_handle: ShutdownHandle) -> Self {
+        self.shutdown_handle = shutdown_handle;
+        self
+    }
+
+    // Handle to stop the server programmatically
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown_handle.clone()
+    }
+
+    // Depth of the queue of requests waiting for the concurrency limit
+    pub fn queue_metrics(&self) -> QueueMetrics {
+        self.metrics.queue()
+    }
+
+    // Request, connection and error counters, also served by the metrics endpoint
+    pub fn metrics(&self) -> Metrics {
+        self.metrics.clone()
+    }
 }
 
 impl<S: State + 'static, T: ASGICallable<S> + 'static> Server<S, T> {
@@ -38,52 +79,177 @@ impl<S: State + 'static, T: ASGICallable<S> + 'static> Server<S, T> {
             .startup(self.state.clone())
             .await?;
 
-        // Wait for an exit signal or the server loop
-        // send shutdown event when exit signal is received.
+        // Wait for a shutdown request or the server loop. On a graceful shutdown stop accepting
+        // connections and drain the open ones, then send the shutdown event.
+        let signals = forward_signals(self.shutdown_handle.clone())?;
+        let mut requested = self.shutdown_handle.requested();
+        let shutdown = Shutdown::new();
+        let grace_period = config.graceful_shutdown_timeout;
         tokio::select! {
-            _ = tokio::signal::ctrl_c() => lifespan_handler.shutdown().await,
-            out = self.run_server(config).map_err(|e| Error::unexpected_shutdown("server", e.to_string())) => out,
-        }
+            _ = requested.any() => (),
+            out = self.run_server(config, &shutdown).map_err(|e| Error::unexpected_shutdown("server", e.to_string())) => {
+                signals.abort();
+                return out;
+            }
+        };
+
+        info!("Shutting down");
+        if requested.is_immediate() {
+            shutdown.close().await;
+        } else {
+            tokio::select! {
+                _ = shutdown.drain(grace_period) => (),
+                _ = requested.immediate() => shutdown.close().await,
+            };
+        };
+        signals.abort();
+        lifespan_handler.shutdown().await
     }
 
-    async fn run_server(&mut self, config: ServerConfig) -> Result<()> {
-        let socket_addr = SocketAddr::new(config.addr, config.port);
-        let listener = TcpListener::bind(socket_addr).await?;
-        let semaphore = Arc::new(Semaphore::new(config.limit_concurrency));
-        info!("Listening on http://{}", socket_addr);
+    async fn run_server(&self, config: ServerConfig, shutdown: &Shutdown) -> Result<()> {
+        let listeners = bind_all(&config.binds).await?;
+        let tls_acceptor = match &config.tls {
+            Some(tls) => Some(tls.build_acceptor(config.http_protocol)?),
+            None => None,
+        };
+        let scheme = if tls_acceptor.is_some() { "https" } else { "http" };
+        for listener in listeners.iter() {
+            info!("Listening on {scheme}://{}", listener.local_addr()?);
+        }
 
+        // The metrics get their own listener, or are served on the application listeners
+        let (metrics_path, metrics_loops) = match &config.metrics {
+            Some(MetricsConfig { path, bind: Some(bind) }) => {
+                let listeners = bind_all(std::slice::from_ref(bind)).await?;
+                let loops = listeners
+                    .into_iter()
+                    .map(|listener| serve_metrics(listener, path.clone(), self.metrics.clone()))
+                    .collect();
+                (None, loops)
+            }
+            Some(MetricsConfig { path, bind: None }) => (Some(Arc::new(path.clone())), Vec::new()),
+            None => (None, Vec::new()),
+        };
+
+        // All listeners share a single concurrency limit and queue
+        let context = ListenerContext {
+            concurrency: ConcurrencyLimit::new(
+                Arc::new(Semaphore::new(config.limit_concurrency)),
+                config.concurrency_queue,
+                self.metrics.queue(),
+            ),
+            websockets: Arc::new(Semaphore::new(config.max_websockets)),
+            rate_limiter: RateLimiter::new(config.rate_limit.clone(), config.forwarded_allow_ips.clone()),
+            access_log: AccessLog::new(
+                config.access_log.clone(),
+                config.forwarded_allow_ips.clone(),
+                self.metrics.clone(),
+            )?,
+            request_metrics: RequestMetrics::new(self.metrics.clone(), metrics_path),
+            request_ids: RequestIds::default(),
+            compression: Compression::new(config.compression.clone()),
+            static_files: StaticFiles::new(config.static_mounts.clone())?,
+            config: Arc::new(config),
+            tls_acceptor,
+        };
+        let accept_loops = listeners
+            .iter()
+            .map(|listener| self.accept_loop(listener, context.clone(), shutdown));
+        futures::try_join!(futures::future::try_join_all(accept_loops), futures::future::try_join_all(metrics_loops))?;
+        Ok(())
+    }
+
+    async fn accept_loop(&self, listener: &Listener, context: ListenerContext, shutdown: &Shutdown) -> Result<()> {
         loop {
-            let (tcp, client) = match listener.accept().await {
-                Ok((t, c)) => (t, c),
+            let (stream, mut conn_info) = match listener.accept().await {
+                Ok((s, c)) => (s, c),
                 Err(e) => {
                     error!("Failed to connect to client: {e}");
                     continue;
                 }
             };
 
-            let io = TokioIo::new(tcp);
+            let iter_context = context.clone();
             let iter_state = self.state.clone();
             let factory_clone = self.app_factory.clone();
-            let iter_semaphore = semaphore.clone();
-            let conn_info = ConnectionInfo::new(client, socket_addr);
+            let watcher = shutdown.watcher();
+            let client = conn_info.to_string();
             info!("Connecting new client {client}");
+            let metrics = self.metrics.clone();
+            let connection = metrics.connection_opened();
 
             tokio::task::spawn(async move {
-                let svc = tower::ServiceBuilder::new()
-                    .layer_fn(Logger::new)
-                    .layer_fn(ConcurrencyLimit::new(iter_semaphore).as_layer())
-                    .layer_fn(ContentLengthLimit::new(config.max_size).as_layer())
-                    .service(ASGIService::new(factory_clone, conn_info, iter_state));
-
-                if let Err(err) = http1::Builder::new()
-                    .timer(TokioTimer::new())
-                    .header_read_timeout(Duration::from_secs(60))
-                    .keep_alive(config.keep_alive)
-                    .serve_connection(io, svc)
-                    .with_upgrades()
-                    .await
-                {
-                    if err.is_closed() || err.is_timeout() {
+                let _connection = connection;
+                let config = iter_context.config;
+                let activity = ConnectionActivity::new();
+                let build_service = |conn_info: ConnectionInfo| {
+                    tower::ServiceBuilder::new()
+                        .layer_fn(activity.as_layer())
+                        .layer_fn(iter_context.request_ids.as_layer())
+                        .layer_fn(iter_context.request_metrics.as_layer())
+                        .layer_fn(iter_context.access_log.as_layer(conn_info.client.map(|addr| addr.ip())))
+                        .layer_fn(iter_context.compression.as_layer())
+                        .layer_fn(iter_context.rate_limiter.as_layer(conn_info.client.map(|addr| addr.ip())))
+                        .layer_fn(iter_context.static_files.as_layer())
+                        .layer_fn(iter_context.concurrency.as_layer())
+                        .layer_fn(ContentLengthLimit::new(config.max_size.clone(), metrics.clone()).as_layer())
+                        .service(ASGIService::new(
+                            factory_clone,
+                            conn_info,
+                            iter_state,
+                            watcher.clone(),
+                            config.clone(),
+                            iter_context.websockets.clone(),
+                            metrics.clone(),
+                        ))
+                };
+                let control = ConnectionControl {
+                    shutdown: watcher.clone(),
+                    activity: activity.clone(),
+                    idle_timeout: config.timeouts.keep_alive,
+                };
+                // The PROXY header counts towards the time to send the request headers
+                let proxied = read_proxy_header(stream, config.proxy_protocol);
+                let stream = match within(config.timeouts.header_read, proxied).await {
+                    Ok((Some((source, destination)), stream)) => {
+                        debug!("Client {client} connects through a proxy for {source}");
+                        conn_info.client = Some(source);
+                        conn_info.server = ServerAddr::Tcp(destination);
+                        stream
+                    }
+                    Ok((None, stream)) => stream,
+                    Err(e) => {
+                        error!("Reading the PROXY protocol header of client {client} failed: {e}");
+                        return;
+                    }
+                };
+                let client = conn_info.to_string();
+                let stream = WriteTimeout::new(stream, config.timeouts.write);
+
+                let served = match iter_context.tls_acceptor {
+                    Some(acceptor) => {
+                        // Bounded like the PROXY header, a client could hold the connection open forever otherwise
+                        let handshake = acceptor.accept(stream);
+                        let (stream, tls_info) = match within(config.timeouts.header_read, handshake).await {
+                            Ok(s) => s,
+                            Err(e) => {
+                                error!("TLS handshake with client {client} failed: {e}");
+                                return;
+                            }
+                        };
+                        let protocol = negotiated_protocol(stream.get_ref().1.alpn_protocol(), config.http_protocol);
+                        conn_info.tls = Some(tls_info);
+                        let svc = build_service(conn_info);
+                        serve_connection(TokioIo::new(stream), svc, protocol, &config, control).await
+                    }
+                    None => {
+                        let svc = build_service(conn_info);
+                        serve_connection(TokioIo::new(stream), svc, config.http_protocol, &config, control).await
+                    }
+                };
+
+                if let Err(err) = served {
+                    if is_disconnect(err.as_ref()) {
                         info!("Disconnected client {client}");
                     } else {
                         error!("Error serving connection: {:?}", err);
@@ -93,3 +259,301 @@ impl<S: State + 'static, T: ASGICallable<S> + 'static> Server<S, T> {
         }
     }
 }
+
+// State shared between the accept loops of all listeners
+#[derive(Clone)]
+struct ListenerContext {
+    config: Arc<ServerConfig>,
+    concurrency: ConcurrencyLimit,
+    websockets: Arc<Semaphore>,
+    rate_limiter: RateLimiter,
+    access_log: AccessLog,
+    request_metrics: RequestMetrics,
+    request_ids: RequestIds,
+    compression: Compression,
+    static_files: StaticFiles,
+    tls_acceptor: Option<TlsAcceptor>,
+}
+
+// Everything that can make a connection stop accepting new requests
+struct ConnectionControl {
+    shutdown: ShutdownWatcher,
+    activity: ConnectionActivity,
+    idle_timeout: Option<Duration>,
+}
+
+// Connection setup before hyper takes over, which has its own header read timeout
+async fn within<T>(timeout: Option<Duration>, setup: impl Future<Output = std::io::Result<T>>) -> std::io::Result<T> {
+    match timeout {
+        Some(timeout) => tokio::time::timeout(timeout, setup)
+            .await
+            .unwrap_or_else(|_| Err(std::io::Error::new(std::io::ErrorKind::TimedOut, "Timed out"))),
+        None => setup.await,
+    }
+}
+
+// Serve a single connection using the configured HTTP version(s).
+// Upgrades (websockets) are only possible over HTTP/1.1.
+async fn serve_connection<I, T>(
+    io: I,
+    svc: T,
+    protocol: HttpProtocol,
+    config: &ServerConfig,
+    control: ConnectionControl,
+) -> std::result::Result<(), ConnectionError>
+where
+    I: hyper::rt::Read + hyper::rt::Write + Unpin + Send + 'static,
+    T: Service<Request<Incoming>, Response = Response, Error = Error, Future = ServiceFuture> + Send + 'static,
+{
+    match protocol {
+        HttpProtocol::Http1 => {
+            let conn = http1::Builder::new()
+                .timer(TokioTimer::new())
+                .header_read_timeout(config.timeouts.header_read)
+                .keep_alive(config.keep_alive)
+                .serve_connection(io, svc)
+                .with_upgrades();
+            watch_connection(conn, control, |c| c.graceful_shutdown())
+                .await
+                .map_err(ConnectionError::from)
+        }
+        HttpProtocol::Http2 => {
+            let conn = http2::Builder::new(TokioExecutor::new())
+                .timer(TokioTimer::new())
+                .serve_connection(io, svc);
+            watch_connection(conn, control, |c| c.graceful_shutdown())
+                .await
+                .map_err(ConnectionError::from)
+        }
+        HttpProtocol::Auto => {
+            let mut builder = auto::Builder::new(TokioExecutor::new());
+            builder
+                .http1()
+                .timer(TokioTimer::new())
+                .header_read_timeout(config.timeouts.header_read)
+                .keep_alive(config.keep_alive);
+            builder.http2().timer(TokioTimer::new());
+            let conn = builder.serve_connection_with_upgrades(io, svc);
+            watch_connection(conn, control, |c| c.graceful_shutdown()).await
+        }
+    }
+}
+
+// Drive a connection to completion. Once the server shuts down or the connection is idle
+// for too long, it's asked to finish its in-flight requests. When the grace period of
+// the shutdown elapses the connection is dropped.
+async fn watch_connection<C, E>(
+    conn: C,
+    mut control: ConnectionControl,
+    graceful_shutdown: impl FnOnce(Pin<&mut C>),
+) -> std::result::Result<(), E>
+where
+    C: Future<Output = std::result::Result<(), E>>,
+{
+    let idle = async {
+        match control.idle_timeout {
+            Some(timeout) => control.activity.idle_for(timeout).await,
+            None => std::future::pending().await,
+        }
+    };
+
+    tokio::pin!(conn);
+    tokio::select! {
+        out = conn.as_mut() => return out,
+        _ = control.shutdown.draining() => graceful_shutdown(conn.as_mut()),
+        _ = idle => graceful_shutdown(conn.as_mut()),
+    };
+    tokio::select! {
+        out = conn => out,
+        _ = control.shutdown.closing() => Ok(()),
+    }
+}
+
+fn is_disconnect(err: &(dyn StdError + 'static)) -> bool {
+    match err.downcast_ref::<hyper::Error>() {
+        Some(e) => e.is_closed() || e.is_timeout(),
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use bytes::Bytes;
+    use http::Version;
+    use http_body_util::{BodyExt, Empty, Full};
+    use hyper::body::Incoming;
+    use hyper::service::Service;
+    use hyper::Request;
+    use hyper_util::rt::{TokioExecutor, TokioIo};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpStream;
+    use tokio::time::Instant;
+
+    use super::{serve_connection, within, ConnectionControl, Server};
+    use crate::asgispec::{ASGICallable, ASGIReceiveEvent, ASGISendEvent, Headers, ReceiveFn, Scope, SendFn, State};
+    use crate::error::{Error, Result};
+    use crate::middleware_services::ConnectionActivity;
+    use crate::server::config::{BindTarget, HttpProtocol, ServerConfig};
+    use crate::server::shutdown::Shutdown;
+    use crate::types::{Response, ServiceFuture};
+
+    #[derive(Clone, Debug)]
+    struct MockState;
+    impl State for MockState {}
+
+    // Answers every request with the HTTP version of its scope
+    #[derive(Clone, Debug)]
+    struct VersionApp;
+
+    impl ASGICallable<MockState> for VersionApp {
+        async fn call(&self, scope: Scope<MockState>, receive: ReceiveFn, send: SendFn) -> Result<()> {
+            match scope {
+                Scope::Lifespan(_) => loop {
+                    match receive().await? {
+                        ASGIReceiveEvent::Startup(_) => send(ASGISendEvent::new_startup_complete()).await?,
+                        ASGIReceiveEvent::Shutdown(_) => return send(ASGISendEvent::new_shutdown_complete()).await,
+                        _ => (),
+                    }
+                },
+                Scope::HTTP(scope) => {
+                    _ = receive().await?;
+                    send(ASGISendEvent::new_http_response_start(200, Headers::new())).await?;
+                    send(ASGISendEvent::new_http_response_body(scope.http_version, false)).await
+                }
+                Scope::Websocket(_) => Err(Error::custom("Invalid scope")),
+            }
+        }
+    }
+
+    async fn body_string(response: hyper::Response<Incoming>) -> String {
+        String::from_utf8(response.into_body().collect().await.unwrap().to_bytes().to_vec()).unwrap()
+    }
+
+    struct HelloService;
+
+    impl Service<Request<Incoming>> for HelloService {
+        type Response = Response;
+        type Error = Error;
+        type Future = ServiceFuture;
+
+        fn call(&self, _req: Request<Incoming>) -> Self::Future {
+            Box::pin(async {
+                let body = Full::new(Bytes::from("hello")).map_err(|never| match never {}).boxed();
+                Ok(hyper::Response::new(body))
+            })
+        }
+    }
+
+    fn connection_control(shutdown: &Shutdown, activity: &ConnectionActivity, config: &ServerConfig) -> ConnectionControl {
+        ConnectionControl {
+            shutdown: shutdown.watcher(),
+            activity: activity.clone(),
+            idle_timeout: config.timeouts.keep_alive,
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_connection_setup_times_out() {
+        // Like a client that connects and never sends its TLS ClientHello
+        let stalled = std::future::pending::<std::io::Result<()>>();
+        let start = Instant::now();
+        let result = within(Some(Duration::from_secs(5)), stalled).await;
+        assert!(result.is_err_and(|e| e.kind() == std::io::ErrorKind::TimedOut));
+        assert!(start.elapsed() == Duration::from_secs(5));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_header_read_timeout() {
+        let mut config = ServerConfig::default();
+        config.timeouts.header_read = Some(Duration::from_secs(10));
+        config.timeouts.keep_alive = None;
+        let shutdown = Shutdown::new();
+        let activity = ConnectionActivity::new();
+        let (mut client, server) = tokio::io::duplex(1024);
+
+        // Only part of the headers is sent
+        client.write_all(b"GET / HTTP/1.1\r\n").await.unwrap();
+        let start = Instant::now();
+        let control = connection_control(&shutdown, &activity, &config);
+        let served = serve_connection(TokioIo::new(server), HelloService, HttpProtocol::Http1, &config, control).await;
+
+        assert!(served.is_err());
+        assert!(start.elapsed() == Duration::from_secs(10));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_keep_alive_timeout() {
+        let mut config = ServerConfig::default();
+        config.timeouts.keep_alive = Some(Duration::from_secs(5));
+        let shutdown = Shutdown::new();
+        let activity = ConnectionActivity::new();
+        let (mut client, server) = tokio::io::duplex(1024);
+
+        let control = connection_control(&shutdown, &activity, &config);
+        let svc = activity.as_layer()(HelloService);
+        let connection = tokio::spawn(async move {
+            serve_connection(TokioIo::new(server), svc, HttpProtocol::Http1, &config, control).await
+        });
+
+        let start = Instant::now();
+        client.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n").await.unwrap();
+        let mut response = Vec::new();
+        client.read_to_end(&mut response).await.unwrap();
+
+        assert!(String::from_utf8(response).unwrap().ends_with("hello"));
+        assert!(start.elapsed() == Duration::from_secs(5));
+        assert!(connection.await.unwrap().is_ok());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_http2_and_http1_through_server() {
+        use std::os::fd::AsRawFd;
+
+        // Bound up front to know the port, the server takes a duplicate
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let config = ServerConfig {
+            binds: vec![BindTarget::Fd(listener.as_raw_fd())],
+            http_protocol: HttpProtocol::Auto,
+            graceful_shutdown_timeout: Duration::from_secs(1),
+            ..ServerConfig::default()
+        };
+        let mut server = Server::new(VersionApp, MockState);
+        let handle = server.shutdown_handle();
+        let serving = tokio::spawn(async move { server.serve(config).await });
+
+        // h2c with prior knowledge
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let (mut sender, connection) =
+            hyper::client::conn::http2::handshake(TokioExecutor::new(), TokioIo::new(stream)).await.unwrap();
+        tokio::spawn(connection);
+        let request = Request::builder()
+            .uri(format!("http://{addr}/"))
+            .body(Empty::<Bytes>::new())
+            .unwrap();
+        let response = sender.send_request(request).await.unwrap();
+        assert!(response.version() == Version::HTTP_2);
+        assert!(body_string(response).await == "2");
+        drop(sender);
+
+        // HTTP/1.1 on the same listener
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let (mut sender, connection) = hyper::client::conn::http1::handshake(TokioIo::new(stream)).await.unwrap();
+        tokio::spawn(connection);
+        let request = Request::builder()
+            .uri("/")
+            .header("host", "localhost")
+            .body(Empty::<Bytes>::new())
+            .unwrap();
+        let response = sender.send_request(request).await.unwrap();
+        assert!(response.version() == Version::HTTP_11);
+        assert!(body_string(response).await == "1.1");
+        drop(sender);
+
+        handle.shutdown();
+        assert!(serving.await.unwrap().is_ok());
+    }
+}
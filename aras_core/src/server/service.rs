@@ -1,16 +1,19 @@
+use std::sync::Arc;
+
 use derive_more::derive::Constructor;
-use http_body_util::{Full, BodyExt};
 use hyper::service::Service;
 use hyper::Request;
 use hyper::body::Incoming;
-use log::error;
+use log::{error, warn};
+use tokio::sync::Semaphore;
 
 use crate::application::ApplicationFactory;
 use crate::asgispec::{ASGICallable, Scope, State};
 use crate::error::{Error, Result};
-use crate::http::{serve_http, HTTPScope};
-use crate::server::ConnectionInfo;
-use crate::types::{Response, ServiceFuture};
+use crate::http::{request_body_encoding, serve_http, HTTPScope};
+use crate::middleware_services::RequestId;
+use crate::server::{ConnectionInfo, Forwarded, Metrics, ServerConfig, ShutdownWatcher};
+use crate::types::{text_response, Response, ServiceFuture};
 use crate::websocket::{serve_websocket, WebsocketScope};
 
 #[derive(Constructor, Clone)]
@@ -18,6 +21,10 @@ pub struct ASGIService<S: State, T: ASGICallable<S>> {
     app_factory: ApplicationFactory<S, T>,
     conn_info: ConnectionInfo,
     state: S,
+    shutdown: ShutdownWatcher,
+    config: Arc<ServerConfig>,
+    websockets: Arc<Semaphore>,
+    metrics: Metrics,
 }
 
 impl<S: State + 'static, T: ASGICallable<S> + 'static> Service<Request<Incoming>> for ASGIService<S, T> {
@@ -27,18 +34,44 @@ impl<S: State + 'static, T: ASGICallable<S> + 'static> Service<Request<Incoming>
 
     fn call(&self, req: Request<Incoming>) -> Self::Future {
         let asgi_app = self.app_factory.build();
+        let peer = self.conn_info.client.map(|addr| addr.ip());
+        let forwarded = Forwarded::from_headers(peer, req.headers(), &self.config.forwarded_allow_ips);
+        let request_id = RequestId::of(&req);
         if is_websocket_request(&req) {
+            // Held until the upgraded connection closes
+            let Ok(permit) = self.websockets.clone().try_acquire_owned() else {
+                warn!("[{request_id}] Maximum number of websockets reached, refusing handshake");
+                return Box::pin(send_503());
+            };
             let mut scope = WebsocketScope::from_hyper_request(&req, self.state.clone());
             scope.set_conn_info(&self.conn_info);
-            Box::pin(finalize(Box::pin(serve_websocket(asgi_app, req, Scope::Websocket(scope)))))
+            scope.set_forwarded(&forwarded);
+            let held = (permit, self.metrics.websocket_opened());
+            let served = serve_websocket(asgi_app, req, Scope::Websocket(scope), self.shutdown.clone(), held);
+            Box::pin(finalize(Box::pin(served), self.metrics.clone(), request_id))
         } else {
             let mut scope = HTTPScope::from_hyper_request(&req, self.state.clone());
             scope.set_conn_info(&self.conn_info);
-            Box::pin(finalize(Box::pin(serve_http(asgi_app, req, Scope::HTTP(scope)))))
+            scope.set_forwarded(&forwarded);
+            let encoding = match self.config.decompress_request_body {
+                true => request_body_encoding(req.headers()),
+                false => None,
+            };
+            if encoding.is_some() {
+                scope.set_body_decoded();
+            };
+            let max_size = self.config.max_size.for_path(req.uri().path());
+            let timeouts = self.config.timeouts;
+            let served = serve_http(asgi_app, req, Scope::HTTP(scope), timeouts, Some(max_size), encoding);
+            Box::pin(finalize(Box::pin(served), self.metrics.clone(), request_id))
         }
     }
 }
 
+async fn send_503() -> Result<Response> {
+    text_response(hyper::Response::builder().status(503), "Too many websocket connections")
+}
+
 fn is_websocket_request(value: &Request<Incoming>) -> bool {
     if let Some(header_value) = value.headers().get("upgrade") {
         if header_value == "websocket" {
@@ -48,21 +81,140 @@ fn is_websocket_request(value: &Request<Incoming>) -> bool {
     false
 }
 
-async fn finalize(result: ServiceFuture) -> Result<Response> {
+async fn finalize(result: ServiceFuture, metrics: Metrics, request_id: RequestId) -> Result<Response> {
     match result.await {
         Ok(response) => Ok(response),
+        Err(Error::Timeout(src)) => {
+            error!("[{request_id}] Error serving request: {src} timed out");
+            metrics.record_timeout();
+            let builder = hyper::Response::builder().status(408).header(hyper::header::CONNECTION, "close");
+            text_response(builder, "Request Timeout")
+        }
+        Err(Error::PayloadTooLarge(limit)) => {
+            warn!("[{request_id}] Refused request body larger than {limit} bytes");
+            metrics.record_payload_too_large();
+            let builder = hyper::Response::builder().status(413).header(hyper::header::CONNECTION, "close");
+            text_response(builder, "Payload too large")
+        }
+        // Hyper closes the connection when the service fails
+        Err(Error::InvalidBody(reason)) => {
+            warn!("[{request_id}] Refused request body. {reason}");
+            let builder = hyper::Response::builder().status(400).header(hyper::header::CONNECTION, "close");
+            text_response(builder, "Bad Request")
+        }
+        Err(error @ Error::Aborted(_)) => {
+            warn!("[{request_id}] {error}");
+            Err(error)
+        }
         Err(error) => {
-            error!("Error serving request: {error}");
-            let body_text = "Internal Server Error";
-            let body = Full::new(body_text.as_bytes().to_vec().into())
-                .map_err(|never| match never {})
-                .boxed();
-            let response = hyper::Response::builder()
-                .status(500)
-                .header(hyper::header::CONTENT_LENGTH, body_text.len())
-                .header(hyper::header::CONTENT_TYPE, "text/plain")
-                .body(body);
-            Ok(response?)
+            error!("[{request_id}] Error serving request: {error}");
+            metrics.record_app_error();
+            text_response(hyper::Response::builder().status(500), "Internal Server Error")
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use hyper_util::rt::TokioIo;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt, DuplexStream};
+    use tokio::sync::Semaphore;
+
+    use super::ASGIService;
+    use crate::application::ApplicationFactory;
+    use crate::asgispec::{ASGICallable, ASGIReceiveEvent, ASGISendEvent, Headers, ReceiveFn, Scope, SendFn, State};
+    use crate::error::Result;
+    use crate::server::connection_info::ServerAddr;
+    use crate::server::shutdown::Shutdown;
+    use crate::server::{ConnectionInfo, Metrics, ServerConfig, ShutdownWatcher};
+
+    const HANDSHAKE: &[u8] = b"GET / HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+        Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n";
+
+    #[derive(Clone, Debug)]
+    struct MockState;
+    impl State for MockState {}
+
+    // Accepts the websocket and waits for the client to go away
+    #[derive(Clone, Debug)]
+    struct AcceptingApp;
+
+    impl ASGICallable<MockState> for AcceptingApp {
+        async fn call(&self, _scope: Scope<MockState>, receive: ReceiveFn, send: SendFn) -> Result<()> {
+            loop {
+                match receive().await? {
+                    ASGIReceiveEvent::WebsocketConnect(_) => {
+                        send(ASGISendEvent::new_websocket_accept(None, Headers::new())).await?
+                    }
+                    ASGIReceiveEvent::WebsocketDisconnect(_) => return Ok(()),
+                    _ => (),
+                }
+            }
+        }
+    }
+
+    fn connect(websockets: Arc<Semaphore>, shutdown: ShutdownWatcher) -> DuplexStream {
+        let conn_info = ConnectionInfo::new(None, ServerAddr::Tcp("127.0.0.1:8080".parse().unwrap()));
+        let service = ASGIService::new(
+            ApplicationFactory::new(AcceptingApp),
+            conn_info,
+            MockState,
+            shutdown,
+            Arc::new(ServerConfig::default()),
+            websockets,
+            Metrics::default(),
+        );
+        let (client, server) = tokio::io::duplex(4096);
+        tokio::spawn(async move {
+            let builder = hyper::server::conn::http1::Builder::new();
+            _ = builder.serve_connection(TokioIo::new(server), service).with_upgrades().await;
+        });
+        client
+    }
+
+    // Sends the handshake and returns the status line of the response
+    async fn handshake(client: &mut DuplexStream) -> String {
+        client.write_all(HANDSHAKE).await.unwrap();
+        let mut response = Vec::new();
+        while !response.ends_with(b"\r\n") {
+            response.push(client.read_u8().await.unwrap());
+        }
+        String::from_utf8(response).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_websocket_limit_refuses_handshake() {
+        let shutdown = Shutdown::new();
+        let websockets = Arc::new(Semaphore::new(1));
+
+        let mut first = connect(websockets.clone(), shutdown.watcher());
+        assert!(handshake(&mut first).await.starts_with("HTTP/1.1 101"));
+
+        let mut second = connect(websockets.clone(), shutdown.watcher());
+        assert!(handshake(&mut second).await.starts_with("HTTP/1.1 503"));
+        assert!(websockets.available_permits() == 0);
+    }
+
+    #[tokio::test]
+    async fn test_websocket_permit_released_on_close() {
+        let shutdown = Shutdown::new();
+        let websockets = Arc::new(Semaphore::new(1));
+
+        let mut first = connect(websockets.clone(), shutdown.watcher());
+        assert!(handshake(&mut first).await.starts_with("HTTP/1.1 101"));
+        drop(first);
+
+        let released = tokio::time::timeout(Duration::from_secs(1), async {
+            while websockets.available_permits() == 0 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        });
+        assert!(released.await.is_ok());
+
+        let mut second = connect(websockets.clone(), shutdown.watcher());
+        assert!(handshake(&mut second).await.starts_with("HTTP/1.1 101"));
+    }
+}
@@ -0,0 +1,298 @@
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+
+use log::{info, warn};
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+
+// Time given to connections to close after the grace period elapsed
+const FORCE_CLOSE_TIMEOUT: Duration = Duration::from_secs(1);
+
+// How the server was asked to stop, a request can only escalate
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum ShutdownMode {
+    Running,
+    // Drain open connections before stopping
+    Graceful,
+    // Close open connections right away
+    Immediate,
+}
+
+// Stops a running server, from another task or thread
+#[derive(Clone)]
+pub struct ShutdownHandle {
+    sender: Arc<watch::Sender<ShutdownMode>>,
+}
+
+impl ShutdownHandle {
+    pub fn new() -> Self {
+        Self {
+            sender: Arc::new(watch::Sender::new(ShutdownMode::Running)),
+        }
+    }
+
+    // Stop accepting connections and wait for the open ones to finish
+    pub fn shutdown(&self) {
+        self.request(ShutdownMode::Graceful);
+    }
+
+    // Close all connections without waiting for them to finish
+    pub fn shutdown_now(&self) {
+        self.request(ShutdownMode::Immediate);
+    }
+
+    pub fn is_shutting_down(&self) -> bool {
+        *self.sender.borrow() != ShutdownMode::Running
+    }
+
+    fn request(&self, mode: ShutdownMode) {
+        self.sender.send_if_modified(|current| {
+            let escalate = mode > *current;
+            if escalate {
+                *current = mode;
+            };
+            escalate
+        });
+    }
+
+    pub(crate) fn requested(&self) -> ShutdownRequest {
+        ShutdownRequest(self.sender.subscribe())
+    }
+}
+
+impl Default for ShutdownHandle {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub(crate) struct ShutdownRequest(watch::Receiver<ShutdownMode>);
+
+impl ShutdownRequest {
+    // Resolves once any shutdown is requested
+    pub async fn any(&mut self) {
+        _ = self.0.wait_for(|mode| *mode != ShutdownMode::Running).await;
+    }
+
+    // Resolves once an immediate shutdown is requested
+    pub async fn immediate(&mut self) {
+        _ = self.0.wait_for(|mode| *mode == ShutdownMode::Immediate).await;
+    }
+
+    pub fn is_immediate(&self) -> bool {
+        *self.0.borrow() == ShutdownMode::Immediate
+    }
+}
+
+// Process signals that stop the server
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Signal {
+    Terminate,
+    Interrupt,
+    Quit,
+}
+
+// SIGTERM drains the server, SIGINT does as well but forces the shutdown
+// when received a second time, SIGQUIT shuts down immediately.
+fn handle_signal(handle: &ShutdownHandle, signal: Signal) {
+    match signal {
+        Signal::Terminate => {
+            info!("Received SIGTERM, shutting down gracefully");
+            handle.shutdown();
+        }
+        Signal::Interrupt if handle.is_shutting_down() => {
+            info!("Received SIGINT again, shutting down immediately");
+            handle.shutdown_now();
+        }
+        Signal::Interrupt => {
+            info!("Received SIGINT, shutting down gracefully. Press Ctrl+C again to force");
+            handle.shutdown();
+        }
+        Signal::Quit => {
+            info!("Received SIGQUIT, shutting down immediately");
+            handle.shutdown_now();
+        }
+    };
+}
+
+// Translate process signals into shutdown requests
+#[cfg(unix)]
+pub fn forward_signals(handle: ShutdownHandle) -> io::Result<JoinHandle<()>> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut terminate = signal(SignalKind::terminate())?;
+    let mut interrupt = signal(SignalKind::interrupt())?;
+    let mut quit = signal(SignalKind::quit())?;
+
+    Ok(tokio::spawn(async move {
+        loop {
+            let received = tokio::select! {
+                _ = terminate.recv() => Signal::Terminate,
+                _ = interrupt.recv() => Signal::Interrupt,
+                _ = quit.recv() => Signal::Quit,
+            };
+            handle_signal(&handle, received);
+        }
+    }))
+}
+
+#[cfg(not(unix))]
+pub fn forward_signals(handle: ShutdownHandle) -> io::Result<JoinHandle<()>> {
+    Ok(tokio::spawn(async move {
+        while tokio::signal::ctrl_c().await.is_ok() {
+            handle_signal(&handle, Signal::Interrupt);
+        }
+    }))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ShutdownState {
+    Running,
+    // Stop accepting new requests, finish the in-flight ones
+    Draining,
+    // Close everything that is still open
+    Closing,
+}
+
+// Signals shutdown to all open connections and keeps track of the ones still running.
+// Every connection holds a `ShutdownWatcher`, the server is drained once all are dropped.
+pub struct Shutdown {
+    sender: watch::Sender<ShutdownState>,
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        Self {
+            sender: watch::Sender::new(ShutdownState::Running),
+        }
+    }
+
+    pub fn watcher(&self) -> ShutdownWatcher {
+        ShutdownWatcher(self.sender.subscribe())
+    }
+
+    // Ask all connections to finish and wait for them up to `grace_period`,
+    // afterwards the remaining connections are closed
+    pub async fn drain(&self, grace_period: Duration) {
+        self.sender.send_replace(ShutdownState::Draining);
+        if self.sender.is_closed() {
+            return;
+        };
+        info!("Waiting for open connections to finish");
+
+        if tokio::time::timeout(grace_period, self.sender.closed()).await.is_err() {
+            warn!("Graceful shutdown timed out, closing remaining connections");
+            self.close().await;
+        };
+    }
+
+    // Close all connections without waiting for in-flight requests
+    pub async fn close(&self) {
+        self.sender.send_replace(ShutdownState::Closing);
+        _ = tokio::time::timeout(FORCE_CLOSE_TIMEOUT, self.sender.closed()).await;
+    }
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Clone)]
+pub struct ShutdownWatcher(watch::Receiver<ShutdownState>);
+
+impl ShutdownWatcher {
+    // Resolves once the server starts shutting down
+    pub async fn draining(&mut self) {
+        _ = self.0.wait_for(|state| *state != ShutdownState::Running).await;
+    }
+
+    // Resolves once the remaining connections should be closed immediately
+    pub async fn closing(&mut self) {
+        _ = self.0.wait_for(|state| *state == ShutdownState::Closing).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{handle_signal, Shutdown, ShutdownHandle, Signal};
+
+    #[tokio::test]
+    async fn test_drain_without_connections() {
+        let shutdown = Shutdown::new();
+        let start = tokio::time::Instant::now();
+        shutdown.drain(Duration::from_secs(10)).await;
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_drain_waits_for_connections() {
+        let shutdown = Shutdown::new();
+        let mut watcher = shutdown.watcher();
+        let connection = tokio::spawn(async move {
+            watcher.draining().await;
+            tokio::time::sleep(Duration::from_secs(5)).await;
+        });
+
+        let start = tokio::time::Instant::now();
+        shutdown.drain(Duration::from_secs(10)).await;
+        assert!(start.elapsed() == Duration::from_secs(5));
+        assert!(connection.await.is_ok());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_drain_closes_after_grace_period() {
+        let shutdown = Shutdown::new();
+        let mut watcher = shutdown.watcher();
+        let connection = tokio::spawn(async move {
+            watcher.closing().await;
+            "closed"
+        });
+
+        let start = tokio::time::Instant::now();
+        shutdown.drain(Duration::from_secs(10)).await;
+        assert!(start.elapsed() == Duration::from_secs(10));
+        assert!(connection.await.unwrap() == "closed");
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_handle_only_escalates() {
+        let handle = ShutdownHandle::new();
+        let mut requested = handle.requested();
+        assert!(!handle.is_shutting_down());
+
+        handle.shutdown();
+        requested.any().await;
+        assert!(!requested.is_immediate());
+
+        handle.shutdown_now();
+        requested.immediate().await;
+        handle.shutdown();
+        assert!(requested.is_immediate());
+    }
+
+    // Real signals would reach every test running in this process
+    #[test]
+    fn test_signals() {
+        let handle = ShutdownHandle::new();
+        handle_signal(&handle, Signal::Terminate);
+        assert!(handle.is_shutting_down());
+        assert!(!handle.requested().is_immediate());
+        handle_signal(&handle, Signal::Interrupt);
+        assert!(handle.requested().is_immediate());
+
+        let handle = ShutdownHandle::new();
+        handle_signal(&handle, Signal::Interrupt);
+        assert!(!handle.requested().is_immediate());
+        handle_signal(&handle, Signal::Interrupt);
+        assert!(handle.requested().is_immediate());
+
+        let handle = ShutdownHandle::new();
+        handle_signal(&handle, Signal::Quit);
+        assert!(handle.requested().is_immediate());
+    }
+}
@@ -0,0 +1,409 @@
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+
+use base64::Engine;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::WebPkiClientVerifier;
+use rustls::RootCertStore;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_rustls::server::TlsStream;
+use x509_parser::prelude::{FromDer, X509Certificate};
+
+use super::config::HttpProtocol;
+use crate::asgispec::TLSExtension;
+use crate::error::{Error, Result};
+
+// Whether clients have to present a certificate signed by the configured CA bundle
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClientCertMode {
+    None,
+    Optional,
+    Required,
+}
+
+impl FromStr for ClientCertMode {
+    type Err = Error;
+
+    fn from_str(value: &str) -> std::result::Result<Self, Self::Err> {
+        match value.to_lowercase().as_str() {
+            "none" => Ok(Self::None),
+            "optional" => Ok(Self::Optional),
+            "required" => Ok(Self::Required),
+            _ => Err(Error::custom(format!(
+                "Invalid client certificate mode '{value}', expected one of 'none', 'optional' or 'required'"
+            ))),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TlsConfig {
+    pub certfile: PathBuf,
+    pub keyfile: PathBuf,
+    pub client_ca: Option<PathBuf>,
+    pub client_cert_mode: ClientCertMode,
+}
+
+impl TlsConfig {
+    pub fn new(certfile: impl Into<PathBuf>, keyfile: impl Into<PathBuf>) -> Self {
+        Self {
+            certfile: certfile.into(),
+            keyfile: keyfile.into(),
+            client_ca: None,
+            client_cert_mode: ClientCertMode::None,
+        }
+    }
+
+    // Verify client certificates against the CA bundle in `client_ca`
+    pub fn with_client_auth(mut self, client_ca: impl Into<PathBuf>, mode: ClientCertMode) -> Self {
+        self.client_ca = Some(client_ca.into());
+        self.client_cert_mode = mode;
+        self
+    }
+
+    // Load the certificate chain and private key and build an acceptor
+    // advertising (through ALPN) the HTTP versions the server is configured for
+    pub fn build_acceptor(&self, protocol: HttpProtocol) -> Result<TlsAcceptor> {
+        let certs = load_certificates(&self.certfile)?;
+        let key = PrivateKeyDer::from_pem_file(&self.keyfile)
+            .map_err(|e| Error::custom(format!("Failed to load private key from {:?}: {e}", self.keyfile)))?;
+        let server_cert = certs.first().map(|cert| der_to_pem(cert));
+
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let builder = rustls::ServerConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()?;
+
+        let builder = match (self.client_cert_mode, &self.client_ca) {
+            (ClientCertMode::None, _) => builder.with_no_client_auth(),
+            (_, None) => {
+                return Err(Error::custom("A client CA bundle is required to verify client certificates"));
+            }
+            (mode, Some(client_ca)) => {
+                let mut roots = RootCertStore::empty();
+                for cert in load_certificates(client_ca)? {
+                    roots.add(cert)?;
+                }
+                let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider);
+                let verifier = match mode {
+                    ClientCertMode::Optional => verifier.allow_unauthenticated().build(),
+                    _ => verifier.build(),
+                }
+                .map_err(|e| Error::custom(format!("Failed to build client certificate verifier: {e}")))?;
+                builder.with_client_cert_verifier(verifier)
+            }
+        };
+
+        let mut server_config = builder.with_single_cert(certs, key)?;
+        server_config.alpn_protocols = alpn_protocols(protocol);
+
+        Ok(TlsAcceptor {
+            inner: tokio_rustls::TlsAcceptor::from(Arc::new(server_config)),
+            server_cert,
+        })
+    }
+}
+
+#[derive(Clone)]
+pub struct TlsAcceptor {
+    inner: tokio_rustls::TlsAcceptor,
+    server_cert: Option<String>,
+}
+
+impl TlsAcceptor {
+    // Perform the TLS handshake and collect the connection details for the ASGI TLS extension
+    pub async fn accept<IO>(&self, stream: IO) -> std::io::Result<(TlsStream<IO>, TLSExtension)>
+    where
+        IO: AsyncRead + AsyncWrite + Unpin,
+    {
+        let stream = self.inner.accept(stream).await?;
+        let connection = stream.get_ref().1;
+        let client_cert_chain: Vec<String> = connection
+            .peer_certificates()
+            .unwrap_or_default()
+            .iter()
+            .map(der_to_pem)
+            .collect();
+        let client_cert_name = connection
+            .peer_certificates()
+            .and_then(|certs| certs.first())
+            .and_then(subject_name);
+
+        let extension = TLSExtension {
+            server_cert: self.server_cert.clone(),
+            client_cert_chain,
+            client_cert_name,
+            // Certificates failing verification abort the handshake
+            client_cert_error: None,
+            tls_version: connection.protocol_version().map(u16::from),
+            cipher_suite: connection.negotiated_cipher_suite().map(|s| u16::from(s.suite())),
+        };
+        Ok((stream, extension))
+    }
+}
+
+fn load_certificates(path: &Path) -> Result<Vec<CertificateDer<'static>>> {
+    let certs = CertificateDer::pem_file_iter(path)
+        .and_then(|certs| certs.collect::<std::result::Result<Vec<_>, _>>())
+        .map_err(|e| Error::custom(format!("Failed to load certificates from {path:?}: {e}")))?;
+
+    if certs.is_empty() {
+        return Err(Error::custom(format!("No certificates found in {path:?}")));
+    };
+    Ok(certs)
+}
+
+fn der_to_pem(cert: &CertificateDer<'_>) -> String {
+    let encoded = base64::engine::general_purpose::STANDARD.encode(cert.as_ref());
+    let mut pem = String::from("-----BEGIN CERTIFICATE-----\n");
+    for line in encoded.as_bytes().chunks(64) {
+        pem.push_str(&String::from_utf8_lossy(line));
+        pem.push('\n');
+    }
+    pem.push_str("-----END CERTIFICATE-----\n");
+    pem
+}
+
+// Subject distinguished name of a certificate, formatted as described in RFC 4514
+fn subject_name(cert: &CertificateDer<'_>) -> Option<String> {
+    let (_, parsed) = X509Certificate::from_der(cert.as_ref()).ok()?;
+    let registry = x509_parser::objects::oid_registry();
+    let rdns: Vec<String> = parsed
+        .subject()
+        .iter_rdn()
+        .map(|rdn| {
+            rdn.iter()
+                .map(|attr| {
+                    let name = x509_parser::objects::oid2abbrev(attr.attr_type(), registry)
+                        .map(String::from)
+                        .unwrap_or_else(|_| attr.attr_type().to_id_string());
+                    let value = attr
+                        .as_str()
+                        .map(escape_dn_value)
+                        .unwrap_or_else(|_| format!("#{}", hex(attr.as_slice())));
+                    format!("{name}={value}")
+                })
+                .collect::<Vec<String>>()
+                .join("+")
+        })
+        .collect();
+    // RFC 4514 lists the most specific RDN first
+    Some(rdns.into_iter().rev().collect::<Vec<String>>().join(","))
+}
+
+fn escape_dn_value(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    let last = value.chars().count().saturating_sub(1);
+    for (i, c) in value.chars().enumerate() {
+        let needs_escape = matches!(c, ',' | '+' | '"' | '\\' | '<' | '>' | ';')
+            || (i == 0 && (c == '#' || c == ' '))
+            || (i == last && c == ' ');
+        if needs_escape {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+fn hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn alpn_protocols(protocol: HttpProtocol) -> Vec<Vec<u8>> {
+    match protocol {
+        HttpProtocol::Http1 => vec![b"http/1.1".to_vec()],
+        HttpProtocol::Http2 => vec![b"h2".to_vec()],
+        HttpProtocol::Auto => vec![b"h2".to_vec(), b"http/1.1".to_vec()],
+    }
+}
+
+// Pick the HTTP version for a connection based on the ALPN negotiation outcome
+pub fn negotiated_protocol(alpn: Option<&[u8]>, configured: HttpProtocol) -> HttpProtocol {
+    match alpn {
+        Some(b"h2") => HttpProtocol::Http2,
+        Some(b"http/1.1") => HttpProtocol::Http1,
+        _ => configured,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use std::sync::Arc;
+
+    use rcgen::{BasicConstraints, CertificateParams, DnType, IsCa, KeyPair};
+    use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::{negotiated_protocol, ClientCertMode, TlsConfig};
+    use crate::server::HttpProtocol;
+
+    struct GeneratedCert {
+        cert: rcgen::Certificate,
+        key: KeyPair,
+        certfile: PathBuf,
+        keyfile: PathBuf,
+    }
+
+    impl GeneratedCert {
+        fn write(name: &str, cert: rcgen::Certificate, key: KeyPair) -> Self {
+            let dir = std::env::temp_dir();
+            let certfile = dir.join(format!("aras-{name}-{}-cert.pem", std::process::id()));
+            let keyfile = dir.join(format!("aras-{name}-{}-key.pem", std::process::id()));
+            std::fs::write(&certfile, cert.pem()).unwrap();
+            std::fs::write(&keyfile, key.serialize_pem()).unwrap();
+            Self { cert, key, certfile, keyfile }
+        }
+    }
+
+    impl Drop for GeneratedCert {
+        fn drop(&mut self) {
+            _ = std::fs::remove_file(&self.certfile);
+            _ = std::fs::remove_file(&self.keyfile);
+        }
+    }
+
+    fn generate_self_signed(name: &str) -> GeneratedCert {
+        let certified = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        GeneratedCert::write(name, certified.cert, certified.key_pair)
+    }
+
+    fn generate_ca(name: &str) -> GeneratedCert {
+        let mut params = CertificateParams::new(Vec::new()).unwrap();
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        params.distinguished_name.push(DnType::CommonName, "aras test CA");
+        let key = KeyPair::generate().unwrap();
+        let cert = params.self_signed(&key).unwrap();
+        GeneratedCert::write(name, cert, key)
+    }
+
+    fn generate_client_cert(name: &str, ca: &GeneratedCert) -> GeneratedCert {
+        let mut params = CertificateParams::new(Vec::new()).unwrap();
+        params.distinguished_name.push(DnType::OrganizationName, "aras");
+        params.distinguished_name.push(DnType::CommonName, "client, one");
+        let key = KeyPair::generate().unwrap();
+        let cert = params.signed_by(&key, &ca.cert, &ca.key).unwrap();
+        GeneratedCert::write(name, cert, key)
+    }
+
+    fn client_config(server: &GeneratedCert, alpn: Vec<Vec<u8>>, client: Option<&GeneratedCert>) -> rustls::ClientConfig {
+        let mut roots = rustls::RootCertStore::empty();
+        roots.add(server.cert.der().clone()).unwrap();
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let builder = rustls::ClientConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(roots);
+        let mut config = match client {
+            Some(c) => {
+                let chain: Vec<CertificateDer<'static>> = vec![c.cert.der().clone()];
+                let key = PrivateKeyDer::try_from(c.key.serialize_der()).unwrap();
+                builder.with_client_auth_cert(chain, key).unwrap()
+            }
+            None => builder.with_no_client_auth(),
+        };
+        config.alpn_protocols = alpn;
+        config
+    }
+
+    async fn connect(tls_config: &TlsConfig, protocol: HttpProtocol, client_config: rustls::ClientConfig) -> (std::io::Result<crate::TLSExtension>, Option<Vec<u8>>) {
+        let acceptor = tls_config.build_acceptor(protocol).unwrap();
+        let connector = tokio_rustls::TlsConnector::from(Arc::new(client_config));
+        let (client_io, server_io) = tokio::io::duplex(16384);
+
+        let server = tokio::spawn(async move {
+            let (mut stream, extension) = acceptor.accept(server_io).await?;
+            let alpn = stream.get_ref().1.alpn_protocol().map(|p| p.to_vec());
+            stream.write_all(b"hello").await?;
+            stream.flush().await?;
+            Ok::<_, std::io::Error>((extension, alpn))
+        });
+
+        let server_name = ServerName::try_from("localhost").unwrap();
+        if let Ok(mut client) = connector.connect(server_name, client_io).await {
+            let mut buf = [0u8; 5];
+            _ = client.read_exact(&mut buf).await;
+        }
+
+        match server.await.unwrap() {
+            Ok((extension, alpn)) => (Ok(extension), alpn),
+            Err(e) => (Err(e), None),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_tls_handshake_negotiates_h2() {
+        let cert = generate_self_signed("handshake");
+        let tls_config = TlsConfig::new(&cert.certfile, &cert.keyfile);
+        let client = client_config(&cert, vec![b"h2".to_vec(), b"http/1.1".to_vec()], None);
+
+        let (extension, alpn) = connect(&tls_config, HttpProtocol::Auto, client).await;
+        let extension = extension.unwrap();
+
+        assert!(negotiated_protocol(alpn.as_deref(), HttpProtocol::Auto) == HttpProtocol::Http2);
+        assert!(extension.server_cert.is_some_and(|c| c.starts_with("-----BEGIN CERTIFICATE-----")));
+        assert!(extension.client_cert_chain.is_empty());
+        assert!(extension.client_cert_name.is_none());
+        assert!(extension.tls_version == Some(0x0304));
+        assert!(extension.cipher_suite.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_tls_alpn_respects_protocol() {
+        let cert = generate_self_signed("alpn");
+        let tls_config = TlsConfig::new(&cert.certfile, &cert.keyfile);
+        let client = client_config(&cert, vec![b"h2".to_vec(), b"http/1.1".to_vec()], None);
+
+        let (_, alpn) = connect(&tls_config, HttpProtocol::Http1, client).await;
+        assert!(alpn.as_deref() == Some(b"http/1.1".as_slice()));
+    }
+
+    #[tokio::test]
+    async fn test_client_cert_required() {
+        let cert = generate_self_signed("required-server");
+        let ca = generate_ca("required-ca");
+        let tls_config =
+            TlsConfig::new(&cert.certfile, &cert.keyfile).with_client_auth(&ca.certfile, ClientCertMode::Required);
+
+        let (extension, _) = connect(&tls_config, HttpProtocol::Auto, client_config(&cert, Vec::new(), None)).await;
+        assert!(extension.is_err());
+
+        let client_cert = generate_client_cert("required-client", &ca);
+        let client = client_config(&cert, Vec::new(), Some(&client_cert));
+        let (extension, _) = connect(&tls_config, HttpProtocol::Auto, client).await;
+        let extension = extension.unwrap();
+
+        assert!(extension.client_cert_chain.len() == 1);
+        assert!(extension.client_cert_name.as_deref() == Some("O=aras,CN=client\\, one"));
+        assert!(extension.client_cert_error.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_client_cert_optional() {
+        let cert = generate_self_signed("optional-server");
+        let ca = generate_ca("optional-ca");
+        let tls_config =
+            TlsConfig::new(&cert.certfile, &cert.keyfile).with_client_auth(&ca.certfile, ClientCertMode::Optional);
+
+        let (extension, _) = connect(&tls_config, HttpProtocol::Auto, client_config(&cert, Vec::new(), None)).await;
+        assert!(extension.is_ok_and(|e| e.client_cert_chain.is_empty()));
+    }
+
+    #[test]
+    fn test_client_ca_required_for_verification() {
+        let cert = generate_self_signed("no-ca");
+        let mut tls_config = TlsConfig::new(&cert.certfile, &cert.keyfile);
+        tls_config.client_cert_mode = ClientCertMode::Required;
+        let result = tls_config.build_acceptor(HttpProtocol::Auto);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_missing_key_file() {
+        let cert = generate_self_signed("missing");
+        let result = TlsConfig::new(&cert.certfile, "/does/not/exist.pem").build_acceptor(HttpProtocol::Auto);
+        assert!(result.is_err_and(|e| e.to_string().starts_with("Failed to load private key")));
+    }
+}
@@ -0,0 +1,128 @@
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::time::Sleep;
+
+// Fails writes that are blocked for longer than the timeout,
+// e.g. because the client stopped reading the response
+pub struct WriteTimeout<T> {
+    inner: T,
+    timeout: Option<Duration>,
+    deadline: Option<Pin<Box<Sleep>>>,
+}
+
+impl<T> WriteTimeout<T> {
+    pub fn new(inner: T, timeout: Option<Duration>) -> Self {
+        Self {
+            inner,
+            timeout,
+            deadline: None,
+        }
+    }
+
+    // Start the timer once a write is blocked, reset it once the write makes progress
+    fn poll_deadline<R>(&mut self, cx: &mut Context<'_>, poll: Poll<io::Result<R>>) -> Poll<io::Result<R>> {
+        let timeout = match (&poll, self.timeout) {
+            (Poll::Pending, Some(timeout)) => timeout,
+            _ => {
+                self.deadline = None;
+                return poll;
+            }
+        };
+        let deadline = self
+            .deadline
+            .get_or_insert_with(|| Box::pin(tokio::time::sleep(timeout)));
+        match deadline.as_mut().poll(cx) {
+            Poll::Ready(()) => {
+                self.deadline = None;
+                Poll::Ready(Err(io::Error::new(io::ErrorKind::TimedOut, "Write to client timed out")))
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl<T: AsyncRead + Unpin> AsyncRead for WriteTimeout<T> {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_read(cx, buf)
+    }
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for WriteTimeout<T> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let poll = Pin::new(&mut this.inner).poll_write(cx, buf);
+        this.poll_deadline(cx, poll)
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let poll = Pin::new(&mut this.inner).poll_write_vectored(cx, bufs);
+        this.poll_deadline(cx, poll)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.inner.is_write_vectored()
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let poll = Pin::new(&mut this.inner).poll_flush(cx);
+        this.poll_deadline(cx, poll)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let poll = Pin::new(&mut this.inner).poll_shutdown(cx);
+        this.poll_deadline(cx, poll)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::WriteTimeout;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_blocked_write_times_out() {
+        let (client, server) = tokio::io::duplex(16);
+        let mut server = WriteTimeout::new(server, Some(Duration::from_secs(5)));
+
+        let start = tokio::time::Instant::now();
+        let result = server.write_all(&[0; 64]).await;
+        assert!(result.is_err_and(|e| e.kind() == std::io::ErrorKind::TimedOut));
+        assert!(start.elapsed() == Duration::from_secs(5));
+        drop(client);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_progressing_write_succeeds() {
+        let (mut client, server) = tokio::io::duplex(16);
+        let mut server = WriteTimeout::new(server, Some(Duration::from_secs(5)));
+
+        let reader = tokio::spawn(async move {
+            let mut received = Vec::new();
+            let mut buf = [0; 16];
+            while received.len() < 64 {
+                tokio::time::sleep(Duration::from_secs(3)).await;
+                let n = client.read(&mut buf).await.unwrap();
+                received.extend_from_slice(&buf[..n]);
+            }
+            received.len()
+        });
+
+        assert!(server.write_all(&[0; 64]).await.is_ok());
+        assert!(reader.await.unwrap() == 64);
+    }
+}
@@ -3,8 +3,19 @@ use std::pin::Pin;
 use bytes::Bytes;
 use futures::Future;
 use http_body_util::combinators::BoxBody;
+use http_body_util::{BodyExt, Full};
+use hyper::header::{CONTENT_LENGTH, CONTENT_TYPE};
 
-use crate::error::Error;
+use crate::error::{Error, Result};
 
 pub type Response = hyper::Response<BoxBody<Bytes, Error>>;
 pub type ServiceFuture = Pin<Box<dyn Future<Output = std::result::Result<Response, Error>> + Send>>;
+
+// Plain text response for refused or failed requests, the builder sets the status and any other headers
+pub(crate) fn text_response(builder: http::response::Builder, body_text: &'static str) -> Result<Response> {
+    let response = builder
+        .header(CONTENT_LENGTH, body_text.len())
+        .header(CONTENT_TYPE, "text/plain")
+        .body(Full::new(Bytes::from(body_text)).map_err(|never| match never {}).boxed());
+    Ok(response?)
+}
@@ -1,3 +1,7 @@
+use bytes::Bytes;
+
+use crate::asgispec::Headers;
+
 #[derive(Debug)]
 pub struct WebsocketConnectEvent {
     pub type_: String,
@@ -9,17 +13,23 @@ impl WebsocketConnectEvent {
     }
 }
 
+impl Default for WebsocketConnectEvent {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 #[derive(Debug)]
 pub struct WebsocketAcceptEvent {
     pub type_: String,
     pub subprotocol: Option<String>,
-    pub headers: Vec<(Vec<u8>, Vec<u8>)>,
+    pub headers: Headers,
 }
 
 impl WebsocketAcceptEvent {
     pub fn new(
         subprotocol: Option<String>,
-        headers: Vec<(Vec<u8>, Vec<u8>)>,
+        headers: Headers,
     ) -> Self {
         Self { type_:  "websocket.accept".into(), subprotocol, headers }
     }
@@ -28,13 +38,13 @@ impl WebsocketAcceptEvent {
 #[derive(Debug)]
 pub struct WebsocketReceiveEvent {
     pub type_: String,
-    pub bytes: Option<Vec<u8>>,
+    pub bytes: Option<Bytes>,
     pub text: Option<String>,
 }
 
 impl WebsocketReceiveEvent {
     pub fn new(
-        bytes: Option<Vec<u8>>,
+        bytes: Option<Bytes>,
         text: Option<String>,
     ) -> Self {
         // TODO: at least one of bytes or text should be present
@@ -45,13 +55,13 @@ impl WebsocketReceiveEvent {
 #[derive(Debug)]
 pub struct WebsocketSendEvent {
     pub type_: String,
-    pub bytes: Option<Vec<u8>>,
+    pub bytes: Option<Bytes>,
     pub text: Option<String>,
 }
 
 impl WebsocketSendEvent {
     pub fn new(
-        bytes: Option<Vec<u8>>,
+        bytes: Option<Bytes>,
         text: Option<String>,
     ) -> Self {
         // TODO: at least one of bytes or text should be present
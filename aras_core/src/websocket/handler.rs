@@ -1,11 +1,10 @@
 use std::sync::Arc;
 
 use bytes::Bytes;
-use bytes::BytesMut;
 use fastwebsockets::upgrade::UpgradeFut;
 use fastwebsockets::{upgrade, FragmentCollector, Frame, OpCode, Payload};
 use futures::TryFutureExt;
-use http::StatusCode;
+use http::{HeaderValue, StatusCode};
 use http_body_util::{BodyExt, Full};
 use hyper::upgrade::Upgraded;
 use hyper::Request;
@@ -16,6 +15,8 @@ use tokio::sync::Mutex;
 
 use crate::asgispec::{Scope, State, ASGIReceiveEvent, ASGISendEvent};
 use crate::error::Result;
+use crate::middleware_services::RequestId;
+use crate::server::ShutdownWatcher;
 use crate::types::Response;
 use crate::{application::Application, ASGICallable};
 use crate::Error;
@@ -24,7 +25,11 @@ pub async fn serve_websocket<S: State + 'static, T: ASGICallable<S> + 'static>(
     asgi_app: Application<S, T>,
     mut req: Request<Incoming>,
     scope: Scope<S>,
+    shutdown: ShutdownWatcher,
+    // Dropped once the connection closes, like the websocket limit's permit
+    held: impl Send + 'static,
 ) -> Result<Response> {
+    let request_id = RequestId::of(&req);
     let app_clone = asgi_app.clone();
     let mut running_app = tokio::task::spawn(async move { app_clone.call(scope).await });
 
@@ -36,22 +41,23 @@ pub async fn serve_websocket<S: State + 'static, T: ASGICallable<S> + 'static>(
     if accepted {
         let (upgrade_response, fut) = upgrade::upgrade(&mut req)?;
         tokio::task::spawn(async move {
+            let _held = held;
             let result = tokio::try_join!(
                 running_app.map_err(|e| Error::custom(format!("{e}"))),
-                run_accepted_websocket(asgi_app, fut)
+                run_accepted_websocket(asgi_app, fut, shutdown, &request_id)
             );
 
             match result {
                 Ok((Ok(_), _)) => (),
-                Ok((Err(e), _)) => error!("Error while serving websocket; {e}"),
-                Err(e) => error!("Error while serving websocket; {e}"),
+                Ok((Err(e), _)) => error!("[{request_id}] Error while serving websocket; {e}"),
+                Err(e) => error!("[{request_id}] Error while serving websocket; {e}"),
             }
         });
         // The application might have send a body and additional headers
         // If connection is accepted, merge the application response with hyper/fastwebsockets
         // proposed response. This way we can make use of their upgrade functionality
         // while maintaining required control by the application
-        return Ok(merge_responses(app_response, upgrade_response)?);
+        return merge_responses(app_response, upgrade_response);
     };
     Ok(app_response)
 }
@@ -68,11 +74,11 @@ async fn accept_websocket_connection<S: State, T: ASGICallable<S>>(mut asgi_app:
                 .map_err(|never| match never {})
                 .boxed();
             builder = builder.status(StatusCode::SWITCHING_PROTOCOLS);
-            if msg.subprotocol.is_some() {
-                builder = builder.header(hyper::header::SEC_WEBSOCKET_PROTOCOL, msg.subprotocol.unwrap())
+            if let Some(subprotocol) = msg.subprotocol {
+                builder = builder.header(hyper::header::SEC_WEBSOCKET_PROTOCOL, subprotocol)
             };
             for (bytes_key, bytes_value) in msg.headers.into_iter() {
-                builder = builder.header(bytes_key, bytes_value);
+                builder = builder.header(&bytes_key[..], HeaderValue::from_maybe_shared(bytes_value).map_err(http::Error::from)?);
             }
             Ok((true, builder.body(body)?))
         }
@@ -88,10 +94,20 @@ async fn accept_websocket_connection<S: State, T: ASGICallable<S>>(mut asgi_app:
 enum WsIteration<'a> {
     ReceiveClient(std::result::Result<fastwebsockets::Frame<'a>, fastwebsockets::WebSocketError>),
     ReceiveApplication(Result<Option<ASGISendEvent>>),
+    Shutdown,
 }
 
-async fn run_accepted_websocket<S: State, T: ASGICallable<S>>(mut asgi_app: Application<S, T>, upgraded_io: UpgradeFut) -> Result<()> {
+// Close code sent to clients when the server shuts down (Service Restart)
+const SHUTDOWN_CLOSE_CODE: u16 = 1012;
+
+async fn run_accepted_websocket<S: State, T: ASGICallable<S>>(
+    mut asgi_app: Application<S, T>,
+    upgraded_io: UpgradeFut,
+    mut shutdown: ShutdownWatcher,
+    request_id: &RequestId,
+) -> Result<()> {
     let ws = Arc::new(Mutex::new(FragmentCollector::new(upgraded_io.await?)));
+    let mut close_code: usize = 1005;
 
     loop {
         let mut app_iter = asgi_app.clone();
@@ -101,6 +117,7 @@ async fn run_accepted_websocket<S: State, T: ASGICallable<S>>(mut asgi_app: Appl
         let iteration: WsIteration<'_> = tokio::select! {
             out = ws_locked.read_frame() => WsIteration::ReceiveClient(out),
             out = app_iter.receive_from() => WsIteration::ReceiveApplication(out),
+            _ = shutdown.closing() => WsIteration::Shutdown,
         };
 
         drop(ws_locked); // Drop the lock so it can be acquired for writing
@@ -108,21 +125,27 @@ async fn run_accepted_websocket<S: State, T: ASGICallable<S>>(mut asgi_app: Appl
         match iteration {
             WsIteration::ReceiveClient(frame) => {
                 let app_clone = asgi_app.clone();
-                if let false = do_server_iteration(frame?, app_clone).await? {
+                if !do_server_iteration(frame?, app_clone).await? {
                     break;
                 };
             }
             WsIteration::ReceiveApplication(msg) => {
                 let ws_clone = ws.clone();
-                if let false = do_app_iteration(msg?, ws_clone).await? {
+                if !do_app_iteration(msg?, ws_clone, request_id).await? {
                     break;
                 };
             }
+            WsIteration::Shutdown => {
+                let frame = Frame::close(SHUTDOWN_CLOSE_CODE, b"Server shutting down");
+                _ = ws.lock().await.write_frame(frame).await;
+                close_code = SHUTDOWN_CLOSE_CODE.into();
+                break;
+            }
         };
     }
 
     asgi_app
-        .send_to(ASGIReceiveEvent::new_websocket_disconnect(1005))
+        .send_to(ASGIReceiveEvent::new_websocket_disconnect(close_code))
         .await?;
 
     asgi_app.disconnect_server();
@@ -133,6 +156,7 @@ async fn run_accepted_websocket<S: State, T: ASGICallable<S>>(mut asgi_app: Appl
 async fn do_app_iteration(
     msg: Option<ASGISendEvent>,
     ws: Arc<Mutex<FragmentCollector<TokioIo<Upgraded>>>>,
+    request_id: &RequestId,
 ) -> Result<bool> {
     match msg {
         Some(ASGISendEvent::WebsocketSend(msg)) => {
@@ -142,7 +166,7 @@ async fn do_app_iteration(
                 ws.lock().await.write_frame(frame).await?;
             }
             if let Some(data) = msg.bytes {
-                let payload = Payload::Bytes(BytesMut::from(&data[..]));
+                let payload = Payload::Borrowed(&data);
                 let frame = Frame::new(true, OpCode::Binary, None, payload);
                 ws.lock().await.write_frame(frame).await?;
             }
@@ -155,7 +179,7 @@ async fn do_app_iteration(
             Ok(false)
         }
         invalid => {
-            error!("Got invalid ASGI message in websocket server loop. Received: {invalid:?}");
+            error!("[{request_id}] Got invalid ASGI message in websocket server loop. Received: {invalid:?}");
             let payload = Payload::Owned(String::from("Internal server error").into_bytes());
             let frame = Frame::new(true, OpCode::Close, None, payload);
             ws.lock().await.write_frame(frame).await?;
@@ -165,18 +189,16 @@ async fn do_app_iteration(
 }
 
 async fn do_server_iteration<S: State, T: ASGICallable<S>>(frame: Frame<'_>, asgi_app: Application<S, T>) -> Result<bool> {
-    let frame_bytes = frame.payload.to_vec();
-
     match frame.opcode {
         OpCode::Close => Ok(false),
         OpCode::Text => {
             // Text is guaranteed to be utf-8 by fastwebsockets
-            let text = String::from_utf8(frame_bytes).unwrap();
+            let text = String::from_utf8(frame.payload.to_vec()).unwrap();
             asgi_app.send_to(ASGIReceiveEvent::new_websocket_receive(None, Some(text))).await?;
             Ok(true)
         }
         OpCode::Binary => {
-            asgi_app.send_to(ASGIReceiveEvent::new_websocket_receive(Some(frame_bytes), None)).await?;
+            asgi_app.send_to(ASGIReceiveEvent::new_websocket_receive(Some(Bytes::from(frame.payload.to_vec())), None)).await?;
             Ok(true)
         }
         _ => Ok(true),
@@ -1,5 +1,7 @@
-use crate::{asgispec::{ASGIScope, State}, server::ConnectionInfo};
+use crate::{asgispec::{http_version_to_scope, scope_headers, ASGIScope, Headers, ScopeExtensions, State}, server::{ConnectionInfo, Forwarded}};
+use crate::middleware_services::RequestId;
 
+use bytes::Bytes;
 use hyper::Request;
 
 #[derive(Debug, Clone)]
@@ -9,20 +11,38 @@ pub struct WebsocketScope<S: State> {
     pub http_version: String,
     pub scheme: String,
     pub path: String,
-    pub raw_path: Vec<u8>,
-    pub query_string: Vec<u8>,
+    pub raw_path: Bytes,
+    pub query_string: Bytes,
     pub root_path: String,
-    pub headers: Vec<(Vec<u8>, Vec<u8>)>,
+    pub headers: Headers,
     pub client: Option<(String, u16)>,
-    pub server: Option<(String, u16)>,
+    pub server: Option<(String, Option<u16>)>,
+    pub extensions: ScopeExtensions,
     pub subprotocols: Vec<String>,
     pub state: S,
 }
 
 impl<S: State> WebsocketScope<S> {
     pub fn set_conn_info(&mut self, info: &ConnectionInfo) {
-        self.client = Some((info.client_ip.to_owned(), info.client_port));
-        self.server = Some((info.server_ip.to_owned(), info.server_port));
+        self.client = info.scope_client();
+        self.server = info.scope_server();
+        if info.tls.is_some() {
+            self.scheme = String::from("wss");
+        };
+        self.extensions.tls = info.tls.clone();
+    }
+
+    // Only holds what trusted proxies told, set after the connection info
+    pub fn set_forwarded(&mut self, forwarded: &Forwarded) {
+        if let Some((ip, port)) = forwarded.client {
+            self.client = Some((ip.to_string(), port));
+        };
+        if let Some(scheme) = forwarded.scheme(true) {
+            self.scheme = String::from(scheme);
+        };
+        if let Some(server) = forwarded.server(&self.scheme) {
+            self.server = Some(server);
+        };
     }
 
     pub fn from_hyper_request(value: &Request<hyper::body::Incoming>, state: S) -> Self {
@@ -37,30 +57,25 @@ impl<S: State> WebsocketScope<S> {
             txt.retain(|c| !c.is_whitespace());
             txt
         })
-        .map(|s| s.split(",").map(|substr| substr.to_owned()).collect::<Vec<String>>())
-        .flatten()
+        .flat_map(|s| s.split(",").map(|substr| substr.to_owned()).collect::<Vec<String>>())
         .collect();
     
         Self{
             type_: String::from("websocket"),
             asgi: ASGIScope::new(),
-            http_version: format!("{:?}", value.version()),
-            scheme: String::from("http"),
+            http_version: http_version_to_scope(value.version()),
+            scheme: String::from("ws"),
             path: value.uri().path().to_owned(),
-            raw_path: value.uri().to_string().as_bytes().to_vec(),
-            query_string: value.uri().query().unwrap_or("").as_bytes().to_vec(),
+            raw_path: Bytes::from(value.uri().to_string()),
+            query_string: Bytes::copy_from_slice(value.uri().query().unwrap_or("").as_bytes()),
             root_path: String::from(""), // Optional, default for now
-            headers: value
-                .headers()
-                .into_iter()
-                .map(
-                    |(name, value)| {
-                        (name.as_str().as_bytes().to_vec(), value.as_bytes().to_vec())
-                    }
-                )
-                .collect(),
+            headers: scope_headers(value.headers()),
             client: None,
             server: None,
+            extensions: ScopeExtensions {
+                request_id: value.extensions().get::<RequestId>().map(RequestId::to_string),
+                ..ScopeExtensions::default()
+            },
             subprotocols,
             state,
         }
@@ -1,22 +1,52 @@
+use std::path::PathBuf;
+
+use bytes::Bytes;
 use log::error;
+use pyo3::buffer::PyBuffer;
 use pyo3::exceptions::PyRuntimeError;
+use pyo3::pybacked::PyBackedBytes;
 use pyo3::types::{PyBytes, PyDict, PyList, PyMapping, PyNone, PyString};
 use pyo3::{prelude::*, IntoPyObjectExt};
 
 use aras_core::*;
 use super::PyState;
 
+// `bytes` objects are shared with Python without copying them, other
+// buffers such as `bytearray` or `memoryview` are copied once
+fn extract_bytes(value: &Bound<PyAny>) -> PyResult<Bytes> {
+    if let Ok(bytes) = value.extract::<PyBackedBytes>() {
+        return Ok(Bytes::from_owner(bytes));
+    };
+    let buffer = PyBuffer::<u8>::get(value)?;
+    Ok(Bytes::from(buffer.to_vec(value.py())?))
+}
+
+fn extract_headers(value: &Bound<PyAny>) -> PyResult<Headers> {
+    value
+        .extract::<Vec<(Bound<PyAny>, Bound<PyAny>)>>()?
+        .iter()
+        .map(|(name, value)| Ok((extract_bytes(name)?, extract_bytes(value)?)))
+        .collect()
+}
+
 pub fn parse_py_http_response_start(py_map: &Bound<PyMapping>) -> PyResult<ASGISendEvent> {
     let status: u16 = py_map.get_item("status")?.extract()?;
     let headers = py_map
         .get_item("headers")
-        .and_then(|v| v.extract::<Vec<(Vec<u8>, Vec<u8>)>>())
+        .and_then(|v| extract_headers(&v))
         .unwrap_or(Vec::new());
-    Ok(ASGISendEvent::new_http_response_start(status, headers))
+    let trailers = py_map
+        .get_item("trailers")
+        .and_then(|v| v.extract::<bool>())
+        .unwrap_or(false);
+    match trailers {
+        true => Ok(ASGISendEvent::new_http_response_start_with_trailers(status, headers)),
+        false => Ok(ASGISendEvent::new_http_response_start(status, headers)),
+    }
 }
 
 pub fn parse_py_http_response_body(py_map: &Bound<PyMapping>) -> PyResult<ASGISendEvent> {
-    let body: Vec<u8> = py_map.get_item("body")?.extract()?;
+    let body = extract_bytes(&py_map.get_item("body")?)?;
     let more_body = py_map
         .get_item("more_body")
         .and_then(|v| v.extract::<bool>())
@@ -24,6 +54,23 @@ pub fn parse_py_http_response_body(py_map: &Bound<PyMapping>) -> PyResult<ASGISe
     Ok(ASGISendEvent::new_http_response_body(body, more_body))
 }
 
+pub fn parse_py_http_response_pathsend(py_map: &Bound<PyMapping>) -> PyResult<ASGISendEvent> {
+    let path: PathBuf = py_map.get_item("path")?.extract()?;
+    Ok(ASGISendEvent::new_http_response_pathsend(path))
+}
+
+pub fn parse_py_http_response_trailers(py_map: &Bound<PyMapping>) -> PyResult<ASGISendEvent> {
+    let headers = py_map
+        .get_item("headers")
+        .and_then(|v| extract_headers(&v))
+        .unwrap_or(Vec::new());
+    let more_trailers = py_map
+        .get_item("more_trailers")
+        .and_then(|v| v.extract::<bool>())
+        .unwrap_or(false);
+    Ok(ASGISendEvent::new_http_response_trailers(headers, more_trailers))
+}
+
 pub fn parse_startup_failed(py_map: &Bound<PyMapping>) -> ASGISendEvent {
     let message = py_map
         .get_item("message")
@@ -43,7 +90,7 @@ pub fn parse_shutdown_failed(py_map: &Bound<PyMapping>) -> ASGISendEvent {
 pub fn http_request_event_into_py<'py>(py: Python<'py>, event: HTTPRequestEvent) -> PyResult<Bound<'py, PyDict>> {
     let python_result_dict = PyDict::new(py);
     python_result_dict.set_item("type", event.type_.into_pyobject(py)?)?;
-    python_result_dict.set_item("body", PyBytes::new(py, event.body.as_slice()))?;
+    python_result_dict.set_item("body", PyBytes::new(py, &event.body))?;
     python_result_dict.set_item("more_body", event.more_body.into_pyobject(py)?)?;
     Ok(python_result_dict)
 }
@@ -57,15 +104,45 @@ pub fn http_disconnect_event_into_py<'py>(py: Python<'py>, event: HTTPDisconnect
 fn asgi_scope_into_py<'py>(py: Python<'py>, scope: ASGIScope) -> PyResult<Bound<'py, PyDict>> {
     let asgi_dict = PyDict::new(py);
     asgi_dict.set_item("version", scope.version.into_pyobject(py)?)?;
-    asgi_dict.set_item("spec_version", String::from(scope.spec_version).into_pyobject(py)?)?;
+    asgi_dict.set_item("spec_version", scope.spec_version.into_pyobject(py)?)?;
     Ok(asgi_dict)
 }
 
+fn tls_extension_into_py<'py>(py: Python<'py>, tls: TLSExtension) -> PyResult<Bound<'py, PyDict>> {
+    let tls_dict = PyDict::new(py);
+    tls_dict.set_item("server_cert", tls.server_cert.into_pyobject(py)?)?;
+    tls_dict.set_item("client_cert_chain", tls.client_cert_chain.into_pyobject(py)?)?;
+    tls_dict.set_item("client_cert_name", tls.client_cert_name.into_pyobject(py)?)?;
+    tls_dict.set_item("client_cert_error", tls.client_cert_error.into_pyobject(py)?)?;
+    tls_dict.set_item("tls_version", tls.tls_version.into_pyobject(py)?)?;
+    tls_dict.set_item("cipher_suite", tls.cipher_suite.into_pyobject(py)?)?;
+    Ok(tls_dict)
+}
+
+fn scope_extensions_into_py<'py>(py: Python<'py>, extensions: ScopeExtensions) -> PyResult<Bound<'py, PyDict>> {
+    let extensions_dict = PyDict::new(py);
+    if let Some(tls) = extensions.tls {
+        extensions_dict.set_item("tls", tls_extension_into_py(py, tls)?)?;
+    };
+    if let Some(request_id) = extensions.request_id {
+        let request_id_dict = PyDict::new(py);
+        request_id_dict.set_item("id", request_id)?;
+        extensions_dict.set_item("request_id", request_id_dict)?;
+    };
+    if extensions.pathsend {
+        extensions_dict.set_item("http.response.pathsend", PyDict::new(py))?;
+    };
+    if extensions.trailers {
+        extensions_dict.set_item("http.response.trailers", PyDict::new(py))?;
+    };
+    Ok(extensions_dict)
+}
+
 pub fn http_scope_into_py<'py>(py: Python<'py>, scope: HTTPScope<PyState>) -> PyResult<Bound<'py, PyDict>> {
     let python_result_dict = PyDict::new(py);
     python_result_dict.set_item("type", scope.type_.into_pyobject(py)?)?;
     python_result_dict.set_item("asgi", asgi_scope_into_py(py, scope.asgi)?)?;
-    python_result_dict.set_item("http_version", String::from(scope.http_version).into_pyobject(py)?)?;
+    python_result_dict.set_item("http_version", scope.http_version.into_pyobject(py)?)?;
     python_result_dict.set_item("method", scope.method.into_pyobject(py)?)?;
     python_result_dict.set_item("scheme", scope.scheme.into_pyobject(py)?)?;
     python_result_dict.set_item("path", scope.path.into_pyobject(py)?)?;
@@ -75,7 +152,7 @@ pub fn http_scope_into_py<'py>(py: Python<'py>, scope: HTTPScope<PyState>) -> Py
     let py_bytes_headers: Vec<(Bound<PyBytes>, Bound<PyBytes>)> = scope
         .headers
         .into_iter()
-        .map(|(k, v)| (PyBytes::new(py, k.as_slice()), PyBytes::new(py, v.as_slice())))
+        .map(|(k, v)| (PyBytes::new(py, &k), PyBytes::new(py, &v)))
         .collect();
     python_result_dict.set_item("headers", py_bytes_headers.into_pyobject(py)?)?;
     let py_client = match scope.client {
@@ -88,6 +165,7 @@ pub fn http_scope_into_py<'py>(py: Python<'py>, scope: HTTPScope<PyState>) -> Py
         None => PyNone::get(py).into_py_any(py),
     };
     python_result_dict.set_item("server", py_server?)?;
+    python_result_dict.set_item("extensions", scope_extensions_into_py(py, scope.extensions)?)?;
     python_result_dict.set_item("state", scope.state.into_pyobject(py)?)?;
     Ok(python_result_dict)
 }
@@ -119,7 +197,7 @@ pub fn parse_websocket_accept(py_map: &Bound<PyMapping>) -> PyResult<ASGISendEve
         .ok();
     let headers = py_map
         .get_item("headers")
-        .and_then(|v| v.extract::<Vec<(Vec<u8>, Vec<u8>)>>())
+        .and_then(|v| extract_headers(&v))
         .unwrap_or(Vec::new());
     Ok(ASGISendEvent::new_websocket_accept(subprotocol, headers))
 }
@@ -127,14 +205,14 @@ pub fn parse_websocket_accept(py_map: &Bound<PyMapping>) -> PyResult<ASGISendEve
 pub fn parse_websocket_send(py_map: &Bound<PyMapping>) -> PyResult<ASGISendEvent> {
     let bytes = py_map
         .get_item("bytes")
-        .and_then(|inner| inner.extract::<Vec<u8>>())
+        .and_then(|inner| extract_bytes(&inner))
         .ok();
     let text = py_map
         .get_item("text")
         .and_then(|inner| inner.extract::<String>())
         .ok();
 
-    if bytes == None && text == None {
+    if bytes.is_none() && text.is_none() {
         error!("Websocket send doesn't have a valid bytes or text field");
         return Err(PyErr::new::<PyRuntimeError, _>("Websocket send doesn't have a valid bytes or text field"))
     };
@@ -181,7 +259,7 @@ pub fn websocket_scope_into_py<'py>(py: Python<'py>, scope: WebsocketScope<PySta
     let python_result_dict = PyDict::new(py);
     python_result_dict.set_item("type", scope.type_.into_pyobject(py)?)?;
     python_result_dict.set_item("asgi", asgi_scope_into_py(py, scope.asgi)?)?;
-    python_result_dict.set_item("http_version", String::from(scope.http_version).into_pyobject(py)?)?;
+    python_result_dict.set_item("http_version", scope.http_version.into_pyobject(py)?)?;
     python_result_dict.set_item("scheme", scope.scheme.into_pyobject(py)?)?;
     python_result_dict.set_item("path", scope.path.into_pyobject(py)?)?;
     python_result_dict.set_item("raw_path", PyBytes::new(py, &scope.raw_path))?;
@@ -190,7 +268,7 @@ pub fn websocket_scope_into_py<'py>(py: Python<'py>, scope: WebsocketScope<PySta
     let py_bytes_headers: Vec<(Bound<PyBytes>, Bound<PyBytes>)> = scope
         .headers
         .into_iter()
-        .map(|(k, v)| (PyBytes::new(py, k.as_slice()), PyBytes::new(py, v.as_slice())))
+        .map(|(k, v)| (PyBytes::new(py, &k), PyBytes::new(py, &v)))
         .collect();
     python_result_dict.set_item("headers", py_bytes_headers.into_pyobject(py)?)?;
     let py_client = match scope.client {
@@ -203,6 +281,7 @@ pub fn websocket_scope_into_py<'py>(py: Python<'py>, scope: WebsocketScope<PySta
         None => PyNone::get(py).into_py_any(py),
     };
     python_result_dict.set_item("server", py_server?)?;
+    python_result_dict.set_item("extensions", scope_extensions_into_py(py, scope.extensions)?)?;
     let py_subprotocols: Vec<Bound<PyString>> = scope
         .subprotocols
         .into_iter()
@@ -1,10 +1,20 @@
+// pyo3 0.23 macros generate `PyErr` conversions that trip this lint
+#![allow(clippy::useless_conversion)]
+
+use std::collections::HashMap;
+use std::time::Duration;
+
 use tokio::runtime::Handle;
-use aras_core::ServerConfig;
+use tokio::sync::Semaphore;
+use aras_core::{
+    parse_networks, AccessLogConfig, AccessLogFormat, AccessLogTarget, BindTarget, ClientCertMode, CompressionConfig,
+    ConcurrencyQueue, ContentEncoding, HttpProtocol, IpNet, MetricsConfig, ProxyProtocol, RateLimit, ServerConfig,
+    StaticMount, Timeouts, TlsConfig, UnixSocketConfig, systemd_listen_fds,
+};
 use log::{debug, error, info};
-use pyo3::exceptions::PyRuntimeError;
+use pyo3::exceptions::{PyRuntimeError, PyValueError};
 use pyo3::prelude::*;
 use pyo3::types::PyDict;
-use pyo3_async_runtimes;
 use simplelog::*;
 
 mod convert;
@@ -55,36 +65,281 @@ fn get_log_level_filter(log_level: &str) -> LevelFilter {
     }
 }
 
+// A single host or a list of hosts to bind to
+#[derive(FromPyObject)]
+enum PyHosts {
+    Single(String),
+    Multiple(Vec<String>),
+}
+
+// Only bind to the default host if no other socket is given. Sockets passed by systemd
+// socket activation are used instead, this runs before the tokio runtime is started.
+fn get_bind_targets(
+    host: Option<PyHosts>,
+    port: u16,
+    uds: Option<String>,
+    uds_mode: Option<u32>,
+    fd: Option<i32>,
+) -> PyResult<Vec<BindTarget>> {
+    if let Some(activated) = systemd_listen_fds() {
+        if host.is_some() || uds.is_some() || fd.is_some() {
+            return Err(PyValueError::new_err(
+                "Sockets passed by systemd can't be combined with 'host', 'uds' or 'fd'",
+            ));
+        };
+        info!("Using {} socket(s) passed by systemd", activated.len());
+        return Ok(activated);
+    };
+    let hosts = match host {
+        Some(PyHosts::Single(h)) => vec![h],
+        Some(PyHosts::Multiple(h)) => h,
+        None if uds.is_some() || fd.is_some() => Vec::new(),
+        None => vec![String::from("127.0.0.1")],
+    };
+    let mut targets: Vec<BindTarget> = hosts.into_iter().map(|h| BindTarget::tcp(h, port)).collect();
+    if let Some(path) = uds {
+        targets.push(BindTarget::Unix(UnixSocketConfig::new(path, uds_mode)));
+    };
+    if let Some(fd) = fd {
+        #[cfg(unix)]
+        targets.push(BindTarget::Fd(fd));
+        #[cfg(not(unix))]
+        return Err(PyValueError::new_err(format!("Binding to file descriptor {fd} is not supported on this platform")));
+    };
+    Ok(targets)
+}
+
+fn get_duration(name: &str, seconds: f64) -> PyResult<Duration> {
+    Duration::try_from_secs_f64(seconds).map_err(|e| PyValueError::new_err(format!("Invalid '{name}'; {e}")))
+}
+
+// A timeout of `None` is disabled
+fn get_timeout(name: &str, seconds: Option<f64>) -> PyResult<Option<Duration>> {
+    seconds.map(|s| get_duration(name, s)).transpose()
+}
+
+// Comma separated IPs and networks, like "127.0.0.1,10.0.0.0/8"
+fn get_networks(name: &str, value: Option<&str>) -> PyResult<Vec<IpNet>> {
+    value
+        .map(parse_networks)
+        .transpose()
+        .map(Option::unwrap_or_default)
+        .map_err(|e| PyValueError::new_err(format!("Invalid '{name}'. {e}")))
+}
+
+// Sizes are given in kilobytes
+fn get_size(name: &str, kb: u64) -> PyResult<u64> {
+    kb.checked_mul(1000)
+        .ok_or_else(|| PyValueError::new_err(format!("Invalid '{name}'; {kb} kB is too large")))
+}
+
+// Comma separated values, like "br,gzip", empty ones are skipped
+fn comma_separated(value: &str) -> impl Iterator<Item = &str> {
+    value.split(',').map(str::trim).filter(|item| !item.is_empty())
+}
+
 // Serve the ASGI application
+#[allow(clippy::too_many_arguments)]
 #[pyfunction]
 #[pyo3(signature = (
     application, 
-    addr = [127, 0, 0, 1], 
+    host = None,
     port = 8080, 
     keep_alive = true, 
     log_level = "INFO", 
+    access_log = true,
+    access_log_format = "common",
+    access_log_file = None,
+    metrics_path = None,
+    metrics_host = "127.0.0.1",
+    metrics_port = None,
+    compression = false,
+    compression_min_size = 500,
+    compression_encodings = None,
+    compression_exclude_types = None,
+    decompress_request_body = false,
+    static_mounts = None,
+    static_index = Some(String::from("index.html")),
+    static_listing = false,
     max_concurrency = None,
+    max_queue = 0,
+    max_websockets = None,
+    rate_limit = None,
+    rate_limit_burst = 10,
+    rate_limit_allow_ips = None,
+    forwarded_allow_ips = None,
+    proxy_protocol = "none",
     max_size_kb = 1_000_000,
+    max_size_kb_by_path = None,
+    http_protocol = "auto",
+    ssl_certfile = None,
+    ssl_keyfile = None,
+    ssl_ca_certs = None,
+    ssl_cert_reqs = "none",
+    uds = None,
+    uds_mode = None,
+    fd = None,
+    timeout_graceful_shutdown = 30.0,
+    timeout_header_read = Some(60.0),
+    timeout_keep_alive = Some(5.0),
+    timeout_body_read = Some(60.0),
+    timeout_request = None,
+    timeout_response_body = None,
+    timeout_write = Some(60.0),
+    timeout_queue = 5.0,
 ))]
 fn serve(
     py: Python,
     application: Py<PyAny>,
-    addr: [u8; 4],
+    host: Option<PyHosts>,
     port: u16,
     keep_alive: bool,
     log_level: &str,
+    access_log: bool,
+    access_log_format: &str,
+    access_log_file: Option<String>,
+    metrics_path: Option<String>,
+    metrics_host: &str,
+    metrics_port: Option<u16>,
+    compression: bool,
+    compression_min_size: u64,
+    compression_encodings: Option<&str>,
+    compression_exclude_types: Option<&str>,
+    decompress_request_body: bool,
+    static_mounts: Option<HashMap<String, String>>,
+    static_index: Option<String>,
+    static_listing: bool,
     max_concurrency: Option<usize>,
+    max_queue: usize,
+    max_websockets: Option<usize>,
+    rate_limit: Option<f64>,
+    rate_limit_burst: u32,
+    rate_limit_allow_ips: Option<&str>,
+    forwarded_allow_ips: Option<&str>,
+    proxy_protocol: &str,
     max_size_kb: u64,
+    max_size_kb_by_path: Option<HashMap<String, u64>>,
+    http_protocol: &str,
+    ssl_certfile: Option<String>,
+    ssl_keyfile: Option<String>,
+    ssl_ca_certs: Option<String>,
+    ssl_cert_reqs: &str,
+    uds: Option<String>,
+    uds_mode: Option<u32>,
+    fd: Option<i32>,
+    timeout_graceful_shutdown: f64,
+    timeout_header_read: Option<f64>,
+    timeout_keep_alive: Option<f64>,
+    timeout_body_read: Option<f64>,
+    timeout_request: Option<f64>,
+    timeout_response_body: Option<f64>,
+    timeout_write: Option<f64>,
+    timeout_queue: f64,
 ) -> PyResult<()> {
     SimpleLogger::init(get_log_level_filter(log_level), Config::default())
         .map_err(|e| PyRuntimeError::new_err(format!("Failed to start logger. {}", e)))?;
-    let config = ServerConfig::new(keep_alive, max_concurrency, addr.into(), port, max_size_kb * 1000);
+    let binds = get_bind_targets(host, port, uds, uds_mode, fd)?;
+    let max_size = get_size("max_size_kb", max_size_kb)?;
+    let mut config = ServerConfig::new(keep_alive, max_concurrency, binds, max_size);
+    for (prefix, limit_kb) in max_size_kb_by_path.unwrap_or_default() {
+        config.max_size = config.max_size.with_prefix(prefix, get_size("max_size_kb_by_path", limit_kb)?);
+    }
+    config.http_protocol = http_protocol
+        .parse::<HttpProtocol>()
+        .map_err(|e| PyValueError::new_err(e.to_string()))?;
+    let client_cert_mode = ssl_cert_reqs
+        .parse::<ClientCertMode>()
+        .map_err(|e| PyValueError::new_err(e.to_string()))?;
+    config.tls = match (ssl_certfile, ssl_keyfile) {
+        (Some(certfile), Some(keyfile)) => {
+            let tls = TlsConfig::new(certfile, keyfile);
+            match ssl_ca_certs {
+                Some(client_ca) => Some(tls.with_client_auth(client_ca, client_cert_mode)),
+                None if client_cert_mode != ClientCertMode::None => {
+                    return Err(PyValueError::new_err("'ssl_ca_certs' is required to verify client certificates"))
+                }
+                None => Some(tls),
+            }
+        }
+        (None, None) => None,
+        _ => return Err(PyValueError::new_err("Both 'ssl_certfile' and 'ssl_keyfile' are required to enable TLS")),
+    };
+    config.max_websockets = max_websockets.unwrap_or(Semaphore::MAX_PERMITS);
+    config.rate_limit = match rate_limit {
+        Some(per_second) => {
+            let mut settings =
+                RateLimit::new(per_second, rate_limit_burst).map_err(|e| PyValueError::new_err(e.to_string()))?;
+            settings.allow_ips = get_networks("rate_limit_allow_ips", rate_limit_allow_ips)?;
+            Some(settings)
+        }
+        None => None,
+    };
+    config.access_log = match access_log {
+        true => Some(AccessLogConfig {
+            format: access_log_format
+                .parse::<AccessLogFormat>()
+                .map_err(|e| PyValueError::new_err(e.to_string()))?,
+            target: match access_log_file.as_deref() {
+                None => AccessLogTarget::Log,
+                Some("-") => AccessLogTarget::Stdout,
+                Some(path) => AccessLogTarget::File(path.into()),
+            },
+        }),
+        false => None,
+    };
+    config.metrics = match (metrics_path, metrics_port) {
+        (None, None) => None,
+        (path, port) => Some(MetricsConfig::new(
+            path.unwrap_or_else(|| String::from("/metrics")),
+            port.map(|port| BindTarget::tcp(metrics_host, port)),
+        )),
+    };
+    config.compression = match compression {
+        true => {
+            let mut settings = CompressionConfig { min_size: compression_min_size, ..CompressionConfig::default() };
+            if let Some(encodings) = compression_encodings {
+                settings.encodings = comma_separated(encodings)
+                    .map(str::parse::<ContentEncoding>)
+                    .collect::<Result<_, _>>()
+                    .map_err(|e| PyValueError::new_err(e.to_string()))?;
+            };
+            if let Some(content_types) = compression_exclude_types {
+                settings.excluded_content_types = comma_separated(content_types).map(String::from).collect();
+            };
+            Some(settings)
+        }
+        false => None,
+    };
+    config.decompress_request_body = decompress_request_body;
+    for (prefix, directory) in static_mounts.unwrap_or_default() {
+        let mut mount = StaticMount::new(prefix, directory);
+        mount.index_file = static_index.clone();
+        mount.list_directories = static_listing;
+        config.static_mounts.push(mount);
+    }
+    config.forwarded_allow_ips = get_networks("forwarded_allow_ips", forwarded_allow_ips)?;
+    config.proxy_protocol = proxy_protocol
+        .parse::<ProxyProtocol>()
+        .map_err(|e| PyValueError::new_err(e.to_string()))?;
+    config.concurrency_queue = ConcurrencyQueue {
+        max_length: max_queue,
+        max_wait: get_duration("timeout_queue", timeout_queue)?,
+    };
+    config.graceful_shutdown_timeout = get_duration("timeout_graceful_shutdown", timeout_graceful_shutdown)?;
+    config.timeouts = Timeouts {
+        header_read: get_timeout("timeout_header_read", timeout_header_read)?,
+        keep_alive: get_timeout("timeout_keep_alive", timeout_keep_alive)?,
+        body_read: get_timeout("timeout_body_read", timeout_body_read)?,
+        request: get_timeout("timeout_request", timeout_request)?,
+        response_body: get_timeout("timeout_response_body", timeout_response_body)?,
+        write: get_timeout("timeout_write", timeout_write)?,
+    };
     let state = PyState::new(PyDict::new(py).unbind()); // State dictionary for the ASGI application
 
     // asyncio setup
     let asyncio = py.import("asyncio")?;
     let event_loop = new_python_event_loop(py)?;
-    let event_loop_clone = event_loop.clone().into();
+    let event_loop_clone = event_loop.clone().unbind();
     asyncio.call_method1("set_event_loop", (&event_loop,))?;
 
     // TaskLocals stores a reference to the event loop, which can be used to run Python coroutines
@@ -100,21 +355,19 @@ fn serve(
 
                 aras_core::serve(asgi_application, state, Some(config))
                     .await
-                    .map_err(|e| PyRuntimeError::new_err(format!("Error running server; {}", e.to_string())))
+                    .map_err(|e| PyRuntimeError::new_err(format!("Error running server; {}", e)))
             })
         });
 
         // When the server is done, stop Python's event loop as well
         debug!("Terminate Python event loop");
-        if let Err(e) = Python::with_gil(|py| terminate_python_event_loop(py, event_loop_clone)) {
-            return Err(e);
-        };
+        Python::with_gil(|py| terminate_python_event_loop(py, event_loop_clone))?;
 
         server_result
     });
 
     // Python's event loop runs in the main thread
-    if let Err(_) = run_python_event_loop(event_loop) {
+    if run_python_event_loop(event_loop).is_err() {
         return Err(PyRuntimeError::new_err(
             "Python event loop quit, cannot shutdown gracefully",
         ));
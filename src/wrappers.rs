@@ -6,7 +6,6 @@ use pyo3::{
     prelude::*,
     types::{PyDict, PyMapping, PyString},
 };
-use pyo3_async_runtimes;
 use aras_core::State;
 use aras_core::{ASGICallable, ASGIReceiveEvent, ASGISendEvent, Error, ReceiveFn, Result, Scope, SendFn};
 
@@ -59,17 +58,17 @@ impl PyState {
 
     fn __iter__<'py>(&'py self, py: Python<'py>) -> PyResult<Bound<'py, PyAny>> {
         let dict = self.state.lock().unwrap().clone_ref(py);
-        Ok(dict.bind(py).call_method0("__iter__")?)
+        dict.bind(py).call_method0("__iter__")
     }
 
     fn __str__<'py>(&'py self, py: Python<'py>) -> PyResult<Bound<'py, PyAny>> {
         let dict = self.state.lock().unwrap().clone_ref(py);
-        Ok(dict.bind(py).call_method0("__str__")?)
+        dict.bind(py).call_method0("__str__")
     }
 
     fn __repr__<'py>(&'py self, py: Python<'py>) -> PyResult<Bound<'py, PyAny>> {
         let dict = self.state.lock().unwrap().clone_ref(py);
-        Ok(dict.bind(py).call_method0("__repr__")?)
+        dict.bind(py).call_method0("__repr__")
     }
 }
 
@@ -78,7 +77,7 @@ pub struct PyASGISendEvent(ASGISendEvent);
 
 impl PyASGISendEvent {
     fn new(msg: ASGISendEvent) -> Self {
-        Self { 0: msg }
+        Self(msg)
     }
 }
 
@@ -90,6 +89,8 @@ impl<'source> FromPyObject<'source> for PyASGISendEvent {
         match msg_type.as_str() {
             "http.response.start" => Ok(Self::new(convert::parse_py_http_response_start(&py_mapping)?)),
             "http.response.body" => Ok(Self::new(convert::parse_py_http_response_body(&py_mapping)?)),
+            "http.response.pathsend" => Ok(Self::new(convert::parse_py_http_response_pathsend(&py_mapping)?)),
+            "http.response.trailers" => Ok(Self::new(convert::parse_py_http_response_trailers(&py_mapping)?)),
             "lifespan.startup.complete" => Ok(Self::new(ASGISendEvent::new_startup_complete())),
             "lifespan.startup.failed" => Ok(Self::new(convert::parse_startup_failed(&py_mapping))),
             "lifespan.shutdown.complete" => Ok(Self::new(ASGISendEvent::new_shutdown_complete())),
@@ -110,7 +111,7 @@ pub struct PyASGIReceiveEvent(ASGIReceiveEvent);
 
 impl PyASGIReceiveEvent {
     fn new(msg: ASGIReceiveEvent) -> Self {
-        Self { 0: msg }
+        Self(msg)
     }
 }
 
@@ -138,7 +139,7 @@ struct PyScope(Scope<PyState>);
 
 impl PyScope {
     pub fn new(scope: Scope<PyState>) -> Self {
-        Self { 0: scope }
+        Self(scope)
     }
 }
 
@@ -203,7 +204,7 @@ impl PyReceive {
         Python::with_gil(|py| {
             PyASGIReceiveEvent::new(received)
                 .into_pyobject(py)
-                .and_then(|v| Ok(v.unbind()))
+                .map(|v| v.unbind())
         })
     }
 }
@@ -235,10 +236,10 @@ impl ASGICallable<PyState> for PyASGIAppWrapper {
                 ),
             );
 
-            Ok(pyo3_async_runtimes::into_future_with_locals(
+            pyo3_async_runtimes::into_future_with_locals(
                 &self.task_locals,
                 maybe_awaitable?.bind(py).to_owned(),
-            )?)
+            )
         });
         future
             .map_err(|e: PyErr| Error::custom(e.to_string()))?